@@ -36,6 +36,8 @@ install -Dm755 %{_sourcedir}/obscura %{buildroot}%{_bindir}/obscura
 install -Dm644 /repo/linux/common/obscura.service %{buildroot}%{_unitdir}/obscura.service
 install -Dm644 /repo/linux/common/obscura-sysusers.conf %{buildroot}%{_sysusersdir}/obscura.conf
 install -Dm644 /repo/linux/common/obscura-preset.conf %{buildroot}%{_presetdir}/80-obscura.preset
+install -Dm644 /repo/linux/common/net.obscura.vpn.policy %{buildroot}%{_datadir}/polkit-1/actions/net.obscura.vpn.policy
+install -Dm644 /repo/linux/common/net.obscura.VPN1.conf %{buildroot}%{_datadir}/dbus-1/system.d/net.obscura.VPN1.conf
 install -Dm755 %{_sourcedir}/obscura-gui %{buildroot}%{_bindir}/obscura-gui
 install -Dm644 /repo/linux/common/net.obscura.vpn.gui.desktop %{buildroot}%{_datadir}/applications/net.obscura.vpn.gui.desktop
 install -Dm644 /repo/linux/common/net.obscura.vpn.gui.metainfo.xml %{buildroot}%{_datadir}/metainfo/net.obscura.vpn.gui.metainfo.xml
@@ -60,6 +62,8 @@ install -Dm644 /repo/linux/rpm/obscura-repository-preset.conf %{buildroot}%{_pre
 %{_unitdir}/obscura.service
 %{_sysusersdir}/obscura.conf
 %{_presetdir}/80-obscura.preset
+%{_datadir}/polkit-1/actions/net.obscura.vpn.policy
+%{_datadir}/dbus-1/system.d/net.obscura.VPN1.conf
 
 %files -n obscura-gui
 %license %{_defaultlicensedir}/obscura-gui/LICENSE.md
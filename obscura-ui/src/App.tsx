@@ -1,4 +1,4 @@
-import { AppShell, AppShellMain } from '@mantine/core';
+import { AppShell, AppShellMain, Button } from '@mantine/core';
 import { useHotkeys, useThrottledValue } from '@mantine/hooks';
 import { notifications } from '@mantine/notifications';
 import { ReactNode, useContext, useEffect, useRef, useState } from 'react';
@@ -136,12 +136,34 @@ export default function () {
         withCloseButton: true,
         color: 'red',
         title: t('Error'),
-        message: <VpnError errorEnum={errorEnum} />,
+        message: <>
+          <VpnError errorEnum={errorEnum} />
+          {errorEnum === 'captivePortal' && PLATFORM === Platform.Linux &&
+            <Button size='xs' mt='xs' onClick={bypassCaptivePortal}>{t('captivePortalBypassButton')}</Button>}
+        </>,
         autoClose: 15_000
       });
     }
   }
 
+  async function bypassCaptivePortal() {
+    try {
+      const bypass = await commands.bypassCaptivePortal();
+      const minutes = Math.round(bypass.durationSecs / 60);
+      notifications.hide(NotificationId.VPN_ERROR);
+      notifications.show({
+        withCloseButton: true,
+        title: t('captivePortalBypassTitle'),
+        message: bypass.portal.url !== null
+          ? t('captivePortalBypassMessage', { url: bypass.portal.url, minutes })
+          : t('captivePortalBypassMessageNoUrl', { minutes }),
+        autoClose: bypass.durationSecs * 1000
+      });
+    } catch (e) {
+      showErrorNotification(t, e, 'captivePortalBypassFailed');
+    }
+  }
+
   function handleNewStatus(newStatus: AppStatus) {
     const vpnStatus = newStatus.vpnStatus;
     if (vpnStatus === undefined) return;
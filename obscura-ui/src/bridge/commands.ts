@@ -4,7 +4,7 @@ import { useState } from 'react';
 import { useTranslation } from 'react-i18next';
 import { AccountId } from '../common/accountUtils';
 import { AccountInfo, Exit } from '../common/api';
-import { AppStatus, DNSContentBlock, FeatureFlagKey, NavigationView, OsStatus, PinnedLocation, SubscriptionProductModel } from '../common/appContext';
+import { AppStatus, DNSContentBlock, FeatureFlagKey, NavigationView, NetworkRule, OsStatus, PinnedLocation, SubscriptionProductModel } from '../common/appContext';
 import { normalizeError } from '../common/utils';
 import { ErrorI18n, fmtErrorI18n } from '../translations/i18n';
 import { Platform, PLATFORM } from './SystemProvider';
@@ -109,6 +109,10 @@ export async function setApiHostAlternate(host: string | null): Promise<void> {
     await jsonFfiCmd('setApiHostAlternate', { host });
 }
 
+export async function setApiDohBootstrapUrls(urls: string[] | null): Promise<void> {
+    await jsonFfiCmd('setApiDohBootstrapUrls', { urls });
+}
+
 export async function setSniRelay(host: string | null): Promise<void> {
     await jsonFfiCmd('setSniRelay', { host });
 }
@@ -282,6 +286,20 @@ export async function setLocalNetworkAccess(enable: boolean): Promise<void> {
   await jsonFfiCmd('setLocalNetworkAccess', { enable });
 }
 
+export async function setNetworkRules(rules: NetworkRule[]): Promise<void> {
+  await jsonFfiCmd('setNetworkRules', { rules });
+}
+
+export interface CaptivePortalBypass {
+  portal: { url: string | null },
+  durationSecs: number,
+}
+
+// Linux only. Probing for the portal and resolving it can take a while.
+export async function bypassCaptivePortal(): Promise<CaptivePortalBypass> {
+  return await jsonFfiCmd('bypassCaptivePortal', {}, 30_000) as CaptivePortalBypass;
+}
+
 export async function setFeatureFlag(flag: FeatureFlagKey, active: boolean) {
   await jsonFfiCmd('setFeatureFlag', { flag, active });
 }
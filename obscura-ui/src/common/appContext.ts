@@ -188,6 +188,31 @@ export interface DNSContentBlock {
     socialMedia: boolean,
 }
 
+// Settings locked by the administrator, unset fields aren't locked.
+export interface Policy {
+    lockdown: boolean,
+    localNetworkAccess: boolean | null,
+    useSystemDns: boolean | null,
+    autoConnect: boolean | null,
+    allowedExitCountries: string[] | null,
+    disableLogout: boolean,
+}
+
+export interface NetworkIdentity {
+    interfaceName: string,
+    wireless: boolean,
+    gatewayIp: string | null,
+    gatewayMac: string | null,
+    connectionUuid: string | null,
+    ssid: string | null,
+}
+
+export interface NetworkRule {
+    name: string,
+    network: Partial<NetworkIdentity>,
+    action: 'connect' | 'disconnect',
+}
+
 export interface AppStatus {
     version: string,
     dnsContentBlock: DNSContentBlock,
@@ -197,12 +222,20 @@ export interface AppStatus {
     lastChosenExit: ExitSelector,
     inNewAccountFlow: boolean,
     apiUrl: string,
+    apiUrlOverride: string | null,
     account: AccountStatus | null,
     autoConnect: boolean,
     featureFlags: Record<FeatureFlagKey, FeatureFlagValue>,
     featureFlagKeys: FeatureFlagKey[],
     useSystemDns: boolean,
     localNetworkAccess: boolean,
+    sniRelay: string | null,
+    apiHostAlternate: string | null,
+    apiDohBootstrapUrls: string[] | null,
+    policy: Policy,
+    networkRules: NetworkRule[],
+    network: NetworkIdentity | null,
+    matchedNetworkRule: NetworkRule | null,
 }
 
 interface IAppContext {
@@ -5,7 +5,7 @@ use super::{
     util::{Utf8JavaStr, throw_runtime_exception},
 };
 use crate::wg_key_store::WgKeyStore;
-use crate::{manager::Manager, manager_cmd::ManagerCmd, net::NetworkInterface, positive_u31::PositiveU31};
+use crate::{manager::Manager, manager_cmd::ManagerCmd, net::NetworkInterface, policy::Policy, positive_u31::PositiveU31};
 use anyhow::Context as _;
 use jni::{
     JNIEnv, JavaVM,
@@ -65,6 +65,7 @@ fn initialize(env: &mut JNIEnv, j_config_dir: &JString, j_user_agent: &JString,
         os_impl.network_interface(),
         log_persistence,
         true,
+        Policy::default(),
     )?;
     Ok(Global { manager, os_impl, runtime, class_cache })
 }
@@ -8,6 +8,7 @@ use crate::manager::Manager;
 use crate::manager_cmd::ManagerCmd;
 use crate::manager_cmd::ManagerCmdErrorCode;
 use crate::net::NetworkInterface;
+use crate::policy::Policy;
 use crate::positive_u31::PositiveU31;
 use crate::wg_key_store::WgKeyStore;
 
@@ -86,6 +87,7 @@ pub unsafe extern "C" fn initialize(
             os_impl.network_interface(),
             log_persistence,
             true, // persistent tunnel activation must be handled by the on-demand OS feature on Apple platforms
+            Policy::default(),
         ) {
             Ok(manager) => {
                 first_init = true;
@@ -0,0 +1,164 @@
+use super::ClientError;
+use crate::{ClientAccountArgs, ClientAccountCommand};
+use anyhow::Context;
+use chrono::{MappedLocalTime, TimeZone};
+use obscuravpn_api::cmd::DeleteAccountOutput;
+use obscuravpn_api::types::AccountInfo;
+use obscuravpn_client::linux::ipc::run_command;
+use obscuravpn_client::manager::Status;
+use obscuravpn_client::manager_cmd::{ManagerCmd, ManagerCmdErrorCode};
+use serde_json::json;
+use std::io::IsTerminal;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub async fn account(args: ClientAccountArgs) -> Result<(), ClientError> {
+    let status: Status = run_command(ManagerCmd::GetStatus { known_version: None }).await??;
+    if status.account_id.is_none() {
+        return Err(ClientError::NotLoggedIn);
+    }
+    match args.command {
+        ClientAccountCommand::Info => info(status, args.json).await,
+        ClientAccountCommand::Logout => {
+            logout().await?;
+            eprintln!("logged out");
+            Ok(())
+        }
+        ClientAccountCommand::Delete { yes } => delete(yes, args.json).await,
+        ClientAccountCommand::RotateKey => {
+            run_command::<()>(ManagerCmd::RotateWgKey {}).await??;
+            eprintln!("rotated WireGuard key, it is used for new connections");
+            Ok(())
+        }
+    }
+}
+
+async fn info(status: Status, json: bool) -> Result<(), ClientError> {
+    let (account_info, last_updated_sec, cached) = match run_command::<AccountInfo>(ManagerCmd::ApiGetAccountInfo {}).await? {
+        Ok(account_info) => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_secs()).unwrap_or_default();
+            (account_info, now, false)
+        }
+        Err(error) => {
+            // The service caches the last successful response, which is better than nothing while the API is unreachable.
+            let Some(account) = status.account else {
+                return Err(error.into());
+            };
+            eprintln!(
+                "Failed to update account info, showing account info from {}: {}",
+                format_timestamp(i64::try_from(account.last_updated_sec).unwrap_or(i64::MAX)),
+                ClientError::from(error)
+            );
+            (account.account_info, account.last_updated_sec, true)
+        }
+    };
+    let active = account_info.active;
+    if json {
+        print_json(&json!({ "accountInfo": account_info, "lastUpdatedSec": last_updated_sec, "cached": cached }))?;
+    } else {
+        print_account_info(&account_info);
+    }
+    if !active {
+        return Err(ClientError::AccountInactive);
+    }
+    Ok(())
+}
+
+async fn logout() -> Result<(), ClientError> {
+    disconnect().await?;
+    run_command::<()>(ManagerCmd::Logout {}).await??;
+    Ok(())
+}
+
+/// The tunnel can't stay connected without an account. Under lockdown the tunnel can't be disabled, which is fine, traffic stays blocked while logged out.
+async fn disconnect() -> Result<(), ClientError> {
+    match run_command::<()>(ManagerCmd::SetTunnelArgs { args: None, active: Some(false) }).await? {
+        Ok(()) | Err(ManagerCmdErrorCode::PolicyLocked) => Ok(()),
+        Err(error) => Err(error.into()),
+    }
+}
+
+async fn delete(yes: bool, json: bool) -> Result<(), ClientError> {
+    if !yes {
+        if !std::io::stdin().is_terminal() {
+            eprintln!("Refusing to delete the account without confirmation, pass --yes to skip it.");
+            return Err(ClientError::Aborted);
+        }
+        eprint!("Permanently delete this account, including any remaining credit? Subscriptions must be canceled separately. [y/N] ");
+        let mut line = String::new();
+        std::io::stdin()
+            .read_line(&mut line)
+            .map_err(|error| anyhow::Error::new(error).context("failed to read confirmation from stdin"))?;
+        if !matches!(line.trim().to_lowercase().as_str(), "y" | "yes") {
+            return Err(ClientError::Aborted);
+        }
+    }
+    // Disconnect first, so logging out is the only step left once the account is gone. The policy permits it whenever it permits deleting the account.
+    disconnect().await?;
+    let output: DeleteAccountOutput = run_command(ManagerCmd::ApiDeleteAccount {}).await??;
+    run_command::<()>(ManagerCmd::Logout {}).await??;
+    if json {
+        print_json(&output)?;
+    }
+    eprintln!("account deleted and logged out");
+    Ok(())
+}
+
+fn print_json(value: &impl serde::Serialize) -> Result<(), ClientError> {
+    let json = serde_json::to_string_pretty(value)
+        .map_err(anyhow::Error::new)
+        .context("JSON encoding failed")?;
+    println!("{json}");
+    Ok(())
+}
+
+fn print_account_info(account_info: &AccountInfo) {
+    let renewal = |renews: bool| if renews { "renews" } else { "ends" };
+    let mut rows: Vec<(&str, String)> = vec![("Status", if account_info.active { "active" } else { "expired" }.to_string())];
+    if let Some(expiry) = account_info.current_expiry {
+        rows.push(("Expires", format_timestamp(expiry)));
+    }
+    if let Some(top_up) = &account_info.top_up {
+        rows.push(("Credit expires", format_timestamp(top_up.credit_expires_at)));
+    }
+    if let Some(stripe) = &account_info.stripe_subscription {
+        rows.push((
+            "Subscription",
+            format!(
+                "{:?} ({} {})",
+                stripe.status,
+                renewal(!stripe.cancel_at_period_end),
+                format_timestamp(stripe.current_period_end)
+            ),
+        ));
+    }
+    if let Some(apple) = &account_info.apple_subscription {
+        rows.push((
+            "App Store subscription",
+            format!(
+                "{:?} ({} {})",
+                apple.status,
+                renewal(apple.auto_renew_status),
+                format_timestamp(apple.renewal_date)
+            ),
+        ));
+    }
+    if let Some(google) = &account_info.google_subscription {
+        let expires_at = google.expires_at.map(format_timestamp).unwrap_or_else(|| "unknown".to_string());
+        rows.push((
+            "Google Play subscription",
+            format!("{:?} ({} {expires_at})", google.status, renewal(google.auto_renew_status)),
+        ));
+    }
+    rows.push(("Auto-renews", if account_info.auto_renews.is_some() { "yes" } else { "no" }.to_string()));
+    let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    for (name, row) in rows {
+        println!("{:<width$}  {row}", format!("{name}:"), width = width + 1);
+    }
+}
+
+fn format_timestamp(secs: i64) -> String {
+    match chrono::Local.timestamp_opt(secs, 0) {
+        MappedLocalTime::Single(timestamp) => timestamp.to_string(),
+        MappedLocalTime::Ambiguous(_, _) | MappedLocalTime::None => secs.to_string(),
+    }
+}
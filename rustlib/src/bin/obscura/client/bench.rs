@@ -0,0 +1,97 @@
+use super::ClientError;
+use crate::{ClientBenchArgs, ClientBenchCommand};
+use anyhow::Context;
+use obscuravpn_client::linux::ipc::run_command;
+use obscuravpn_client::manager_cmd::ManagerCmd;
+use obscuravpn_client::quicwg::TransportKind;
+use obscuravpn_client::relay_selection::{RelayBench, RelayBenchResult};
+
+pub async fn bench(args: ClientBenchArgs) -> Result<(), ClientError> {
+    match args.command {
+        ClientBenchCommand::Relays { json, csv } => bench_relays(json, csv).await,
+    }
+}
+
+async fn bench_relays(json: bool, csv: bool) -> Result<(), ClientError> {
+    eprintln!("benchmarking relays, this can take a few seconds...");
+    let bench: RelayBench = run_command(ManagerCmd::BenchRelays {}).await??;
+    if json {
+        let json = serde_json::to_string_pretty(&bench)
+            .map_err(anyhow::Error::new)
+            .context("JSON encoding failed")?;
+        println!("{json}");
+    } else if csv {
+        println!("relay_id,ip,port,transport,rtt_ms,error");
+        for result in &bench.results {
+            println!(
+                "{},{},{},{},{},{}",
+                csv_field(&result.relay_id),
+                result.ip,
+                result.port,
+                transport_name(result.transport),
+                result.rtt_ms.map(|rtt_ms| rtt_ms.to_string()).unwrap_or_default(),
+                csv_field(result.error.as_deref().unwrap_or_default()),
+            );
+        }
+    } else {
+        print_table(&bench.results);
+    }
+    Ok(())
+}
+
+fn print_table(results: &[RelayBenchResult]) {
+    let rows: Vec<[String; 4]> = results
+        .iter()
+        .map(|result| {
+            let rtt = result.rtt_ms.map(|rtt_ms| format!("{rtt_ms} ms")).unwrap_or_else(|| "-".to_string());
+            let outcome = match &result.error {
+                Some(error) => format!("failed: {error}"),
+                None => "ok".to_string(),
+            };
+            [
+                result.relay_id.clone(),
+                format!("{}:{}", result.ip, result.port),
+                transport_name(result.transport).to_string(),
+                format!("{rtt:>7}  {outcome}"),
+            ]
+        })
+        .collect();
+    let header = [
+        "RELAY".to_string(),
+        "ADDRESS".to_string(),
+        "TRANSPORT".to_string(),
+        "    RTT  RESULT".to_string(),
+    ];
+    let width = |column: usize| rows.iter().chain([&header]).map(|row| row[column].chars().count()).max().unwrap_or(0);
+    let widths = [width(0), width(1), width(2)];
+    for row in [&header].into_iter().chain(&rows) {
+        println!(
+            "{:<w0$}  {:<w1$}  {:<w2$}  {}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2]
+        );
+    }
+    let reachable = results.iter().filter(|result| result.rtt_ms.is_some()).count();
+    println!("{reachable} of {} relay ports reachable", results.len());
+}
+
+fn transport_name(transport: TransportKind) -> &'static str {
+    match transport {
+        TransportKind::Quic => "quic",
+        TransportKind::TcpTls => "tcp-tls",
+    }
+}
+
+/// Quotes fields containing separators, quotes or newlines as described in RFC 4180.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
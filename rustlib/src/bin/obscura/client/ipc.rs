@@ -1,13 +1,82 @@
 use super::ClientError;
-use crate::ClientIpcTestArgs;
-use anyhow::anyhow;
-use obscuravpn_client::linux::ipc::run_command;
-use obscuravpn_client::manager_cmd::ManagerCmd;
+use crate::{ClientIpcArgs, ClientIpcTestArgs};
+use anyhow::{Context, anyhow};
+use obscuravpn_client::linux::ipc::{LinuxIpcHeader, MAX_RESPONSE_LEN, check_access, connect, read_frame, run_command, run_raw_command, write_frame};
+use obscuravpn_client::manager_cmd::{AccessLevel, ManagerCmd, ManagerCmdErrorCode};
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
 
 pub async fn ipc_test(_: ClientIpcTestArgs) -> Result<(), ClientError> {
+    // Anyone can ping the service, so also check whether the group membership grants control over it.
+    check_access(AccessLevel::Operator).await?;
     if let Err(error) = run_command::<()>(ManagerCmd::Ping {}).await? {
         tracing::error!(message_id = "my5QZfPB", ?error, "IPC ping returned error");
         return Err(anyhow!("IPC ping returned error").into());
     }
     Ok(())
 }
+
+pub async fn ipc(args: ClientIpcArgs) -> Result<(), ClientError> {
+    // Raw commands are passed through as is, so unsupported ones are rejected by the service instead of checked against the header.
+    let (mut stream, header) = connect().await?;
+    let Some(command) = args.command else {
+        if header.is_legacy() {
+            return sequential(stream, header).await;
+        }
+        return pipeline(stream).await;
+    };
+    let response = run_raw_command(&mut stream, &header, command.as_bytes()).await?;
+    stream
+        .shutdown()
+        .await
+        .map_err(|error| anyhow::Error::new(error).context("failed to close IPC connection"))?;
+    println!("{}", String::from_utf8_lossy(&response));
+    // The response is printed either way, but scripts can check the exit code instead of parsing it.
+    if let Ok(Err(error)) = serde_json::from_slice::<Result<Value, ManagerCmdErrorCode>>(&response) {
+        return Err(error.into());
+    }
+    Ok(())
+}
+
+/// Sends each line of stdin as a command on its own connection, for services predating pipelining.
+async fn sequential(mut stream: UnixStream, mut header: LinuxIpcHeader) -> Result<(), ClientError> {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut first = true;
+    while let Some(line) = lines.next_line().await.context("failed to read command from stdin")? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if !first {
+            (stream, header) = connect().await?;
+        }
+        first = false;
+        let response = run_raw_command(&mut stream, &header, line.as_bytes()).await?;
+        println!("{}", String::from_utf8_lossy(&response));
+    }
+    Ok(())
+}
+
+/// Sends each line of stdin as a command without waiting for responses, which are printed one per line in the same order.
+async fn pipeline(stream: UnixStream) -> Result<(), ClientError> {
+    let (mut reader, mut writer) = stream.into_split();
+    let send_commands = tokio::spawn(async move {
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        while let Some(line) = lines.next_line().await.context("failed to read command from stdin")? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            write_frame(&mut writer, line.as_bytes()).await.context("failed to send command")?;
+        }
+        // The service closes the connection after responding to all commands.
+        writer.shutdown().await.context("failed to close IPC connection")
+    });
+    let mut responses = 0;
+    while let Some(response) = read_frame(&mut reader, MAX_RESPONSE_LEN).await.context("failed to receive response")? {
+        println!("{}", String::from_utf8_lossy(&response));
+        responses += 1;
+    }
+    send_commands.await.context("command sender task failed")??;
+    tracing::info!(message_id = "Zr4hKc7P", responses, "finished pipelined IPC commands");
+    Ok(())
+}
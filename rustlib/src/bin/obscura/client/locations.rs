@@ -0,0 +1,137 @@
+use super::ClientError;
+use crate::{ClientConnectArgs, ClientLocationsArgs, ClientLocationsCommand};
+use anyhow::{Context, anyhow};
+use obscuravpn_api::cmd::ExitList;
+use obscuravpn_api::types::{CityCode, CountryCode, OneExit};
+use obscuravpn_client::cached_value::CachedValue;
+use obscuravpn_client::config::PinnedLocation;
+use obscuravpn_client::exit_selection::ExitSelector;
+use obscuravpn_client::linux::ipc::run_command;
+use obscuravpn_client::manager::Status;
+use obscuravpn_client::manager_cmd::ManagerCmd;
+use std::time::{Duration, SystemTime};
+
+const EXIT_LIST_FRESHNESS: Duration = Duration::from_secs(60 * 60);
+
+pub async fn locations(args: ClientLocationsArgs) -> Result<(), ClientError> {
+    match args.command {
+        Some(ClientLocationsCommand::Pin { country, city }) => return pin(&country, &city).await,
+        Some(ClientLocationsCommand::Unpin { country, city }) => return unpin(&country, &city).await,
+        None => {}
+    }
+    let exit_list = exit_list().await?;
+    let country = args.country.map(|country| country.to_lowercase());
+    let city = args.city.map(|city| city.to_lowercase());
+    let provider = args.provider.map(|provider| provider.to_lowercase());
+    let mut exits: Vec<&OneExit> = exit_list
+        .exits
+        .iter()
+        .filter(|exit| country.as_ref().is_none_or(|country| exit.city_code.country_code.0 == *country))
+        .filter(|exit| {
+            city.as_ref()
+                .is_none_or(|city| exit.city_code.city_code == *city || exit.city_name.to_lowercase() == *city)
+        })
+        .filter(|exit| {
+            provider
+                .as_ref()
+                .is_none_or(|provider| exit.provider_name.to_lowercase().contains(provider) || exit.provider_id.to_lowercase().contains(provider))
+        })
+        .collect();
+
+    if args.json {
+        let json = serde_json::to_string_pretty(&exits)
+            .map_err(anyhow::Error::new)
+            .context("JSON encoding failed")?;
+        println!("{json}");
+        return Ok(());
+    }
+
+    let status: Status = run_command(ManagerCmd::GetStatus { known_version: None }).await??;
+    let is_pinned = |exit: &OneExit| status.pinned_locations.iter().any(|pinned| is_location(pinned, &exit.city_code));
+    exits.sort_by(|a, b| {
+        (!is_pinned(a), &a.city_code.country_code.0, &a.city_name, &a.id).cmp(&(!is_pinned(b), &b.city_code.country_code.0, &b.city_name, &b.id))
+    });
+    if exits.is_empty() {
+        eprintln!("No locations match the given filters.");
+        return Ok(());
+    }
+    let city_name_width = exits.iter().map(|exit| exit.city_name.chars().count()).max().unwrap_or(0);
+    let id_width = exits.iter().map(|exit| exit.id.chars().count()).max().unwrap_or(0);
+    for exit in exits {
+        println!(
+            "{} {}  {:<4} {:<city_name_width$}  {:<id_width$}  {}",
+            if is_pinned(exit) { '*' } else { ' ' },
+            exit.city_code.country_code.0.to_uppercase(),
+            exit.city_code.city_code,
+            exit.city_name,
+            exit.id,
+            exit.provider_name,
+        );
+    }
+    Ok(())
+}
+
+/// Builds the exit selector for `obscura connect`, checking that at least one exit matches.
+pub async fn exit_selector(args: ClientConnectArgs) -> Result<ExitSelector, ClientError> {
+    let selector = match (args.exit, args.country, args.city) {
+        (Some(id), _, _) => ExitSelector::Exit { id },
+        (None, Some(country), Some(city)) => ExitSelector::City { city_code: city_code(&country, &city) },
+        (None, Some(country), None) => ExitSelector::Country { country_code: CountryCode(country.to_lowercase()) },
+        (None, None, _) => return Ok(ExitSelector::Any {}),
+    };
+    if !exit_list().await?.exits.iter().any(|exit| selector.matches(exit)) {
+        return Err(anyhow!("No exit matches the requested location. Use `obscura locations` to list available locations.").into());
+    }
+    Ok(selector)
+}
+
+async fn pin(country: &str, city: &str) -> Result<(), ClientError> {
+    let city_code = city_code(country, city);
+    if !exit_list().await?.exits.iter().any(|exit| exit.city_code == city_code) {
+        return Err(anyhow!("Unknown location. Use `obscura locations` to list available locations.").into());
+    }
+    let status: Status = run_command(ManagerCmd::GetStatus { known_version: None }).await??;
+    let mut pinned_locations = status.pinned_locations;
+    if pinned_locations.iter().any(|pinned| is_location(pinned, &city_code)) {
+        eprintln!("location is already pinned");
+        return Ok(());
+    }
+    pinned_locations.push(PinnedLocation { country_code: city_code.country_code.0, city_code: city_code.city_code, pinned_at: SystemTime::now() });
+    run_command::<()>(ManagerCmd::SetPinnedExits { exits: pinned_locations }).await??;
+    eprintln!("pinned location");
+    Ok(())
+}
+
+async fn unpin(country: &str, city: &str) -> Result<(), ClientError> {
+    let city_code = city_code(country, city);
+    let status: Status = run_command(ManagerCmd::GetStatus { known_version: None }).await??;
+    let mut pinned_locations = status.pinned_locations;
+    let count = pinned_locations.len();
+    pinned_locations.retain(|pinned| !is_location(pinned, &city_code));
+    if pinned_locations.len() == count {
+        return Err(anyhow!("Location is not pinned.").into());
+    }
+    run_command::<()>(ManagerCmd::SetPinnedExits { exits: pinned_locations }).await??;
+    eprintln!("unpinned location");
+    Ok(())
+}
+
+async fn exit_list() -> Result<ExitList, ClientError> {
+    // Best effort, a stale list is better than none.
+    match run_command::<()>(ManagerCmd::RefreshExitList { freshness: EXIT_LIST_FRESHNESS }).await {
+        Ok(Ok(())) => {}
+        Ok(Err(error)) => tracing::warn!(message_id = "Pf6cRy2T", ?error, "failed to refresh exit list"),
+        // Refreshing requires operator access, listing doesn't.
+        Err(error) => tracing::warn!(message_id = "Bq7tMz4R", ?error, "cannot refresh exit list"),
+    }
+    let exit_list: CachedValue<ExitList> = run_command(ManagerCmd::GetExitList { known_version: None }).await??;
+    Ok(exit_list.value)
+}
+
+fn city_code(country: &str, city: &str) -> CityCode {
+    CityCode { country_code: CountryCode(country.to_lowercase()), city_code: city.to_lowercase() }
+}
+
+fn is_location(pinned: &PinnedLocation, city_code: &CityCode) -> bool {
+    pinned.country_code == city_code.country_code.0 && pinned.city_code == city_code.city_code
+}
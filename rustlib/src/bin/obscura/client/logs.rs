@@ -0,0 +1,79 @@
+use super::ClientError;
+use crate::ClientLogsArgs;
+use anyhow::Context;
+use obscuravpn_client::linux::ipc::run_command;
+use obscuravpn_client::log_query::{LogEntries, LogQuery};
+use obscuravpn_client::manager_cmd::ManagerCmd;
+use serde_json::Value;
+use std::time::{Duration, SystemTime};
+
+/// Entries are requested once per interval while following, so this only limits bursts.
+const FOLLOW_LIMIT: usize = 10_000;
+
+pub async fn logs(args: ClientLogsArgs) -> Result<(), ClientError> {
+    let since = args.since.as_deref().map(parse_since).transpose()?;
+    let mut query = LogQuery { since, min_level: args.level, message_id: args.message_id, cursor: None, limit: args.lines };
+    loop {
+        let LogEntries { entries, cursor, truncated: _ } = run_command(ManagerCmd::GetLogs { query: query.clone() }).await??;
+        for entry in &entries {
+            if args.json {
+                let json = serde_json::to_string(entry).map_err(anyhow::Error::new).context("JSON encoding failed")?;
+                println!("{json}");
+            } else {
+                println!("{}", format_entry(entry));
+            }
+        }
+        if !args.follow {
+            return Ok(());
+        }
+        query.cursor = Some(cursor);
+        query.limit = FOLLOW_LIMIT;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+fn parse_since(since: &str) -> Result<SystemTime, ClientError> {
+    if let Ok(timestamp) = chrono::DateTime::parse_from_rfc3339(since) {
+        return Ok(timestamp.into());
+    }
+    let invalid = || anyhow::anyhow!("invalid --since value {since:?}, expected e.g. \"10m\", \"2h\", \"1d\" or an RFC 3339 timestamp");
+    let unit_pos = since.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+    let (amount, unit) = since.split_at(unit_pos);
+    let amount: u64 = amount.parse().map_err(|_| invalid())?;
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(invalid().into()),
+    };
+    let ago = Duration::from_secs(amount.saturating_mul(unit_secs));
+    Ok(SystemTime::now().checked_sub(ago).unwrap_or(SystemTime::UNIX_EPOCH))
+}
+
+/// Formats an entry as e.g. `2025-03-01 11:00:00.123 WARN  [bbbbbbbb] slow error="timeout" (obscuravpn_client::manager)`.
+fn format_entry(entry: &Value) -> String {
+    let timestamp = entry["timestamp"]
+        .as_str()
+        .and_then(|timestamp| chrono::DateTime::parse_from_rfc3339(timestamp).ok())
+        .map(|timestamp| timestamp.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S%.3f").to_string())
+        .unwrap_or_else(|| entry["timestamp"].to_string());
+    let level = entry["level"].as_str().unwrap_or("?");
+    let mut line = format!("{timestamp} {level:<5}");
+    let fields = &entry["fields"];
+    if let Some(message_id) = fields["message_id"].as_str() {
+        line += &format!(" [{message_id}]");
+    }
+    if let Some(message) = fields["message"].as_str() {
+        line += &format!(" {message}");
+    }
+    if let Some(fields) = fields.as_object() {
+        for (name, value) in fields.iter().filter(|(name, _)| !matches!(name.as_str(), "message" | "message_id")) {
+            line += &format!(" {name}={value}");
+        }
+    }
+    if let Some(target) = entry["target"].as_str() {
+        line += &format!(" ({target})");
+    }
+    line
+}
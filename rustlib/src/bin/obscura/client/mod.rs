@@ -1,16 +1,33 @@
+mod account;
+mod bench;
 mod ipc;
+mod locations;
+mod logs;
+mod settings;
+mod stats;
+mod status_bar;
 
-use crate::client::ipc::ipc_test;
-use crate::{ClientCommand, ClientDebugBundleArgs, ClientLoginArgs, ClientStatusArgs};
+use crate::client::account::account;
+use crate::client::bench::bench;
+use crate::client::ipc::{ipc, ipc_test};
+use crate::client::locations::{exit_selector, locations};
+use crate::client::logs::logs;
+use crate::client::settings::{FEATURE_FLAG_PREFIX, settings};
+use crate::client::stats::stats;
+use crate::client::status_bar::status_bar;
+use crate::{ClientCommand, ClientDebugBundleArgs, ClientDiagnoseArgs, ClientLeakTestArgs, ClientLoginArgs, ClientStatusArgs};
 use anyhow::Context;
 use chrono::{MappedLocalTime, TimeZone};
 use obscuravpn_api::types::{AccountId, AccountInfo};
-use obscuravpn_client::exit_selection::ExitSelector;
+use obscuravpn_client::captive_portal::CaptivePortalBypass;
+use obscuravpn_client::diagnostics::{DiagnosticsReport, Recommendation};
+use obscuravpn_client::leak_test::{LeakTestOutcome, LeakTestReport};
 use obscuravpn_client::linux::client_log_dir;
 use obscuravpn_client::linux::debug_bundle::create_combined_debug_bundle;
-use obscuravpn_client::linux::ipc::{LinuxIpcError, run_command};
+use obscuravpn_client::linux::ipc::{LinuxIpcError, connect, run_command, subscribe_events};
 use obscuravpn_client::manager::{Status, TunnelArgs, VpnStatus};
-use obscuravpn_client::manager_cmd::{ManagerCmd, ManagerCmdErrorCode};
+use obscuravpn_client::manager_cmd::{AccessLevel, ManagerCmd, ManagerCmdErrorCode};
+use obscuravpn_client::manager_event::{EventSubscription, ManagerEvent};
 
 #[derive(thiserror::Error, Debug)]
 pub enum ClientError {
@@ -24,8 +41,45 @@ pub enum ClientError {
     NoService,
     #[error("Malformed account ID.")]
     MalformedAccountId,
+    #[error("Leak test failed.")]
+    LeakTestFailed,
     #[error("The running Obscura VPN service does not match this app version ({app_version}).")]
     VersionMismatch { service_version: String, app_version: String },
+    #[error("Not logged in.")]
+    NotLoggedIn,
+    #[error("Account is expired (top-up or subscribe to activate).")]
+    AccountInactive,
+    #[error("Aborted.")]
+    Aborted,
+    #[error("The running Obscura VPN service does not support {command}, restart it to complete the update.")]
+    UnsupportedCommand { command: &'static str },
+    #[error("Locked by the administrator's policy.")]
+    PolicyLocked,
+    #[error("No captive portal detected on this network.")]
+    NoCaptivePortal,
+}
+
+impl ClientError {
+    /// Process exit code, so scripts can tell failures apart without parsing messages. 2 is used by argument parsing errors.
+    ///
+    /// Errors which existed before distinct exit codes were introduced keep exiting with 1, as scripts may depend on it.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ClientError::Unexpected(_)
+            | ClientError::NoService
+            | ClientError::InsufficientPermissions
+            | ClientError::VersionMismatch { service_version: _, app_version: _ }
+            | ClientError::ApiUnreachable
+            | ClientError::MalformedAccountId => 1,
+            ClientError::NotLoggedIn => 8,
+            ClientError::AccountInactive => 9,
+            ClientError::LeakTestFailed => 10,
+            ClientError::Aborted => 11,
+            ClientError::UnsupportedCommand { command: _ } => 12,
+            ClientError::PolicyLocked => 13,
+            ClientError::NoCaptivePortal => 14,
+        }
+    }
 }
 
 impl From<ManagerCmdErrorCode> for ClientError {
@@ -33,6 +87,9 @@ impl From<ManagerCmdErrorCode> for ClientError {
         match error {
             ManagerCmdErrorCode::ApiInvalidAccountId => ClientError::MalformedAccountId,
             ManagerCmdErrorCode::ApiUnreachable => ClientError::ApiUnreachable,
+            ManagerCmdErrorCode::InsufficientPermissions => ClientError::InsufficientPermissions,
+            ManagerCmdErrorCode::PolicyLocked => ClientError::PolicyLocked,
+            ManagerCmdErrorCode::NoCaptivePortal => ClientError::NoCaptivePortal,
             ManagerCmdErrorCode::ApiAssociateAccountConflict
             | ManagerCmdErrorCode::ApiError
             | ManagerCmdErrorCode::ApiNoLongerSupported
@@ -51,6 +108,7 @@ impl From<LinuxIpcError> for ClientError {
             LinuxIpcError::InsufficientPermissions => ClientError::InsufficientPermissions,
             LinuxIpcError::NoListener => ClientError::NoService,
             LinuxIpcError::VersionMismatch { service_version, app_version } => ClientError::VersionMismatch { service_version, app_version },
+            LinuxIpcError::UnsupportedCommand { command } => ClientError::UnsupportedCommand { command },
             LinuxIpcError::Other => ClientError::Unexpected(anyhow::Error::msg("unexpected IPC error")),
         }
     }
@@ -66,10 +124,20 @@ pub async fn run(no_group_refresh: bool, cmd: ClientCommand) -> Result<(), Clien
     match cmd {
         ClientCommand::AddOperator { users } => crate::add_operator::run_add_operator(users).await,
         ClientCommand::Login(args) => login(args).await,
-        ClientCommand::Connect(_args) => go_to_target_state(Some(TunnelArgs { exit: ExitSelector::Any {} })).await,
+        ClientCommand::Account(args) => account(args).await,
+        ClientCommand::Connect(args) => go_to_target_state(Some(TunnelArgs { exit: exit_selector(args).await? })).await,
         ClientCommand::Disconnect(_args) => go_to_target_state(None).await,
         ClientCommand::Status(args) => status(args).await,
+        ClientCommand::Stats(args) => stats(args).await,
+        ClientCommand::Locations(args) => locations(args).await,
+        ClientCommand::Settings(args) => settings(args).await,
         ClientCommand::DebugBundle(args) => debug_bundle(args).await,
+        ClientCommand::Logs(args) => logs(args).await,
+        ClientCommand::Diagnose(args) => diagnose(args).await,
+        ClientCommand::Bench(args) => bench(args).await,
+        ClientCommand::LeakTest(args) => leak_test(args).await,
+        ClientCommand::CaptivePortalBypass => captive_portal_bypass().await,
+        ClientCommand::Ipc(args) => ipc(args).await,
         ClientCommand::IpcTest(args) => ipc_test(args).await,
     }
 }
@@ -83,32 +151,126 @@ async fn debug_bundle(args: ClientDebugBundleArgs) -> Result<(), ClientError> {
     Ok(())
 }
 
+async fn diagnose(args: ClientDiagnoseArgs) -> Result<(), ClientError> {
+    eprintln!("running diagnostics, this can take up to a minute...");
+    let report: DiagnosticsReport = run_command(ManagerCmd::RunDiagnostics {}).await??;
+    if args.json {
+        let json = serde_json::to_string_pretty(&report)
+            .map_err(anyhow::Error::new)
+            .context("JSON encoding failed")?;
+        println!("{json}");
+        return Ok(());
+    }
+    for (name, probe) in [("UDP", &report.relays_quic), ("TCP/TLS", &report.relays_tcp_tls)] {
+        match (&probe.error, probe.reachable.first()) {
+            (Some(error), _) => println!("relays over {name}: not probed ({error})"),
+            (None, Some(fastest)) => println!(
+                "relays over {name}: {}/{} ports reachable, fastest {} port {} ({} ms)",
+                probe.reachable.len(),
+                probe.attempted,
+                fastest.relay_id,
+                fastest.port,
+                fastest.rtt_ms
+            ),
+            (None, None) => println!("relays over {name}: 0/{} ports reachable", probe.attempted),
+        }
+    }
+    println!();
+    for verdict in &report.verdicts {
+        println!("{verdict}");
+    }
+    for recommendation in &report.recommendations {
+        match recommendation {
+            Recommendation::EnableFeatureFlag { flag } => println!("Recommended: obscura settings set {FEATURE_FLAG_PREFIX}{flag} true"),
+        }
+    }
+    Ok(())
+}
+
+async fn leak_test(args: ClientLeakTestArgs) -> Result<(), ClientError> {
+    let report: LeakTestReport = run_command(ManagerCmd::RunLeakTest {}).await??;
+    if args.json {
+        let json = serde_json::to_string_pretty(&report)
+            .map_err(anyhow::Error::new)
+            .context("JSON encoding failed")?;
+        println!("{json}");
+    } else {
+        for check in &report.checks {
+            let outcome = match check.outcome {
+                LeakTestOutcome::Pass => "PASS",
+                LeakTestOutcome::Fail => "FAIL",
+                LeakTestOutcome::Skipped => "SKIP",
+            };
+            println!("{outcome} {}", check.name);
+            for detail in &check.details {
+                println!("     {detail}");
+            }
+        }
+    }
+    if !report.passed {
+        return Err(ClientError::LeakTestFailed);
+    }
+    if !args.json {
+        println!("Leak test passed.");
+    }
+    Ok(())
+}
+
 async fn status(args: ClientStatusArgs) -> Result<(), ClientError> {
-    let get_account_info_result: Result<AccountInfo, _> = run_command(ManagerCmd::ApiGetAccountInfo {}).await?;
-    match get_account_info_result {
-        Ok(account_info) => {
-            if !args.json {
-                println!("Account is {}.", account_info_summary(&account_info))
+    if args.format.is_some() || args.waybar {
+        return status_bar(args).await;
+    }
+    // Read-only peers can't fetch account info, and peers authorized by polkit would be prompted to authenticate just to see the status.
+    let (_stream, header) = connect().await?;
+    if header.grants(AccessLevel::Operator) {
+        let get_account_info_result: Result<AccountInfo, _> = run_command(ManagerCmd::ApiGetAccountInfo {}).await?;
+        match get_account_info_result {
+            Ok(account_info) => {
+                if !args.json {
+                    println!("Account is {}.", account_info_summary(&account_info))
+                }
             }
+            Err(error) => eprintln!("Failed to update account info: {}", ClientError::from(error)),
         }
-        Err(error) => eprintln!("Failed to update account info: {}", ClientError::from(error)),
     }
+    if !args.follow {
+        let status: Status = run_command(ManagerCmd::GetStatus { known_version: None }).await??;
+        return print_status(&status, args.json);
+    }
+    // The subscription starts with the current status.
+    let mut events = match subscribe_events(EventSubscription::default()).await {
+        Ok(events) => events,
+        Err(LinuxIpcError::UnsupportedCommand { command: _ }) => return poll_status(args.json).await,
+        Err(error) => return Err(error.into()),
+    };
+    while let Some(event) = events.recv().await? {
+        if let ManagerEvent::Status(status) = event {
+            print_status(&status, args.json)?;
+        }
+    }
+    Err(ClientError::NoService)
+}
+
+/// Follows status changes of services which don't support event subscriptions.
+async fn poll_status(json: bool) -> Result<(), ClientError> {
     let mut known_version = None;
     loop {
         let status: Status = run_command(ManagerCmd::GetStatus { known_version }).await??;
         known_version = Some(status.version);
-        if args.json {
-            let json = serde_json::to_string_pretty(&status)
-                .map_err(anyhow::Error::new)
-                .context("JSON encoding failed")?;
-            println!("{json}");
-        } else {
-            println!("VPN is {}.", vpn_status_summary(&status.vpn_status));
-        }
-        if !args.follow {
-            break Ok(());
-        }
+        print_status(&status, json)?;
+    }
+}
+
+fn print_status(status: &Status, json: bool) -> Result<(), ClientError> {
+    if json {
+        let json = serde_json::to_string_pretty(status)
+            .map_err(anyhow::Error::new)
+            .context("JSON encoding failed")?;
+        println!("{json}");
+    } else {
+        println!("VPN is {}.", vpn_status_summary(&status.vpn_status));
     }
+    Ok(())
 }
 
 async fn login(args: ClientLoginArgs) -> Result<(), ClientError> {
@@ -155,6 +317,19 @@ async fn go_to_target_state(target_state: Option<TunnelArgs>) -> Result<(), Clie
     Ok(())
 }
 
+async fn captive_portal_bypass() -> Result<(), ClientError> {
+    let bypass: CaptivePortalBypass = run_command(ManagerCmd::BypassCaptivePortal {}).await??;
+    match &bypass.portal.url {
+        Some(url) => println!("Open {url} in your browser to log in."),
+        None => println!("Open any http:// website in your browser to log in."),
+    }
+    println!(
+        "The portal is reachable for {} minutes, other traffic stays blocked. The VPN connects once you are logged in.",
+        bypass.duration_secs / 60
+    );
+    Ok(())
+}
+
 fn vpn_status_summary(vpn_status: &VpnStatus) -> String {
     match vpn_status {
         VpnStatus::Connecting { connect_error: Some(error_code), .. } => {
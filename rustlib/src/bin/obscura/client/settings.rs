@@ -0,0 +1,280 @@
+use super::ClientError;
+use crate::{ClientSettingsArgs, ClientSettingsCommand};
+use anyhow::{Context, anyhow};
+use obscuravpn_client::config::{Config, PinnedLocation};
+use obscuravpn_client::linux::ipc::run_command;
+use obscuravpn_client::manager::Status;
+use obscuravpn_client::manager_cmd::ManagerCmd;
+use obscuravpn_client::network_config::DnsContentBlock;
+use serde_json::{Map, Value, json};
+use std::time::SystemTime;
+
+pub const FEATURE_FLAG_PREFIX: &str = "feature-flag.";
+
+#[derive(Clone)]
+enum Setting {
+    AutoConnect,
+    LocalNetworkAccess,
+    UseSystemDns,
+    DnsContentBlock,
+    InNewAccountFlow,
+    PinnedLocations,
+    NetworkRules,
+    SniRelay,
+    ApiUrl,
+    ApiHostAlternate,
+    ApiDohBootstrapUrls,
+    FeatureFlag(String),
+}
+
+impl Setting {
+    const FIXED: &[Setting] = &[
+        Setting::AutoConnect,
+        Setting::LocalNetworkAccess,
+        Setting::UseSystemDns,
+        Setting::DnsContentBlock,
+        Setting::InNewAccountFlow,
+        Setting::PinnedLocations,
+        Setting::NetworkRules,
+        Setting::SniRelay,
+        Setting::ApiUrl,
+        Setting::ApiHostAlternate,
+        Setting::ApiDohBootstrapUrls,
+    ];
+
+    fn all(status: &Status) -> impl Iterator<Item = Setting> {
+        let feature_flags = status.feature_flag_keys.iter().map(|key| Setting::FeatureFlag(key.clone()));
+        Self::FIXED.iter().cloned().chain(feature_flags)
+    }
+
+    fn parse(name: &str, status: &Status) -> Result<Setting, ClientError> {
+        if let Some(key) = name.strip_prefix(FEATURE_FLAG_PREFIX)
+            && let Some(key) = status.feature_flag_keys.iter().find(|known| known.eq_ignore_ascii_case(key))
+        {
+            return Ok(Setting::FeatureFlag(key.clone()));
+        }
+        Self::all(status).find(|setting| setting.name() == name).ok_or_else(|| {
+            let names: Vec<String> = Self::all(status).map(|setting| setting.name()).collect();
+            anyhow!("Unknown setting {name:?}. Available settings: {}", names.join(", ")).into()
+        })
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Setting::AutoConnect => "auto-connect".to_string(),
+            Setting::LocalNetworkAccess => "local-network-access".to_string(),
+            Setting::UseSystemDns => "use-system-dns".to_string(),
+            Setting::DnsContentBlock => "dns-content-block".to_string(),
+            Setting::InNewAccountFlow => "in-new-account-flow".to_string(),
+            Setting::PinnedLocations => "pinned-locations".to_string(),
+            Setting::NetworkRules => "network-rules".to_string(),
+            Setting::SniRelay => "sni-relay".to_string(),
+            Setting::ApiUrl => "api-url".to_string(),
+            Setting::ApiHostAlternate => "api-host-alternate".to_string(),
+            Setting::ApiDohBootstrapUrls => "api-doh-bootstrap-urls".to_string(),
+            Setting::FeatureFlag(key) => format!("{FEATURE_FLAG_PREFIX}{key}"),
+        }
+    }
+
+    fn get(&self, status: &Status) -> Value {
+        match self {
+            Setting::AutoConnect => json!(status.auto_connect),
+            Setting::LocalNetworkAccess => json!(status.local_network_access),
+            Setting::UseSystemDns => json!(status.use_system_dns),
+            Setting::DnsContentBlock => json!(status.dns_content_block),
+            Setting::InNewAccountFlow => json!(status.in_new_account_flow),
+            Setting::PinnedLocations => json!(status.pinned_locations),
+            Setting::NetworkRules => json!(status.network_rules),
+            Setting::SniRelay => json!(status.sni_relay),
+            Setting::ApiUrl => json!(status.api_url_override),
+            Setting::ApiHostAlternate => json!(status.api_host_alternate),
+            Setting::ApiDohBootstrapUrls => json!(status.api_doh_bootstrap_urls),
+            Setting::FeatureFlag(key) => json!(status.feature_flags.get(key)),
+        }
+    }
+
+    /// Formats the value the same way `set` accepts it.
+    fn display(&self, status: &Status) -> String {
+        match self {
+            Setting::DnsContentBlock => {
+                let categories = enabled_categories(&self.get(status));
+                if categories.is_empty() {
+                    "none".to_string()
+                } else {
+                    categories.join(",")
+                }
+            }
+            Setting::PinnedLocations => status
+                .pinned_locations
+                .iter()
+                .map(|pinned| format!("{}-{}", pinned.country_code, pinned.city_code))
+                .collect::<Vec<_>>()
+                .join(","),
+            Setting::NetworkRules => self.get(status).to_string(),
+            _ => match self.get(status) {
+                Value::Null => "unset".to_string(),
+                Value::String(value) => value,
+                Value::Array(values) => values
+                    .iter()
+                    .map(|value| value.as_str().unwrap_or_default())
+                    .collect::<Vec<_>>()
+                    .join(","),
+                value => value.to_string(),
+            },
+        }
+    }
+
+    /// Builds the command applying `value`, or restoring the default if `value` is `None`.
+    fn set_cmd(&self, value: Option<&str>, status: &Status) -> Result<ManagerCmd, ClientError> {
+        let defaults = Config::default();
+        let bool_value = |default: bool| value.map(parse_bool).unwrap_or(Ok(default));
+        let list_value = || -> Option<Vec<String>> {
+            value.map(|value| {
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+        };
+        Ok(match self {
+            Setting::AutoConnect => ManagerCmd::SetAutoConnect { enable: bool_value(defaults.auto_connect)? },
+            Setting::LocalNetworkAccess => ManagerCmd::SetLocalNetworkAccess { enable: bool_value(defaults.local_network_access.is_enabled())? },
+            Setting::UseSystemDns => ManagerCmd::SetUseSystemDns { enable: bool_value(defaults.dns.is_system())? },
+            Setting::DnsContentBlock => {
+                ManagerCmd::SetDnsContentBlock { value: value.map(parse_dns_content_block).unwrap_or(Ok(defaults.dns_content_block))? }
+            }
+            Setting::InNewAccountFlow => ManagerCmd::SetInNewAccountFlow { value: bool_value(defaults.in_new_account_flow)? },
+            Setting::PinnedLocations => ManagerCmd::SetPinnedExits {
+                exits: list_value()
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|location| parse_pinned_location(location, status))
+                    .collect::<Result<_, _>>()?,
+            },
+            Setting::NetworkRules => ManagerCmd::SetNetworkRules {
+                rules: value
+                    .map(serde_json::from_str)
+                    .transpose()
+                    .map_err(|error| anyhow!("Expected a JSON array of network rules: {error}"))?
+                    .unwrap_or_default(),
+            },
+            Setting::SniRelay => ManagerCmd::SetSniRelay { host: value.map(str::to_string) },
+            Setting::ApiUrl => ManagerCmd::SetApiUrl { url: value.map(str::to_string) },
+            Setting::ApiHostAlternate => ManagerCmd::SetApiHostAlternate { host: value.map(str::to_string) },
+            Setting::ApiDohBootstrapUrls => ManagerCmd::SetApiDohBootstrapUrls { urls: list_value() },
+            Setting::FeatureFlag(key) => ManagerCmd::SetFeatureFlag { flag: key.clone(), active: bool_value(defaults.feature_flags.get(key))? },
+        })
+    }
+}
+
+pub async fn settings(args: ClientSettingsArgs) -> Result<(), ClientError> {
+    match args.command {
+        None => get(None, args.json).await,
+        Some(ClientSettingsCommand::Get { name }) => get(name.as_deref(), args.json).await,
+        Some(ClientSettingsCommand::Set { name, value, reset }) => {
+            let value = if reset { None } else { value };
+            set(&name, value.as_deref(), args.json).await
+        }
+    }
+}
+
+async fn get(name: Option<&str>, json: bool) -> Result<(), ClientError> {
+    let status: Status = run_command(ManagerCmd::GetStatus { known_version: None }).await??;
+    if let Some(name) = name {
+        print_setting(&Setting::parse(name, &status)?, &status, json)
+    } else if json {
+        let settings: Map<String, Value> = Setting::all(&status).map(|setting| (setting.name(), setting.get(&status))).collect();
+        print_json(&settings)
+    } else {
+        for setting in Setting::all(&status) {
+            println!("{} = {}", setting.name(), setting.display(&status));
+        }
+        Ok(())
+    }
+}
+
+async fn set(name: &str, value: Option<&str>, json: bool) -> Result<(), ClientError> {
+    let status: Status = run_command(ManagerCmd::GetStatus { known_version: None }).await??;
+    let setting = Setting::parse(name, &status)?;
+    run_command::<()>(setting.set_cmd(value, &status)?).await??;
+    let status: Status = run_command(ManagerCmd::GetStatus { known_version: None }).await??;
+    print_setting(&setting, &status, json)
+}
+
+fn print_setting(setting: &Setting, status: &Status, json: bool) -> Result<(), ClientError> {
+    if json {
+        print_json(&setting.get(status))
+    } else {
+        println!("{}", setting.display(status));
+        Ok(())
+    }
+}
+
+fn print_json(value: &impl serde::Serialize) -> Result<(), ClientError> {
+    let json = serde_json::to_string_pretty(value)
+        .map_err(anyhow::Error::new)
+        .context("JSON encoding failed")?;
+    println!("{json}");
+    Ok(())
+}
+
+fn parse_bool(value: &str) -> Result<bool, ClientError> {
+    match value.to_lowercase().as_str() {
+        "true" | "on" | "yes" | "1" => Ok(true),
+        "false" | "off" | "no" | "0" => Ok(false),
+        _ => Err(anyhow!("Expected \"true\" or \"false\", got {value:?}.").into()),
+    }
+}
+
+fn enabled_categories(dns_content_block: &Value) -> Vec<&str> {
+    let Some(categories) = dns_content_block.as_object() else {
+        return Vec::new();
+    };
+    categories
+        .iter()
+        .filter(|(_, enabled)| enabled.as_bool() == Some(true))
+        .map(|(category, _)| category.as_str())
+        .collect()
+}
+
+/// Parses a comma separated list of categories to block, or "none".
+fn parse_dns_content_block(value: &str) -> Result<DnsContentBlock, ClientError> {
+    let Value::Object(mut categories) = json!(DnsContentBlock::default()) else {
+        return Err(anyhow!("unexpected DNS content block encoding").into());
+    };
+    for category in value
+        .split(',')
+        .map(str::trim)
+        .filter(|category| !category.is_empty() && *category != "none")
+    {
+        let Some(enabled) = categories.get_mut(category) else {
+            let known: Vec<&str> = categories.keys().map(String::as_str).collect();
+            return Err(anyhow!("Unknown content block category {category:?}. Available categories: {}", known.join(", ")).into());
+        };
+        *enabled = Value::Bool(true);
+    }
+    serde_json::from_value(Value::Object(categories))
+        .map_err(anyhow::Error::new)
+        .context("invalid DNS content block")
+        .map_err(Into::into)
+}
+
+/// Parses a `<country>-<city>` location, keeping the pin time of already pinned locations.
+fn parse_pinned_location(location: &str, status: &Status) -> Result<PinnedLocation, ClientError> {
+    let Some((country_code, city_code)) = location
+        .to_lowercase()
+        .split_once('-')
+        .map(|(country, city)| (country.to_string(), city.to_string()))
+    else {
+        return Err(anyhow!("Expected location as <country>-<city>, e.g. \"de-fra\", got {location:?}.").into());
+    };
+    let pinned_at = status
+        .pinned_locations
+        .iter()
+        .find(|pinned| pinned.country_code == country_code && pinned.city_code == city_code)
+        .map(|pinned| pinned.pinned_at)
+        .unwrap_or_else(SystemTime::now);
+    Ok(PinnedLocation { country_code, city_code, pinned_at })
+}
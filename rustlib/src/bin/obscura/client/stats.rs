@@ -0,0 +1,58 @@
+use super::ClientError;
+use crate::ClientStatsArgs;
+use anyhow::Context;
+use obscuravpn_client::linux::ipc::run_command;
+use obscuravpn_client::linux::traffic_stats::{TrafficRates, format_bytes, format_rate, format_uptime};
+use obscuravpn_client::manager::ManagerTrafficStats;
+use obscuravpn_client::manager_cmd::ManagerCmd;
+use serde_json::json;
+use std::time::Duration;
+use tokio::time::{MissedTickBehavior, interval};
+
+pub async fn stats(args: ClientStatsArgs) -> Result<(), ClientError> {
+    let mut ticks = interval(Duration::from_secs(args.interval));
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut previous: Option<ManagerTrafficStats> = None;
+    loop {
+        ticks.tick().await;
+        let stats: ManagerTrafficStats = run_command(ManagerCmd::GetTrafficStats {}).await??;
+        let rates = previous.as_ref().and_then(|previous| TrafficRates::between(previous, &stats));
+        if args.json {
+            let sample = json!({
+                "connectedMs": stats.connected_ms,
+                "txBytes": stats.tx_bytes,
+                "rxBytes": stats.rx_bytes,
+                "latestLatencyMs": stats.latest_latency_ms,
+                "txBytesPerSec": rates.map(|rates| rates.tx_bytes_per_sec),
+                "rxBytesPerSec": rates.map(|rates| rates.rx_bytes_per_sec),
+            });
+            let json = serde_json::to_string(&sample)
+                .map_err(anyhow::Error::new)
+                .context("JSON encoding failed")?;
+            println!("{json}");
+        } else {
+            println!("{}", summary(&stats, rates));
+        }
+        if !args.follow {
+            return Ok(());
+        }
+        previous = Some(stats);
+    }
+}
+
+pub fn summary(stats: &ManagerTrafficStats, rates: Option<TrafficRates>) -> String {
+    if stats.connected_ms == 0 {
+        return "not connected".to_string();
+    }
+    let mut summary = format!(
+        "uptime {}  latency {} ms  received {}  sent {}",
+        format_uptime(Duration::from_millis(stats.connected_ms)),
+        stats.latest_latency_ms,
+        format_bytes(stats.rx_bytes),
+        format_bytes(stats.tx_bytes),
+    );
+    if let Some(rates) = rates {
+        summary += &format!("  ↓ {}  ↑ {}", format_rate(rates.rx_bytes_per_sec), format_rate(rates.tx_bytes_per_sec));
+    }
+    summary
+}
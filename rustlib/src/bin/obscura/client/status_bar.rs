@@ -0,0 +1,311 @@
+use super::stats::summary as traffic_summary;
+use super::{ClientError, vpn_status_summary};
+use crate::ClientStatusArgs;
+use anyhow::Context;
+use obscuravpn_client::backoff::Backoff;
+use obscuravpn_client::linux::ipc::run_command;
+use obscuravpn_client::linux::traffic_stats::{TrafficRates, format_bytes, format_rate, format_uptime};
+use obscuravpn_client::manager::{ManagerTrafficStats, Status, VpnStatus};
+use obscuravpn_client::manager_cmd::ManagerCmd;
+use obscuravpn_client::quicwg::TransportKind;
+use serde_json::json;
+use std::collections::HashMap;
+use std::pin::pin;
+use std::time::Duration;
+use tokio::time::{MissedTickBehavior, interval};
+use uuid::Uuid;
+
+const PLACEHOLDERS: &[&str] = &[
+    "state",
+    "country",
+    "city",
+    "city_code",
+    "exit",
+    "provider",
+    "relay",
+    "transport",
+    "error",
+    "latency_ms",
+    "uptime",
+    "rx_rate",
+    "tx_rate",
+    "rx_bytes",
+    "tx_bytes",
+];
+
+const DEFAULT_WAYBAR_TEMPLATE: &str = "{state} {country}";
+
+#[derive(Debug, PartialEq, Eq)]
+enum Segment {
+    Text(String),
+    Placeholder(&'static str),
+}
+
+/// Output template such as `{state} {country} {latency_ms}`. Literal braces are written as `{{` and `}}`.
+#[derive(Debug, PartialEq, Eq)]
+struct StatusTemplate(Vec<Segment>);
+
+impl StatusTemplate {
+    fn parse(template: &str) -> Result<Self, String> {
+        let mut segments = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest.find('}').ok_or("unclosed '{' in format template")?;
+                    let name = &rest[..end];
+                    let placeholder = PLACEHOLDERS
+                        .iter()
+                        .find(|placeholder| **placeholder == name)
+                        .ok_or_else(|| format!("unknown placeholder {{{name}}}, available: {}", PLACEHOLDERS.join(", ")))?;
+                    if !text.is_empty() {
+                        segments.push(Segment::Text(std::mem::take(&mut text)));
+                    }
+                    segments.push(Segment::Placeholder(placeholder));
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => return Err("unmatched '}' in format template, write '}}' for a literal brace".to_string()),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+        Ok(Self(segments))
+    }
+
+    /// Placeholders without a value, e.g. the country while disconnected, render as empty and surrounding whitespace is trimmed.
+    fn render(&self, values: &HashMap<&'static str, String>) -> String {
+        let mut rendered = String::new();
+        for segment in &self.0 {
+            match segment {
+                Segment::Text(text) => rendered += text,
+                Segment::Placeholder(name) => rendered += values.get(name).map(String::as_str).unwrap_or_default(),
+            }
+        }
+        rendered.trim().to_string()
+    }
+
+    fn uses_traffic(&self) -> bool {
+        self.0.iter().any(|segment| {
+            matches!(
+                segment,
+                Segment::Placeholder("latency_ms" | "uptime" | "rx_rate" | "tx_rate" | "rx_bytes" | "tx_bytes")
+            )
+        })
+    }
+}
+
+fn state_name(vpn_status: &VpnStatus) -> &'static str {
+    match vpn_status {
+        VpnStatus::Connecting { .. } => "connecting",
+        VpnStatus::Connected { .. } => "connected",
+        VpnStatus::Disconnected {} => "disconnected",
+    }
+}
+
+fn values(status: &Status, traffic: Option<&(ManagerTrafficStats, Option<TrafficRates>)>) -> HashMap<&'static str, String> {
+    let mut values = HashMap::from([("state", state_name(&status.vpn_status).to_string())]);
+    match &status.vpn_status {
+        VpnStatus::Connecting { connect_error, .. } => {
+            if let Some(error) = connect_error {
+                values.insert("error", error.as_static_str().to_string());
+            }
+        }
+        VpnStatus::Connected { exit, relay, transport, .. } => {
+            values.insert("country", exit.city_code.country_code.0.to_uppercase());
+            values.insert("city", exit.city_name.clone());
+            values.insert("city_code", exit.city_code.city_code.clone());
+            values.insert("exit", exit.id.clone());
+            values.insert("provider", exit.provider_name.clone());
+            values.insert("relay", relay.id.clone());
+            let transport = match transport {
+                TransportKind::Quic => "quic",
+                TransportKind::TcpTls => "tcp-tls",
+            };
+            values.insert("transport", transport.to_string());
+        }
+        VpnStatus::Disconnected {} => {}
+    }
+    if let Some((stats, rates)) = traffic {
+        values.insert("latency_ms", stats.latest_latency_ms.to_string());
+        values.insert("uptime", format_uptime(Duration::from_millis(stats.connected_ms)));
+        values.insert("rx_bytes", format_bytes(stats.rx_bytes));
+        values.insert("tx_bytes", format_bytes(stats.tx_bytes));
+        if let Some(rates) = rates {
+            values.insert("rx_rate", format_rate(rates.rx_bytes_per_sec));
+            values.insert("tx_rate", format_rate(rates.tx_bytes_per_sec));
+        }
+    }
+    values
+}
+
+/// Compact status output for status bars like waybar, polybar and i3status. Prints a line whenever the output changes.
+pub async fn status_bar(args: ClientStatusArgs) -> Result<(), ClientError> {
+    let template = StatusTemplate::parse(args.format.as_deref().unwrap_or(DEFAULT_WAYBAR_TEMPLATE)).map_err(anyhow::Error::msg)?;
+    let mut last_output = None;
+    // Status bars start with the session and keep running while the service is restarted, e.g. during updates.
+    let mut backoff = Backoff::BACKGROUND.take(usize::MAX);
+    loop {
+        let mut connected = false;
+        let error = match print_status_changes(&args, &template, &mut last_output, &mut connected).await {
+            Ok(()) => return Ok(()),
+            Err(error) => error,
+        };
+        if !args.follow || !matches!(error, ClientError::NoService | ClientError::Unexpected(_)) {
+            return Err(error);
+        }
+        if connected {
+            backoff = Backoff::BACKGROUND.take(usize::MAX);
+        }
+        print_if_changed(unavailable_output(&template, args.waybar, &error)?, &mut last_output);
+        tracing::debug!(message_id = "Vq6tLr9M", "reconnecting after status bar update failed: {error}");
+        backoff.wait().await;
+    }
+}
+
+/// Prints the current output and, if following, each change until the service connection fails. `connected` is set once the service responded.
+async fn print_status_changes(
+    args: &ClientStatusArgs,
+    template: &StatusTemplate,
+    last_output: &mut Option<String>,
+    connected: &mut bool,
+) -> Result<(), ClientError> {
+    let poll_traffic = args.waybar || template.uses_traffic();
+
+    let mut status: Status = run_command(ManagerCmd::GetStatus { known_version: None }).await??;
+    *connected = true;
+    let mut traffic = None;
+    let mut ticks = interval(Duration::from_secs(args.interval));
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // The first tick completes immediately.
+    ticks.tick().await;
+    let mut status_update = pin!(next_status(status.version));
+    loop {
+        traffic = match (&status.vpn_status, poll_traffic) {
+            (VpnStatus::Connected { .. }, true) => {
+                let stats: ManagerTrafficStats = run_command(ManagerCmd::GetTrafficStats {}).await??;
+                let rates = traffic.and_then(|(previous, _)| TrafficRates::between(&previous, &stats));
+                Some((stats, rates))
+            }
+            _ => None,
+        };
+        let output = if args.waybar {
+            waybar_output(template, &status, traffic.as_ref())?
+        } else {
+            template.render(&values(&status, traffic.as_ref()))
+        };
+        print_if_changed(output, last_output);
+        if !args.follow {
+            return Ok(());
+        }
+        tokio::select! {
+            next = &mut status_update => {
+                status = next?;
+                status_update.set(next_status(status.version));
+            }
+            _ = ticks.tick(), if poll_traffic => {}
+        }
+    }
+}
+
+fn print_if_changed(output: String, last_output: &mut Option<String>) {
+    if last_output.as_ref() != Some(&output) {
+        println!("{output}");
+        *last_output = Some(output);
+    }
+}
+
+/// Output while the service can't be reached.
+fn unavailable_output(template: &StatusTemplate, waybar: bool, error: &ClientError) -> Result<String, ClientError> {
+    let state = "unavailable";
+    let text = template.render(&HashMap::from([("state", state.to_string())]));
+    if !waybar {
+        return Ok(text);
+    }
+    let output = json!({
+        "text": text,
+        "alt": state,
+        "tooltip": error.to_string(),
+        "class": state,
+    });
+    Ok(serde_json::to_string(&output)
+        .map_err(anyhow::Error::new)
+        .context("JSON encoding failed")?)
+}
+
+async fn next_status(known_version: Uuid) -> Result<Status, ClientError> {
+    Ok(run_command(ManagerCmd::GetStatus { known_version: Some(known_version) }).await??)
+}
+
+/// See the `custom` module documentation of waybar for the format.
+fn waybar_output(
+    template: &StatusTemplate,
+    status: &Status,
+    traffic: Option<&(ManagerTrafficStats, Option<TrafficRates>)>,
+) -> Result<String, ClientError> {
+    let state = state_name(&status.vpn_status);
+    let mut tooltip = format!("VPN is {}.", vpn_status_summary(&status.vpn_status));
+    if let Some((stats, rates)) = traffic {
+        tooltip += &format!("\n{}", traffic_summary(stats, *rates));
+    }
+    let output = json!({
+        "text": template.render(&values(status, traffic)),
+        "alt": state,
+        "tooltip": tooltip,
+        "class": state,
+    });
+    Ok(serde_json::to_string(&output)
+        .map_err(anyhow::Error::new)
+        .context("JSON encoding failed")?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_template() {
+        let template = StatusTemplate::parse("{{{state}}} {country} {latency_ms}ms").unwrap();
+        assert_eq!(
+            template,
+            StatusTemplate(vec![
+                Segment::Text("{".to_string()),
+                Segment::Placeholder("state"),
+                Segment::Text("} ".to_string()),
+                Segment::Placeholder("country"),
+                Segment::Text(" ".to_string()),
+                Segment::Placeholder("latency_ms"),
+                Segment::Text("ms".to_string()),
+            ])
+        );
+        assert!(template.uses_traffic());
+
+        let values = HashMap::from([
+            ("state", "connected".to_string()),
+            ("country", "DE".to_string()),
+            ("latency_ms", "23".to_string()),
+        ]);
+        assert_eq!(template.render(&values), "{connected} DE 23ms");
+        assert_eq!(
+            StatusTemplate::parse("{state} {country}")
+                .unwrap()
+                .render(&HashMap::from([("state", "disconnected".to_string())])),
+            "disconnected"
+        );
+
+        assert!(StatusTemplate::parse("{unknown}").is_err());
+        assert!(StatusTemplate::parse("{state").is_err());
+        assert!(StatusTemplate::parse("state}").is_err());
+    }
+}
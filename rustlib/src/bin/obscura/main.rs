@@ -41,6 +41,14 @@ pub struct ServiceArgs {
     #[cfg(target_os = "linux")]
     #[arg(long, value_enum, default_value_t = service::os::linux::dns::DnsManagerArg::Auto)]
     pub dns: service::os::linux::dns::DnsManagerArg,
+    #[cfg(target_os = "linux")]
+    #[arg(long, env = "OBSCURA_HTTP_API")]
+    /// Serve the HTTP control API on this address, e.g. "127.0.0.1:8731". Callers authenticate with the token in the http-api-token file of the config directory.
+    pub http_api: Option<std::net::SocketAddr>,
+    #[cfg(target_os = "linux")]
+    #[arg(long, env = "OBSCURA_HTTP_API_ALLOW_REMOTE", requires = "http_api")]
+    /// Allow serving the HTTP control API on an address other hosts can reach. The bearer token is sent unencrypted, so only use this on trusted networks, e.g. a container network.
+    pub http_api_allow_remote: bool,
 }
 
 #[derive(Args, Debug)]
@@ -53,7 +61,17 @@ pub struct ClientLoginArgs {
 }
 
 #[derive(Args, Debug)]
-pub struct ClientConnectArgs {}
+pub struct ClientConnectArgs {
+    #[arg(long)]
+    /// Two-letter country code of the location to connect to, e.g. "de".
+    pub country: Option<String>,
+    #[arg(long, requires = "country")]
+    /// City code of the location to connect to, e.g. "fra". Requires --country.
+    pub city: Option<String>,
+    #[arg(long, conflicts_with_all = ["country", "city"])]
+    /// ID of a specific exit server to connect to.
+    pub exit: Option<String>,
+}
 
 #[derive(Args, Debug)]
 pub struct ClientDisconnectArgs {}
@@ -63,9 +81,172 @@ pub struct ClientStatusArgs {
     #[arg(long, short)]
     /// Continuously print new status updates as they are published by the service.
     pub follow: bool,
-    #[arg(long)]
+    #[arg(long, conflicts_with_all = ["format", "waybar"])]
     /// Print full JSON status instead of summary.
     pub json: bool,
+    #[arg(long)]
+    /// Print a single line from a template for status bars, e.g. "{state} {country} {latency_ms}". Available placeholders: state, country, city, city_code, exit, provider, relay, transport, error, latency_ms, uptime, rx_rate, tx_rate, rx_bytes and tx_bytes.
+    pub format: Option<String>,
+    #[arg(long)]
+    /// Print JSON for a waybar custom module, with the text taken from --format if given. The state is "unavailable" while following and the service can't be reached.
+    pub waybar: bool,
+    #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u64).range(1..))]
+    /// Seconds between traffic statistics updates when following with --format or --waybar.
+    pub interval: u64,
+}
+
+#[derive(Args, Debug)]
+pub struct ClientStatsArgs {
+    #[arg(long, short)]
+    /// Keep sampling and print throughput rates computed between samples.
+    pub follow: bool,
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    /// Seconds between samples when following.
+    pub interval: u64,
+    #[arg(long)]
+    /// Print one JSON object per sample instead of summary.
+    pub json: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ClientLogsArgs {
+    #[arg(long, short)]
+    /// Keep printing new log entries as they are written by the service.
+    pub follow: bool,
+    #[arg(long)]
+    /// Only show entries since this time, either relative like "10m", "2h" and "1d", or an RFC 3339 timestamp.
+    pub since: Option<String>,
+    #[arg(long)]
+    /// Only show entries of this level or more severe: trace, debug, info, warn or error.
+    pub level: Option<obscuravpn_client::log_query::LogLevel>,
+    #[arg(long)]
+    /// Only show entries with this message ID.
+    pub message_id: Option<String>,
+    #[arg(long, short = 'n', default_value_t = 100)]
+    /// Number of most recent matching entries to show.
+    pub lines: usize,
+    #[arg(long)]
+    /// Print raw JSON log entries, one per line.
+    pub json: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ClientDiagnoseArgs {
+    #[arg(long)]
+    /// Print full JSON report, including raw probe results, instead of summary.
+    pub json: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ClientBenchArgs {
+    #[command(subcommand)]
+    pub command: ClientBenchCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ClientBenchCommand {
+    /// Measure handshake round-trip times to every relay on every port over QUIC and TCP/TLS.
+    Relays {
+        #[arg(long)]
+        /// Print results as JSON instead of a table.
+        json: bool,
+        #[arg(long, conflicts_with = "json")]
+        /// Print results as CSV instead of a table.
+        csv: bool,
+    },
+}
+
+#[derive(Args, Debug)]
+pub struct ClientLeakTestArgs {
+    #[arg(long)]
+    /// Print full JSON report instead of summary.
+    pub json: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ClientLocationsArgs {
+    #[command(subcommand)]
+    pub command: Option<ClientLocationsCommand>,
+    #[arg(long)]
+    /// Only list locations in this country, e.g. "de".
+    pub country: Option<String>,
+    #[arg(long)]
+    /// Only list locations matching this city code or name, e.g. "fra".
+    pub city: Option<String>,
+    #[arg(long)]
+    /// Only list exits of providers whose name or ID contains this text.
+    pub provider: Option<String>,
+    #[arg(long)]
+    /// Print full JSON exit list instead of summary.
+    pub json: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ClientLocationsCommand {
+    /// Pin a location, so it's shown first in location lists.
+    Pin { country: String, city: String },
+    /// Unpin a previously pinned location.
+    Unpin { country: String, city: String },
+}
+
+#[derive(Args, Debug)]
+pub struct ClientSettingsArgs {
+    #[command(subcommand)]
+    pub command: Option<ClientSettingsCommand>,
+    #[arg(long, global = true)]
+    /// Print settings as JSON.
+    pub json: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ClientSettingsCommand {
+    /// Print the value of a setting, or all settings if no name is given.
+    Get {
+        /// Setting name, e.g. "auto-connect" or "feature-flag.killSwitch".
+        name: Option<String>,
+    },
+    /// Change a setting.
+    Set {
+        /// Setting name, e.g. "auto-connect" or "feature-flag.killSwitch".
+        name: String,
+        #[arg(required_unless_present = "reset")]
+        /// New value. Booleans accept "true"/"false", lists are comma separated and pinned locations are given as "<country>-<city>".
+        value: Option<String>,
+        #[arg(long, conflicts_with = "value")]
+        /// Restore the default value instead.
+        reset: bool,
+    },
+}
+
+#[derive(Args, Debug)]
+pub struct ClientAccountArgs {
+    #[command(subcommand)]
+    pub command: ClientAccountCommand,
+    #[arg(long, global = true)]
+    /// Print JSON instead of summary.
+    pub json: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ClientAccountCommand {
+    /// Show account details such as expiry and subscriptions. Exits with code 9 if the account is not active.
+    Info,
+    /// Disconnect and log out.
+    Logout,
+    /// Permanently delete the account, including any remaining credit, and log out.
+    Delete {
+        #[arg(long)]
+        /// Don't ask for confirmation.
+        yes: bool,
+    },
+    /// Rotate the WireGuard key used for new connections.
+    RotateKey,
+}
+
+#[derive(Args, Debug)]
+pub struct ClientIpcArgs {
+    /// JSON encoded command, e.g. '{"ping":{}}'. If omitted, commands are read from stdin one per line and sent over a single connection without waiting for responses.
+    pub command: Option<String>,
 }
 
 #[derive(Args, Debug)]
@@ -84,15 +265,37 @@ pub enum ClientCommand {
     AddOperator { users: Vec<String> },
     /// Log in with your account number.
     Login(ClientLoginArgs),
+    /// Show and manage your account.
+    Account(ClientAccountArgs),
     /// Connect to the VPN.
     Connect(ClientConnectArgs),
     /// Disconnect from the VPN.
     Disconnect(ClientDisconnectArgs),
     /// Show account and VPN status.
     Status(ClientStatusArgs),
+    /// Show traffic statistics, latency and uptime of the current connection.
+    Stats(ClientStatsArgs),
+    /// List available locations and manage pinned locations.
+    Locations(ClientLocationsArgs),
+    /// Show and change settings.
+    Settings(ClientSettingsArgs),
     #[cfg(target_os = "linux")]
     /// Create a debug bundle and print its path.
     DebugBundle(ClientDebugBundleArgs),
+    /// Show service logs.
+    Logs(ClientLogsArgs),
+    /// Check connectivity to the Obscura API and relays and explain likely problems.
+    Diagnose(ClientDiagnoseArgs),
+    /// Benchmark connectivity, e.g. relay latency.
+    Bench(ClientBenchArgs),
+    #[cfg(target_os = "linux")]
+    /// Check that DNS and traffic can't bypass the VPN while connected. Exits with an error if any check fails.
+    LeakTest(ClientLeakTestArgs),
+    #[cfg(target_os = "linux")]
+    /// Temporarily let your browser reach the captive portal of the current network (e.g. hotel Wi-Fi) to log in, while other traffic stays blocked.
+    CaptivePortalBypass,
+    /// Send raw JSON commands to the service and print the JSON results, one per line. Intended for scripting, the command format may change between versions.
+    Ipc(ClientIpcArgs),
     #[command(hide = true)]
     IpcTest(ClientIpcTestArgs),
 }
@@ -234,7 +437,7 @@ async fn run_service(_args: ServiceArgs, _log_persistence: Option<LogPersistence
 async fn run_client(no_group_refresh: bool, args: ClientCommand) {
     if let Err(error) = client::run(no_group_refresh, args).await {
         eprintln!("{}", error);
-        exit(1)
+        exit(error.exit_code())
     }
 }
 
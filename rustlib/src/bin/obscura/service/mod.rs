@@ -3,7 +3,10 @@ pub mod os;
 use crate::ServiceArgs;
 
 use anyhow::Context;
+#[cfg(target_os = "linux")]
+use obscuravpn_client::manager_cmd::ManagerCmd;
 use obscuravpn_client::os::os_trait::{Os, RevocableOs};
+use obscuravpn_client::policy::Policy;
 use obscuravpn_client::version::release_version;
 use obscuravpn_client::wg_key_store::WgKeyStore;
 use obscuravpn_client::{logging::LogPersistence, manager::Manager};
@@ -26,7 +29,13 @@ pub async fn run(
     let is_restart = detect_restart(args.runtime_dir.as_deref(), scm_start_reason);
 
     #[cfg(target_os = "linux")]
-    let os_impl = os::linux::LinuxOsImpl::new(args.dns).await?;
+    let http_api = args.http_api.map(|listen| os::linux::http_api::HttpApiConfig {
+        listen,
+        allow_remote: args.http_api_allow_remote,
+        state_dir: args.config_dir.clone().into(),
+    });
+    #[cfg(target_os = "linux")]
+    let os_impl = os::linux::LinuxOsImpl::new(args.dns, args.runtime_dir.as_deref(), is_restart, http_api).await?;
     #[cfg(target_os = "windows")]
     let os_impl = os::windows::WindowsOsImpl::new().await?;
 
@@ -46,7 +55,13 @@ pub async fn run(
             WgKeyStore::None
         }
     };
+    #[cfg(target_os = "linux")]
+    let policy = Policy::load(Path::new(os::linux::POLICY_PATH)).map_err(|()| os::linux::LinuxServiceStartError::InvalidPolicy)?;
+    #[cfg(target_os = "windows")]
+    let policy = Policy::default();
     let src_version = release_version().to_owned();
+    #[cfg(target_os = "linux")]
+    let state_dir = std::path::PathBuf::from(&args.config_dir);
     let manager = Manager::new(
         args.config_dir.into(),
         wg_key_store,
@@ -55,8 +70,28 @@ pub async fn run(
         os_impl.network_interface(),
         log_persistence,
         !is_restart,
+        policy,
     )
     .context("failed to create manager")?;
+    #[cfg(target_os = "linux")]
+    tokio::spawn(os::linux::hooks::run_hooks(manager.subscribe_tunnel_state()));
+    #[cfg(target_os = "linux")]
+    tokio::spawn(os::linux::dns::watchdog::report_dns_watchdog_interventions(
+        manager.clone(),
+        os_impl.dns_watchdog_interventions(),
+    ));
+    #[cfg(target_os = "linux")]
+    tokio::spawn(os::linux::network_identity::watch_network_identity(
+        manager.clone(),
+        os_impl.network_interface(),
+    ));
+    #[cfg(target_os = "linux")]
+    {
+        let provisioned = os::linux::provisioning::provision(&manager, &state_dir).await;
+        if provisioned && !is_restart {
+            os::linux::provisioning::auto_connect(&manager).await;
+        }
+    }
 
     let mut shutdown = std::pin::pin!(async {
         match shutdown {
@@ -73,7 +108,24 @@ pub async fn run(
             _ = &mut shutdown => break,
             (cmd, response_fn) = os_impl.next_manager_command() => {
                 let manager = manager.clone();
-                tokio::spawn(async move { response_fn(cmd.run(&manager).await) });
+                #[cfg(target_os = "linux")]
+                let os_impl = os_impl.clone();
+                tokio::spawn(async move {
+                    // Commands requiring the OS network integration, which the manager doesn't have access to.
+                    #[cfg(target_os = "linux")]
+                    match cmd {
+                        ManagerCmd::RunLeakTest {} => return response_fn(Ok(os_impl.run_leak_test(&manager).await.into())),
+                        // Rejected by `run` below if locked by the policy.
+                        ManagerCmd::BypassCaptivePortal {} if manager.permitted_by_policy(&cmd) => {
+                            return response_fn(os_impl.bypass_captive_portal(&manager).await.map(Into::into));
+                        }
+                        _ => {}
+                    }
+                    response_fn(cmd.run(&manager).await)
+                });
+            }
+            (subscription, events_fn) = os_impl.next_event_subscription() => {
+                events_fn(manager.subscribe_events(subscription));
             }
         }
     }
@@ -0,0 +1,166 @@
+//! User-initiated captive portal bypass. Behind a captive portal the tunnel can't connect, and the kill switch keeps browsers from reaching the portal to log in. The bypass stops capturing traffic for a few minutes, but the kill switch only lets DNS to the network's resolvers and HTTP and HTTPS to the portal through, see `TrafficPolicy::BypassCaptivePortal`. It ends early once the tunnel connects.
+
+use crate::service::os::linux::LinuxOsImpl;
+use crate::service::os::linux::dns::resolv_conf::RESOLV_CONF_PATH;
+use crate::service::os::linux::dns::resolved;
+use obscuravpn_client::captive_portal::{BYPASS_DURATION, CaptivePortalBypass};
+use obscuravpn_client::manager::Manager;
+use obscuravpn_client::manager_cmd::ManagerCmdErrorCode;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::lookup_host;
+use tokio::time::{Instant, sleep_until, timeout};
+use url::{Host, Url};
+
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct ActiveBypass {
+    until: Instant,
+    /// Addresses of the portal, which are reachable via HTTP and HTTPS.
+    portal: Vec<IpAddr>,
+    /// The network's DNS servers.
+    resolvers: Vec<IpAddr>,
+}
+
+impl LinuxOsImpl {
+    pub async fn bypass_captive_portal(self: &Arc<Self>, manager: &Manager) -> Result<CaptivePortalBypass, ManagerCmdErrorCode> {
+        let portal = match manager.detect_captive_portal().await {
+            Ok(Some(portal)) => portal,
+            Ok(None) => {
+                tracing::info!(message_id = "Wd5jRn2X", "no captive portal detected, not bypassing");
+                return Err(ManagerCmdErrorCode::NoCaptivePortal);
+            }
+            Err(()) => return Err(ManagerCmdErrorCode::Other),
+        };
+        let host = portal
+            .url
+            .as_deref()
+            .and_then(|url| Url::parse(url).ok()?.host().map(|host| host.to_owned()));
+        let mut addrs = match &host {
+            Some(Host::Ipv4(ip)) => vec![IpAddr::V4(*ip)],
+            Some(Host::Ipv6(ip)) => vec![IpAddr::V6(*ip)],
+            Some(Host::Domain(_)) | None => Vec::new(),
+        };
+        let until = Instant::now() + BYPASS_DURATION;
+        tracing::info!(message_id = "Xk3pLw7C", ?portal, "starting captive portal bypass");
+        self.change_captive_portal_bypass(|bypass| *bypass = Some(ActiveBypass { until, portal: addrs.clone(), resolvers: Vec::new() }))
+            .await
+            .map_err(|()| ManagerCmdErrorCode::Other)?;
+
+        // The system DNS configuration only lists the network's resolvers again once the bypass is active.
+        let resolvers = self.network_resolvers().await;
+        if resolvers.is_empty() {
+            tracing::warn!(
+                message_id = "Fh6pXc3M",
+                "network resolvers unknown, blocking DNS during captive portal bypass"
+            );
+        } else {
+            tracing::info!(message_id = "Sj9dLq4T", ?resolvers, "allowing DNS to network resolvers");
+        }
+        self.change_captive_portal_bypass(|bypass| {
+            if let Some(bypass) = bypass.as_mut().filter(|bypass| bypass.until == until) {
+                bypass.resolvers = resolvers;
+            }
+        })
+        .await
+        .map_err(|()| ManagerCmdErrorCode::Other)?;
+
+        // Portal host names often only resolve with the network's resolver, which is used once the bypass is active.
+        if let Some(Host::Domain(domain)) = &host {
+            match timeout(RESOLVE_TIMEOUT, lookup_host((domain.as_str(), 443))).await {
+                Ok(Ok(resolved)) => addrs.extend(resolved.map(|addr| addr.ip())),
+                Ok(Err(error)) => tracing::warn!(message_id = "Rb8vNs4G", ?error, domain, "failed to resolve captive portal: {error}"),
+                Err(error) => tracing::warn!(message_id = "Hf2cTm9Q", ?error, domain, "resolving captive portal timed out: {error}"),
+            }
+            tracing::info!(message_id = "Mq6wZd3K", ?addrs, "allowing HTTP and HTTPS to captive portal");
+            self.change_captive_portal_bypass(|bypass| {
+                if let Some(bypass) = bypass.as_mut().filter(|bypass| bypass.until == until) {
+                    bypass.portal = addrs;
+                }
+            })
+            .await
+            .map_err(|()| ManagerCmdErrorCode::Other)?;
+        }
+
+        let this = self.clone();
+        tokio::spawn(async move {
+            sleep_until(until).await;
+            let result = this
+                .change_captive_portal_bypass(|bypass| {
+                    if bypass.as_ref().is_some_and(|bypass| bypass.until == until) {
+                        tracing::info!(message_id = "Tn4hYc8B", "captive portal bypass expired");
+                        *bypass = None;
+                    }
+                })
+                .await;
+            if let Err(()) = result {
+                tracing::error!(message_id = "Gp7sKx2V", "failed to end captive portal bypass");
+            }
+        });
+        Ok(CaptivePortalBypass { portal, duration_secs: BYPASS_DURATION.as_secs() })
+    }
+
+    /// Portal addresses and network resolvers if a bypass is active.
+    pub(super) fn active_captive_portal_bypass(&self) -> Option<(Vec<IpAddr>, Vec<IpAddr>)> {
+        let bypass = self.captive_portal_bypass.lock().unwrap();
+        bypass
+            .as_ref()
+            .filter(|bypass| bypass.until > Instant::now())
+            .map(|bypass| (bypass.portal.clone(), bypass.resolvers.clone()))
+    }
+
+    /// DNS servers of the network behind the preferred network interface. Read from `/etc/resolv.conf` unless it points to a local stub resolver, in which case systemd-resolved is asked for the interface's servers.
+    async fn network_resolvers(&self) -> Vec<IpAddr> {
+        match tokio::fs::read_to_string(RESOLV_CONF_PATH).await {
+            Ok(resolv_conf) => {
+                let nameservers = nameservers(&resolv_conf);
+                if !nameservers.is_empty() {
+                    return nameservers;
+                }
+            }
+            Err(error) => tracing::warn!(message_id = "Bw3kTn7H", ?error, "failed to read resolv.conf: {error}"),
+        }
+        let Some(interface) = self.preferred_network_interface.borrow().clone() else {
+            return Vec::new();
+        };
+        resolved::link_dns(&interface).await.unwrap_or_default()
+    }
+
+    /// Called when the tunnel connects, since traffic has to be captured again right away.
+    pub(super) fn end_captive_portal_bypass(&self) {
+        if self.captive_portal_bypass.lock().unwrap().take().is_some() {
+            tracing::info!(message_id = "Jc9rFv5N", "tunnel connected, ending captive portal bypass");
+        }
+    }
+
+    /// Re-applies the current network config with the changed bypass. Nothing is applied while disconnected, a bypass that is still active applies to the next connection attempt instead.
+    async fn change_captive_portal_bypass(&self, change: impl FnOnce(&mut Option<ActiveBypass>)) -> Result<(), ()> {
+        let current_network_config = self.current_network_config.lock().await;
+        change(&mut self.captive_portal_bypass.lock().unwrap());
+        match &*current_network_config {
+            Ok(Some(network_config)) => self.apply_network_config(network_config).await,
+            Ok(None) | Err(()) => Ok(()),
+        }
+    }
+}
+
+/// Non-loopback nameservers, local stub resolvers forward to the network's servers, which are looked up separately.
+fn nameservers(resolv_conf: &str) -> Vec<IpAddr> {
+    resolv_conf
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver")?.trim().parse::<IpAddr>().ok())
+        .filter(|ip| !ip.is_loopback())
+        .collect()
+}
+
+#[test]
+fn test_nameservers() {
+    let resolv_conf =
+        "# Generated\nnameserver 192.168.1.1\nnameserver 127.0.0.53\n  nameserver   2001:db8::1\nsearch lan\nnameserver fe80::1%wlan0\n";
+    assert_eq!(
+        nameservers(resolv_conf),
+        ["192.168.1.1".parse::<IpAddr>().unwrap(), "2001:db8::1".parse().unwrap()]
+    );
+    assert_eq!(nameservers("nameserver 127.0.0.53\noptions edns0\n"), Vec::<IpAddr>::new());
+}
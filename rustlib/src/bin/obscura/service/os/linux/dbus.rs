@@ -0,0 +1,233 @@
+//! Control API on the D-Bus system bus, see `linux/common/net.obscura.VPN1.conf`. Methods are authorized like commands on the IPC socket, properties and signals are visible to everyone and therefore never contain account details.
+
+use crate::service::os::linux::ipc::{CommandSender, SubscriptionSender, run_json_command, subscribe};
+use crate::service::os::linux::peer_access::Peer;
+use obscuravpn_api::types::OneExit;
+use obscuravpn_client::exit_selection::ExitSelector;
+use obscuravpn_client::manager::{Status, TunnelArgs, VpnStatus};
+use obscuravpn_client::manager_cmd::{ManagerCmd, ManagerCmdErrorCode};
+use obscuravpn_client::manager_event::{EventSubscription, ManagerEvent};
+use tokio::sync::watch;
+use zbus::fdo;
+use zbus::message::Header;
+use zbus::object_server::{InterfaceRef, SignalEmitter};
+
+const BUS_NAME: &str = "net.obscura.VPN1";
+const OBJECT_PATH: &str = "/net/obscura/VPN1";
+
+struct Vpn {
+    commands: CommandSender,
+    /// Latest status with account details removed, unset until the first status event.
+    status: watch::Receiver<Option<Status>>,
+}
+
+#[zbus::interface(name = "net.obscura.VPN1")]
+impl Vpn {
+    /// Connects to the last chosen exit.
+    async fn connect(&self, #[zbus(connection)] connection: &zbus::Connection, #[zbus(header)] header: Header<'_>) -> fdo::Result<()> {
+        let cmd = ManagerCmd::SetTunnelArgs { args: None, active: Some(true) };
+        self.run(connection, &header, cmd).await.map(|_| ())
+    }
+
+    /// Connects to the exit selected by a JSON encoded `ExitSelector`, e.g. `{"country":{"country_code":"us"}}`.
+    async fn connect_to(
+        &self,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(header)] header: Header<'_>,
+        exit_selector: &str,
+    ) -> fdo::Result<()> {
+        let exit: ExitSelector =
+            serde_json::from_str(exit_selector).map_err(|error| fdo::Error::InvalidArgs(format!("invalid exit selector: {error}")))?;
+        let cmd = ManagerCmd::SetTunnelArgs { args: Some(TunnelArgs { exit }), active: Some(true) };
+        self.run(connection, &header, cmd).await.map(|_| ())
+    }
+
+    async fn disconnect(&self, #[zbus(connection)] connection: &zbus::Connection, #[zbus(header)] header: Header<'_>) -> fdo::Result<()> {
+        let cmd = ManagerCmd::SetTunnelArgs { args: None, active: Some(false) };
+        self.run(connection, &header, cmd).await.map(|_| ())
+    }
+
+    /// JSON encoded status. Unlike the `StatusChanged` signal, this includes account details if the caller may see them.
+    async fn get_status(&self, #[zbus(connection)] connection: &zbus::Connection, #[zbus(header)] header: Header<'_>) -> fdo::Result<String> {
+        let status = self.run(connection, &header, ManagerCmd::GetStatus { known_version: None }).await?;
+        Ok(status.to_string())
+    }
+
+    /// Runs a JSON encoded `ManagerCmd` and returns the JSON encoded result, exactly as the IPC socket would.
+    async fn command(
+        &self,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(header)] header: Header<'_>,
+        command: &str,
+    ) -> fdo::Result<String> {
+        let cmd: ManagerCmd = serde_json::from_str(command).map_err(|error| fdo::Error::InvalidArgs(format!("invalid command: {error}")))?;
+        if let ManagerCmd::SubscribeEvents { .. } = cmd {
+            return Err(fdo::Error::NotSupported("use the StatusChanged signal instead".into()));
+        }
+        let response = self.run_json(connection, &header, cmd).await?;
+        String::from_utf8(response).map_err(|error| {
+            tracing::error!(message_id = "Gv5mHc1T", ?error, "command response is not UTF-8: {error}");
+            fdo::Error::Failed("invalid command response".into())
+        })
+    }
+
+    /// One of `disconnected`, `connecting` or `connected`.
+    #[zbus(property)]
+    fn state(&self) -> String {
+        match self.status.borrow().as_ref().map(|status| &status.vpn_status) {
+            None | Some(VpnStatus::Disconnected {}) => "disconnected",
+            Some(VpnStatus::Connecting { .. }) => "connecting",
+            Some(VpnStatus::Connected { .. }) => "connected",
+        }
+        .into()
+    }
+
+    /// Latest error while connecting, empty unless connecting.
+    #[zbus(property)]
+    fn connect_error(&self) -> String {
+        match self.status.borrow().as_ref().map(|status| &status.vpn_status) {
+            Some(VpnStatus::Connecting { connect_error: Some(error), .. }) => <&'static str>::from(error).into(),
+            _ => String::new(),
+        }
+    }
+
+    /// Empty unless connected.
+    #[zbus(property)]
+    fn exit_id(&self) -> String {
+        self.connected_exit(|exit| exit.id.clone())
+    }
+
+    /// Lowercase ISO 3166-1 alpha-2 code, empty unless connected.
+    #[zbus(property)]
+    fn exit_country(&self) -> String {
+        self.connected_exit(|exit| exit.city_code.country_code.0.clone())
+    }
+
+    /// Empty unless connected.
+    #[zbus(property)]
+    fn exit_city(&self) -> String {
+        self.connected_exit(|exit| exit.city_name.clone())
+    }
+
+    #[zbus(property)]
+    fn auto_connect(&self) -> bool {
+        self.status.borrow().as_ref().is_some_and(|status| status.auto_connect)
+    }
+
+    #[zbus(property)]
+    fn local_network_access(&self) -> bool {
+        self.status.borrow().as_ref().is_some_and(|status| status.local_network_access)
+    }
+
+    #[zbus(property)]
+    fn use_system_dns(&self) -> bool {
+        self.status.borrow().as_ref().is_some_and(|status| status.use_system_dns)
+    }
+
+    /// JSON encoded status without account details, emitted whenever the status changes.
+    #[zbus(signal)]
+    async fn status_changed(emitter: &SignalEmitter<'_>, status: &str) -> zbus::Result<()>;
+}
+
+impl Vpn {
+    fn connected_exit(&self, f: impl FnOnce(&OneExit) -> String) -> String {
+        match self.status.borrow().as_ref().map(|status| &status.vpn_status) {
+            Some(VpnStatus::Connected { exit, .. }) => f(exit),
+            _ => String::new(),
+        }
+    }
+
+    /// Runs the command and returns its successful result, errors of the command are returned as `org.freedesktop.DBus.Error.Failed` with the `ManagerCmdErrorCode` as message.
+    async fn run(&self, connection: &zbus::Connection, header: &Header<'_>, cmd: ManagerCmd) -> fdo::Result<serde_json::Value> {
+        let response = self.run_json(connection, header, cmd).await?;
+        let result: Result<serde_json::Value, ManagerCmdErrorCode> = serde_json::from_slice(&response).map_err(|error| {
+            tracing::error!(message_id = "Tz8kWq3N", ?error, "failed to decode command response: {error}");
+            fdo::Error::Failed("invalid command response".into())
+        })?;
+        result.map_err(|code| fdo::Error::Failed(<&'static str>::from(code).into()))
+    }
+
+    async fn run_json(&self, connection: &zbus::Connection, header: &Header<'_>, cmd: ManagerCmd) -> fdo::Result<Vec<u8>> {
+        let Some(sender) = header.sender() else {
+            return Err(fdo::Error::AccessDenied("unknown sender".into()));
+        };
+        let peer = Peer::from_dbus(connection, sender).await;
+        if !peer.authorize(&cmd).await {
+            return Err(fdo::Error::AccessDenied(
+                <&'static str>::from(ManagerCmdErrorCode::InsufficientPermissions).into(),
+            ));
+        }
+        let redact_account = matches!(cmd, ManagerCmd::GetStatus { .. }) && !peer.may_see_account().await;
+        let message = serde_json::to_vec(&cmd).map_err(|error| {
+            tracing::error!(message_id = "Lf4rYd9P", ?error, "failed to encode command: {error}");
+            fdo::Error::Failed("failed to encode command".into())
+        })?;
+        match run_json_command(&self.commands, message, redact_account).await {
+            Ok(Some(response)) => Ok(response),
+            Ok(None) | Err(()) => Err(fdo::Error::Failed("service is shutting down".into())),
+        }
+    }
+}
+
+/// Claims the bus name and keeps properties up to date until the service stops accepting event subscriptions. Failing to claim the bus name is logged, the IPC socket keeps working without it.
+pub async fn serve(commands: CommandSender, subscriptions: SubscriptionSender) {
+    let (status_sender, status) = watch::channel(None);
+    let connection = async {
+        zbus::connection::Builder::system()?
+            .name(BUS_NAME)?
+            .serve_at(OBJECT_PATH, Vpn { commands, status })?
+            .build()
+            .await
+    };
+    let connection = match connection.await {
+        Ok(connection) => connection,
+        Err(error) => {
+            tracing::error!(message_id = "Hn6wBx2Q", ?error, "failed to serve D-Bus API: {error}");
+            return;
+        }
+    };
+    let vpn = match connection.object_server().interface::<_, Vpn>(OBJECT_PATH).await {
+        Ok(vpn) => vpn,
+        Err(error) => {
+            tracing::error!(message_id = "Pc3jTm7V", ?error, "failed to get D-Bus interface: {error}");
+            return;
+        }
+    };
+    tracing::info!(message_id = "Ks9dFv4R", BUS_NAME, "serving D-Bus API");
+
+    let Ok(Some(mut events)) = subscribe(&subscriptions, EventSubscription::default()).await else {
+        return;
+    };
+    while let Some(event) = events.recv().await {
+        let ManagerEvent::Status(mut status) = event else {
+            continue;
+        };
+        status.redact_account();
+        let json = match serde_json::to_string(&status) {
+            Ok(json) => json,
+            Err(error) => {
+                tracing::error!(message_id = "Bq2xNs8L", ?error, "failed to encode status: {error}");
+                continue;
+            }
+        };
+        status_sender.send_replace(Some(status));
+        if let Err(error) = emit_changes(&vpn, &json).await {
+            tracing::error!(message_id = "Wr7hGk5C", ?error, "failed to emit D-Bus signals: {error}");
+        }
+    }
+    tracing::info!(message_id = "Ye4tZp6M", "stop serving D-Bus API");
+}
+
+async fn emit_changes(vpn: &InterfaceRef<Vpn>, status_json: &str) -> zbus::Result<()> {
+    let emitter = vpn.signal_emitter();
+    let vpn = vpn.get().await;
+    vpn.state_changed(emitter).await?;
+    vpn.connect_error_changed(emitter).await?;
+    vpn.exit_id_changed(emitter).await?;
+    vpn.exit_country_changed(emitter).await?;
+    vpn.exit_city_changed(emitter).await?;
+    vpn.auto_connect_changed(emitter).await?;
+    vpn.local_network_access_changed(emitter).await?;
+    vpn.use_system_dns_changed(emitter).await?;
+    Vpn::status_changed(emitter, status_json).await
+}
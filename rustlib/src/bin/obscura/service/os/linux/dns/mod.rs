@@ -1,7 +1,12 @@
+use crate::service::os::linux::dns::resolv_conf::ResolvConf;
 use crate::service::os::linux::network_manager;
 use clap::ValueEnum;
+use obscuravpn_client::net::NetworkInterface;
 
+pub mod networkd;
+pub mod resolv_conf;
 pub mod resolved;
+pub mod watchdog;
 
 #[derive(Debug, Clone, Copy, ValueEnum)]
 pub enum DnsManagerArg {
@@ -9,6 +14,10 @@ pub enum DnsManagerArg {
     Disabled,
     NetworkManager,
     Resolved,
+    /// Configure tun DNS through systemd-networkd, which hands it to systemd-resolved. Requires both to be running and networkd to manage the tun link.
+    Networkd,
+    /// Rewrite /etc/resolv.conf directly. Used as fallback if neither systemd-resolved nor NetworkManager are available.
+    ResolvConf,
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, strum::EnumIs)]
@@ -16,38 +25,77 @@ pub enum DnsManager {
     Disabled,
     Resolved,
     NetworkManager,
+    Networkd,
+    ResolvConf,
 }
 
-pub async fn choose_dns_manager(dns_manager_arg: DnsManagerArg) -> Result<DnsManager, ()> {
-    let network_manager = network_manager::detect().await;
-    let resolved = resolved::detect().await;
+#[derive(Debug, Copy, Clone, Default)]
+struct Detected {
+    network_manager: bool,
+    resolved: bool,
+    networkd: bool,
+    networkd_manages_tun: bool,
+    resolv_conf: bool,
+}
+
+pub async fn choose_dns_manager(dns_manager_arg: DnsManagerArg, resolv_conf: &ResolvConf, tun: &NetworkInterface) -> Result<DnsManager, ()> {
+    let networkd = networkd::detect().await;
+    let detected = Detected {
+        network_manager: network_manager::detect().await,
+        resolved: resolved::detect().await,
+        networkd,
+        networkd_manages_tun: networkd && networkd::manages(tun).await,
+        resolv_conf: resolv_conf.detect(),
+    };
+    let choice = select(dns_manager_arg, detected);
+    match (dns_manager_arg, choice) {
+        (_, Some(_)) => {}
+        (DnsManagerArg::Auto, None) => tracing::error!(message_id = "ltV4egoX", "no supported DNS manager detected"),
+        (dns_manager_arg, None) => {
+            tracing::error!(message_id = "bJO46yTy", ?dns_manager_arg, "requested DNS manager not detected")
+        }
+    }
+    tracing::info!(message_id = "PsaY3ZPO", ?dns_manager_arg, ?detected, ?choice, "DNS manager detection");
+    choice.ok_or(())
+}
 
-    let choice = match dns_manager_arg {
-        DnsManagerArg::Disabled => Ok(DnsManager::Disabled),
+/// Link DNS configured in networkd only takes effect through resolved, but networkd resets resolved link settings of links it manages, so it is preferred if both are running and networkd manages the tun link. Networkd rejects link DNS for unmanaged links, which are configured in resolved directly.
+fn select(dns_manager_arg: DnsManagerArg, detected: Detected) -> Option<DnsManager> {
+    let networkd = detected.networkd && detected.networkd_manages_tun && detected.resolved;
+    match dns_manager_arg {
+        DnsManagerArg::Disabled => Some(DnsManager::Disabled),
         DnsManagerArg::Auto => {
-            if resolved {
-                Ok(DnsManager::Resolved)
-            } else if network_manager {
-                Ok(DnsManager::NetworkManager)
+            if networkd {
+                Some(DnsManager::Networkd)
+            } else if detected.resolved {
+                Some(DnsManager::Resolved)
+            } else if detected.network_manager {
+                Some(DnsManager::NetworkManager)
+            } else if detected.resolv_conf {
+                Some(DnsManager::ResolvConf)
             } else {
-                tracing::error!(message_id = "ltV4egoX", "no supported DNS manager detected");
-                Err(())
+                None
             }
         }
-        DnsManagerArg::NetworkManager if network_manager => Ok(DnsManager::NetworkManager),
-        DnsManagerArg::Resolved if resolved => Ok(DnsManager::Resolved),
-        dns_manager_arg => {
-            tracing::error!(message_id = "bJO46yTy", ?dns_manager_arg, "requested DNS manager not detected");
-            Err(())
-        }
-    };
-    tracing::info!(
-        message_id = "PsaY3ZPO",
-        ?dns_manager_arg,
-        network_manager,
-        resolved,
-        ?choice,
-        "DNS manager detection"
-    );
-    choice
+        DnsManagerArg::NetworkManager => detected.network_manager.then_some(DnsManager::NetworkManager),
+        DnsManagerArg::Resolved => detected.resolved.then_some(DnsManager::Resolved),
+        DnsManagerArg::Networkd => networkd.then_some(DnsManager::Networkd),
+        DnsManagerArg::ResolvConf => detected.resolv_conf.then_some(DnsManager::ResolvConf),
+    }
+}
+
+#[test]
+fn test_select() {
+    let all = Detected { network_manager: true, resolved: true, networkd: true, networkd_manages_tun: true, resolv_conf: true };
+    assert_eq!(select(DnsManagerArg::Auto, all), Some(DnsManager::Networkd));
+    let unmanaged_tun = Detected { networkd_manages_tun: false, ..all };
+    assert_eq!(select(DnsManagerArg::Auto, unmanaged_tun), Some(DnsManager::Resolved));
+    assert_eq!(select(DnsManagerArg::Networkd, unmanaged_tun), None);
+    let networkd_only = Detected { networkd: true, resolv_conf: true, ..Default::default() };
+    assert_eq!(select(DnsManagerArg::Auto, networkd_only), Some(DnsManager::ResolvConf));
+    assert_eq!(select(DnsManagerArg::Networkd, networkd_only), None);
+    let network_manager = Detected { network_manager: true, resolv_conf: true, ..Default::default() };
+    assert_eq!(select(DnsManagerArg::Auto, network_manager), Some(DnsManager::NetworkManager));
+    assert_eq!(select(DnsManagerArg::Auto, Detected::default()), None);
+    assert_eq!(select(DnsManagerArg::Disabled, Detected::default()), Some(DnsManager::Disabled));
 }
@@ -0,0 +1,193 @@
+//! DNS configuration through systemd-networkd, for systems where networkd manages the tun link and would otherwise reset link DNS settings configured in resolved. Networkd passes link DNS on to resolved, so this is only used while resolved is running.
+//!
+//! Networkd reconfiguring links (e.g. `networkctl reload`) or restarting drops these settings, the DNS watchdog re-applies them.
+
+use obscuravpn_client::net::NetworkInterface;
+use std::collections::BTreeSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+use tokio::time::sleep;
+
+pub const SERVICE: &str = "org.freedesktop.network1";
+
+/// Networkd decides whether it manages a new link shortly after the link appears.
+const SETUP_STATE_POLL_INTERVAL: Duration = Duration::from_millis(200);
+const SETUP_STATE_POLL_ATTEMPTS: usize = 10;
+
+/// See https://www.freedesktop.org/software/systemd/man/latest/org.freedesktop.network1.html
+#[zbus::proxy(
+    interface = "org.freedesktop.network1.Manager",
+    default_service = "org.freedesktop.network1",
+    default_path = "/org/freedesktop/network1"
+)]
+trait Manager {
+    #[zbus(name = "SetLinkDNS")]
+    fn set_link_dns(&self, ifindex: i32, addresses: Vec<(i32, Vec<u8>)>) -> zbus::Result<()>;
+    fn set_link_domains(&self, ifindex: i32, domains: Vec<(String, bool)>) -> zbus::Result<()>;
+    #[zbus(name = "RevertLinkDNS")]
+    fn revert_link_dns(&self, ifindex: i32) -> zbus::Result<()>;
+    fn describe_link(&self, ifindex: i32) -> zbus::Result<String>;
+    #[zbus(property)]
+    fn operational_state(&self) -> zbus::Result<String>;
+}
+
+async fn zbus_connect() -> Result<ManagerProxy<'static>, ()> {
+    let conn = zbus::Connection::system()
+        .await
+        .map_err(|error| tracing::error!(message_id = "Zr4kTm7W", ?error, "failed to create DBUS system connection: {}", error))?;
+    ManagerProxy::new(&conn)
+        .await
+        .map_err(|error| tracing::error!(message_id = "Qd8nVx2L", ?error, "failed to create networkd zbus proxy: {}", error))
+        .map(|proxy| proxy.to_owned())
+}
+
+// Returns true if networkd is running
+pub async fn detect() -> bool {
+    let Ok(proxy) = zbus_connect().await else {
+        return false;
+    };
+    match proxy.operational_state().await {
+        Ok(state) => {
+            tracing::info!(message_id = "Hb6wRc3N", state, "networkd is running");
+            true
+        }
+        Err(error) => {
+            tracing::info!(message_id = "Ty2jLp9S", ?error, "networkd is not available: {}", error);
+            false
+        }
+    }
+}
+
+/// Returns true if networkd manages the tun link, i.e. a `.network` file matches it. Networkd rejects link DNS for links it doesn't manage, and doesn't reset resolved link settings of those either.
+pub async fn manages(tun: &NetworkInterface) -> bool {
+    let Ok(proxy) = zbus_connect().await else {
+        return false;
+    };
+    for attempt in 1..=SETUP_STATE_POLL_ATTEMPTS {
+        let setup_state = match proxy.describe_link(tun.index.into()).await {
+            Ok(description) => parse_setup_state(&description),
+            Err(error) => {
+                tracing::info!(message_id = "Gv7rNc2K", ?error, "failed to describe tun link: {}", error);
+                None
+            }
+        };
+        match setup_state.as_deref() {
+            Some("pending" | "initialized") | None if attempt < SETUP_STATE_POLL_ATTEMPTS => sleep(SETUP_STATE_POLL_INTERVAL).await,
+            setup_state => {
+                let managed = !matches!(setup_state, Some("unmanaged" | "pending" | "initialized") | None);
+                tracing::info!(message_id = "Wz3hQs8M", setup_state, managed, "networkd tun link setup state");
+                return managed;
+            }
+        }
+    }
+    false
+}
+
+pub async fn reset_dns(tun: &NetworkInterface) -> Result<(), ()> {
+    zbus_connect()
+        .await?
+        .revert_link_dns(tun.index.into())
+        .await
+        .map_err(|error| tracing::error!(message_id = "Cs5hXq8D", ?error, "failed to revert networkd DNS: {}", error))
+}
+
+pub async fn set_dns(tun: &NetworkInterface, dns: &[IpAddr]) -> Result<(), ()> {
+    let proxy = zbus_connect().await?;
+    let addresses = dns
+        .iter()
+        .map(|entry| match entry {
+            IpAddr::V4(entry) => (libc::AF_INET, entry.octets().to_vec()),
+            IpAddr::V6(entry) => (libc::AF_INET6, entry.octets().to_vec()),
+        })
+        .collect();
+    // Equivalent to `networkctl` applying `DNS=<DNS IP>` to the tun link
+    proxy
+        .set_link_dns(tun.index.into(), addresses)
+        .await
+        .map_err(|error| tracing::error!(message_id = "Mw3gFk6R", ?error, "failed to set networkd tun DNS IPs: {}", error))?;
+    // Routing-only domain `~.`, so all queries use the tun DNS servers.
+    proxy
+        .set_link_domains(tun.index.into(), vec![(".".to_string(), true)])
+        .await
+        .map_err(|error| tracing::error!(message_id = "Jn7vPb4Y", ?error, "failed to set networkd tun DNS domain: {}", error))?;
+    Ok(())
+}
+
+/// Returns true if the tun link DNS servers differ from `dns`.
+pub async fn dns_dirty(tun: &NetworkInterface, dns: &[IpAddr]) -> Result<bool, ()> {
+    let description = zbus_connect()
+        .await?
+        .describe_link(tun.index.into())
+        .await
+        .map_err(|error| tracing::error!(message_id = "Sd5kJv2X", ?error, "failed to describe tun link: {}", error))?;
+    let want: BTreeSet<IpAddr> = dns.iter().copied().collect();
+    Ok(want != parse_link_dns(&description))
+}
+
+/// Extracts DNS server IPs from the JSON returned by `DescribeLink`, e.g. `{"DNS": [{"Family": 2, "Address": [10, 64, 0, 1], ...}], ...}`.
+fn parse_link_dns(description: &str) -> BTreeSet<IpAddr> {
+    #[derive(serde::Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct Link {
+        #[serde(default, rename = "DNS")]
+        dns: Vec<Dns>,
+    }
+    #[derive(serde::Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct Dns {
+        address: Vec<u8>,
+    }
+    let link: Link = match serde_json::from_str(description) {
+        Ok(link) => link,
+        Err(error) => {
+            tracing::warn!(message_id = "Kt9fBw6P", ?error, "failed to parse tun link description: {}", error);
+            return BTreeSet::new();
+        }
+    };
+    link.dns
+        .into_iter()
+        .filter_map(|dns| match dns.address.len() {
+            4 => <[u8; 4]>::try_from(dns.address).ok().map(|octets| Ipv4Addr::from(octets).into()),
+            16 => <[u8; 16]>::try_from(dns.address).ok().map(|octets| Ipv6Addr::from(octets).into()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Extracts the setup state from the JSON returned by `DescribeLink`, e.g. `{"SetupState": "unmanaged", ...}`.
+fn parse_setup_state(description: &str) -> Option<String> {
+    #[derive(serde::Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct Link {
+        setup_state: Option<String>,
+    }
+    match serde_json::from_str::<Link>(description) {
+        Ok(link) => link.setup_state,
+        Err(error) => {
+            tracing::warn!(message_id = "Ej4mYd9T", ?error, "failed to parse tun link description: {}", error);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_link_dns() {
+        let description = r#"{"Index": 5, "Name": "obscuravpn", "DNS": [{"Family": 2, "Address": [10, 64, 0, 1], "ConfigSource": "runtime"}, {"Family": 10, "Address": [253, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]}]}"#;
+        let expected: BTreeSet<IpAddr> = ["10.64.0.1".parse().unwrap(), "fd00::1".parse().unwrap()].into();
+        assert_eq!(parse_link_dns(description), expected);
+        assert!(parse_link_dns(r#"{"Index": 5}"#).is_empty());
+        assert!(parse_link_dns("not json").is_empty());
+    }
+
+    #[test]
+    fn test_parse_setup_state() {
+        let description = r#"{"Index": 5, "Name": "obscuravpn", "SetupState": "unmanaged", "OperationalState": "routable"}"#;
+        assert_eq!(parse_setup_state(description).as_deref(), Some("unmanaged"));
+        assert_eq!(parse_setup_state(r#"{"Index": 5}"#), None);
+        assert_eq!(parse_setup_state("not json"), None);
+    }
+}
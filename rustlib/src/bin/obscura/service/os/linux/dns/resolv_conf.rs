@@ -0,0 +1,298 @@
+//! `/etc/resolv.conf` management for systems without systemd-resolved or NetworkManager.
+//!
+//! If a `resolvconf` implementation (openresolv or Debian's resolvconf) is installed, DNS is registered as a record for the tun interface, so it composes with other users of the tool. Otherwise resolv.conf is rewritten directly. Before the first rewrite the original file (or symlink) is recorded in a state file in the runtime directory, which survives service restarts. The original is restored on disconnect, on service shutdown and, after a crash, on the next fresh service start.
+
+use crate::service::os::linux::tun::TUN_NAME;
+use nix::unistd::{AccessFlags, access};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::net::IpAddr;
+use std::os::unix::fs::{PermissionsExt, symlink};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use tempfile::NamedTempFile;
+
+pub const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+const STATE_FILE: &str = "resolv-conf-backup.json";
+const HEADER: &str = "# Generated by Obscura VPN. The original file is restored on disconnect.\n";
+const RESOLVCONF_DIRS: &[&str] = &["/usr/sbin", "/sbin", "/usr/bin", "/bin"];
+
+#[serde_with::serde_as]
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", rename_all_fields = "camelCase")]
+enum Original {
+    Missing,
+    Symlink {
+        target: PathBuf,
+    },
+    File {
+        #[serde_as(as = "serde_with::base64::Base64")]
+        contents: Vec<u8>,
+        mode: u32,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Backend {
+    /// A `resolvconf` executable, which isn't systemd's `resolvectl` compatibility link.
+    Resolvconf(PathBuf),
+    /// Rewrite resolv.conf, keeping the backup in the state file.
+    Direct { state_path: PathBuf },
+}
+
+pub struct ResolvConf {
+    path: PathBuf,
+    backend: Option<Backend>,
+}
+
+impl ResolvConf {
+    pub fn new(runtime_dir: Option<&str>) -> Self {
+        let state_path = runtime_dir.map(|dir| Path::new(dir).join(STATE_FILE));
+        Self::with_paths(RESOLV_CONF_PATH.into(), state_path, find_resolvconf())
+    }
+
+    fn with_paths(path: PathBuf, state_path: Option<PathBuf>, resolvconf: Option<PathBuf>) -> Self {
+        let backend = match (resolvconf, state_path) {
+            (Some(resolvconf), _) => Some(Backend::Resolvconf(resolvconf)),
+            (None, Some(state_path)) if can_rewrite(&path, &state_path) => Some(Backend::Direct { state_path }),
+            (None, _) => None,
+        };
+        Self { path, backend }
+    }
+
+    /// Returns true if resolv.conf can be managed, either through `resolvconf` or by rewriting it while keeping a backup.
+    pub fn detect(&self) -> bool {
+        tracing::info!(message_id = "qT4nVb8X", backend = ?self.backend, "resolv.conf DNS management availability");
+        self.backend.is_some()
+    }
+
+    /// Restores the original resolv.conf if a previous service instance exited without doing so. Must not be called on seamless restarts, where the previous instance's configuration is still in use and reset later.
+    pub fn recover(&self) {
+        match &self.backend {
+            Some(Backend::Resolvconf(resolvconf)) => {
+                // Fails if there is no record, which is the common case.
+                let _ = run_resolvconf(resolvconf, "-d", None);
+            }
+            Some(Backend::Direct { state_path }) if state_path.exists() => {
+                tracing::warn!(
+                    message_id = "mJ7cXw2P",
+                    "found resolv.conf backup from previous service instance, restoring"
+                );
+                let _ = self.reset_dns();
+            }
+            Some(Backend::Direct { .. }) | None => {}
+        }
+    }
+
+    pub fn set_dns(&self, dns: &[IpAddr]) -> Result<(), ()> {
+        let contents = render(dns);
+        match &self.backend {
+            Some(Backend::Resolvconf(resolvconf)) => run_resolvconf(resolvconf, "-a", Some(contents.as_bytes()))?,
+            Some(Backend::Direct { state_path }) => {
+                if !state_path.exists() {
+                    let original = read_original(&self.path)?;
+                    tracing::info!(message_id = "nC6vRt3L", ?original, "backing up resolv.conf");
+                    let json = serde_json::to_vec(&original)
+                        .map_err(|error| tracing::error!(message_id = "gK8wZp4D", ?error, "failed to encode resolv.conf backup: {error}"))?;
+                    write_atomic(state_path, &json, 0o600)?;
+                }
+                write_atomic(&self.path, contents.as_bytes(), 0o644)?;
+            }
+            None => {
+                tracing::error!(message_id = "Hs3kWq9N", "resolv.conf can't be managed on this system");
+                return Err(());
+            }
+        }
+        tracing::info!(message_id = "eX2mYq7B", ?dns, "rewrote resolv.conf");
+        Ok(())
+    }
+
+    pub fn reset_dns(&self) -> Result<(), ()> {
+        let state_path = match &self.backend {
+            Some(Backend::Resolvconf(resolvconf)) => return run_resolvconf(resolvconf, "-d", None),
+            Some(Backend::Direct { state_path }) => state_path,
+            None => return Ok(()),
+        };
+        let json = match fs::read(state_path) {
+            Ok(json) => json,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(error) => {
+                tracing::error!(message_id = "Wb5tNd1K", ?error, "failed to read resolv.conf backup: {error}");
+                return Err(());
+            }
+        };
+        match serde_json::from_slice::<Original>(&json) {
+            Ok(original) => {
+                if is_ours(&self.path)? {
+                    restore(&self.path, &original)?;
+                    tracing::info!(message_id = "Lr8sJf3Q", ?original, "restored resolv.conf");
+                } else {
+                    // Something else (e.g. a DHCP client) replaced our file since, its version is more current than our backup.
+                    tracing::warn!(message_id = "Pz4gHm6C", "resolv.conf was changed by another program, discarding backup");
+                }
+            }
+            Err(error) => tracing::error!(message_id = "Fy6qBc2T", ?error, "failed to parse resolv.conf backup, discarding: {error}"),
+        }
+        fs::remove_file(state_path).map_err(|error| tracing::error!(message_id = "Ud9xKv5M", ?error, "failed to remove resolv.conf backup: {error}"))
+    }
+}
+
+fn render(dns: &[IpAddr]) -> String {
+    let mut contents = HEADER.to_owned();
+    for ip in dns {
+        contents += &format!("nameserver {ip}\n");
+    }
+    contents
+}
+
+/// systemd ships `resolvconf` as a link to `resolvectl`, which only works while resolved is running, in which case resolved is used directly.
+fn find_resolvconf() -> Option<PathBuf> {
+    RESOLVCONF_DIRS
+        .iter()
+        .map(|dir| Path::new(dir).join("resolvconf"))
+        .find(|path| fs::canonicalize(path).is_ok_and(|target| target.is_file() && target.file_name().is_some_and(|name| name != "resolvectl")))
+}
+
+/// The backup must be kept until the original is restored, and both resolv.conf and the state file are replaced by renaming a temporary file into their directories.
+fn can_rewrite(path: &Path, state_path: &Path) -> bool {
+    let writable = |dir: Option<&Path>| dir.is_some_and(|dir| dir.is_dir() && access(dir, AccessFlags::W_OK).is_ok());
+    writable(path.parent()) && writable(state_path.parent())
+}
+
+fn run_resolvconf(resolvconf: &Path, action: &str, stdin: Option<&[u8]>) -> Result<(), ()> {
+    let mut child = Command::new(resolvconf)
+        .args([action, TUN_NAME])
+        .stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|error| tracing::error!(message_id = "Xe5rTg2M", ?error, ?resolvconf, "failed to run resolvconf: {error}"))?;
+    if let Some(stdin) = stdin
+        && let Some(mut pipe) = child.stdin.take()
+    {
+        pipe.write_all(stdin)
+            .map_err(|error| tracing::error!(message_id = "Cw8kLn4H", ?error, "failed to write to resolvconf: {error}"))?;
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|error| tracing::error!(message_id = "Qs2vMb7P", ?error, "failed to wait for resolvconf: {error}"))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        tracing::error!(message_id = "Dm9hWc3Z", action, status =% output.status, %stderr, "resolvconf failed");
+        return Err(());
+    }
+    Ok(())
+}
+
+fn read_original(path: &Path) -> Result<Original, ()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Original::Missing),
+        Err(error) => {
+            tracing::error!(message_id = "Aq3pLw8V", ?error, "failed to stat resolv.conf: {error}");
+            return Err(());
+        }
+    };
+    if metadata.is_symlink() {
+        let target =
+            fs::read_link(path).map_err(|error| tracing::error!(message_id = "Kc7nSx4R", ?error, "failed to read resolv.conf symlink: {error}"))?;
+        return Ok(Original::Symlink { target });
+    }
+    let contents = fs::read(path).map_err(|error| tracing::error!(message_id = "Tv2bGh9J", ?error, "failed to read resolv.conf: {error}"))?;
+    Ok(Original::File { contents, mode: metadata.permissions().mode() & 0o7777 })
+}
+
+fn is_ours(path: &Path) -> Result<bool, ()> {
+    match fs::read(path) {
+        Ok(contents) => Ok(contents.starts_with(HEADER.as_bytes())),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(false),
+        Err(error) => {
+            tracing::error!(message_id = "Rk5wDp2F", ?error, "failed to read resolv.conf: {error}");
+            Err(())
+        }
+    }
+}
+
+fn restore(path: &Path, original: &Original) -> Result<(), ()> {
+    match original {
+        Original::Missing => fs::remove_file(path)
+            .or_else(|error| if error.kind() == ErrorKind::NotFound { Ok(()) } else { Err(error) })
+            .map_err(|error| tracing::error!(message_id = "Nf8jCq3W", ?error, "failed to remove resolv.conf: {error}")),
+        Original::Symlink { target } => {
+            // Renaming a fresh symlink over the file replaces it atomically.
+            let temp_path = path.with_file_name(".resolv.conf.obscura");
+            let _ = fs::remove_file(&temp_path);
+            symlink(target, &temp_path)
+                .map_err(|error| tracing::error!(message_id = "Yx4mTb7K", ?error, "failed to create resolv.conf symlink: {error}"))?;
+            fs::rename(&temp_path, path)
+                .map_err(|error| tracing::error!(message_id = "Gd6vNw1S", ?error, "failed to replace resolv.conf with symlink: {error}"))
+        }
+        Original::File { contents, mode } => write_atomic(path, contents, *mode),
+    }
+}
+
+fn write_atomic(path: &Path, contents: &[u8], mode: u32) -> Result<(), ()> {
+    let dir = path.parent().unwrap_or(Path::new("/"));
+    let mut file = NamedTempFile::new_in(dir)
+        .map_err(|error| tracing::error!(message_id = "Jp3sXk8H", ?error, ?path, "failed to create temporary file: {error}"))?;
+    file.write_all(contents)
+        .and_then(|()| file.as_file().set_permissions(fs::Permissions::from_mode(mode)))
+        .and_then(|()| file.as_file().sync_data())
+        .map_err(|error| tracing::error!(message_id = "Bw7qFn5D", ?error, ?path, "failed to write temporary file: {error}"))?;
+    file.persist(path)
+        .map_err(|error| tracing::error!(message_id = "Vm2cRz6T", ?error, ?path, "failed to persist temporary file: {error}"))?;
+    Ok(())
+}
+
+#[test]
+fn test_render() {
+    let dns = ["10.64.0.1".parse().unwrap(), "fd00::1".parse().unwrap()];
+    assert_eq!(render(&dns), format!("{HEADER}nameserver 10.64.0.1\nnameserver fd00::1\n"));
+}
+
+#[test]
+fn test_detect() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("resolv.conf");
+    let state_path = dir.path().join(STATE_FILE);
+    assert!(ResolvConf::with_paths(path.clone(), Some(state_path.clone()), None).detect());
+    assert!(!ResolvConf::with_paths(path.clone(), None, None).detect());
+    assert!(!ResolvConf::with_paths(path.clone(), Some(dir.path().join("missing").join(STATE_FILE)), None).detect());
+    let resolvconf = ResolvConf::with_paths(path, None, Some("/usr/sbin/resolvconf".into()));
+    assert_eq!(resolvconf.backend, Some(Backend::Resolvconf("/usr/sbin/resolvconf".into())));
+}
+
+#[test]
+fn test_set_and_reset_dns() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("resolv.conf");
+    let state_path = dir.path().join(STATE_FILE);
+    fs::write(&path, "nameserver 192.168.1.1\n").unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+    let resolv_conf = ResolvConf::with_paths(path.clone(), Some(state_path.clone()), None);
+
+    resolv_conf.set_dns(&["10.64.0.1".parse().unwrap()]).unwrap();
+    assert!(is_ours(&path).unwrap());
+    // A second rewrite must keep the original backup.
+    resolv_conf.set_dns(&["10.64.0.2".parse().unwrap()]).unwrap();
+    let backup: Original = serde_json::from_slice(&fs::read(&state_path).unwrap()).unwrap();
+    assert_eq!(backup, Original::File { contents: b"nameserver 192.168.1.1\n".to_vec(), mode: 0o644 });
+
+    resolv_conf.reset_dns().unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "nameserver 192.168.1.1\n");
+    assert!(!state_path.exists());
+}
+
+#[test]
+fn test_recover_restores_symlink() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("resolv.conf");
+    symlink("/run/example/resolv.conf", &path).unwrap();
+    let resolv_conf = ResolvConf::with_paths(path.clone(), Some(dir.path().join(STATE_FILE)), None);
+    resolv_conf.set_dns(&["10.64.0.1".parse().unwrap()]).unwrap();
+    assert!(!fs::symlink_metadata(&path).unwrap().is_symlink());
+
+    resolv_conf.recover();
+    assert_eq!(fs::read_link(&path).unwrap(), Path::new("/run/example/resolv.conf"));
+}
@@ -1,7 +1,19 @@
 use obscuravpn_client::net::NetworkInterface;
-use std::net::IpAddr;
+use std::collections::BTreeSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use zbus_systemd::zbus;
 
+pub const SERVICE: &str = "org.freedesktop.resolve1";
+
+/// See https://www.freedesktop.org/software/systemd/man/latest/org.freedesktop.resolve1.html
+#[zbus::proxy(interface = "org.freedesktop.resolve1.Link", default_service = "org.freedesktop.resolve1")]
+trait Link {
+    #[zbus(property, name = "DNS")]
+    fn dns(&self) -> zbus::Result<Vec<(i32, Vec<u8>)>>;
+    #[zbus(property)]
+    fn domains(&self) -> zbus::Result<Vec<(String, bool)>>;
+}
+
 async fn zbus_connect() -> Result<zbus_systemd::resolve1::ManagerProxy<'static>, ()> {
     let conn = zbus::Connection::system()
         .await
@@ -58,3 +70,60 @@ pub async fn reset_dns(tun: &NetworkInterface) -> Result<(), ()> {
         .await
         .map_err(|error| tracing::error!(message_id = "MV4oVXSy", ?error, "failed to revert DNS: {}", error))
 }
+
+/// Returns true if the tun link DNS servers differ from `dns` or the routing domain got lost.
+pub async fn dns_dirty(tun: &NetworkInterface, dns: &[IpAddr]) -> Result<bool, ()> {
+    let proxy = zbus_connect().await?;
+    let link_path = proxy
+        .get_link(tun.index.into())
+        .await
+        .map_err(|error| tracing::error!(message_id = "Uz6mCk2Q", ?error, "failed to get resolved tun link: {}", error))?;
+    let link = LinkProxy::builder(proxy.inner().connection())
+        .path(link_path)
+        .map_err(|error| tracing::error!(message_id = "Rf3vXn8B", ?error, "invalid resolved link path: {}", error))?
+        .cache_properties(zbus::proxy::CacheProperties::No)
+        .build()
+        .await
+        .map_err(|error| tracing::error!(message_id = "Hk7pWd4M", ?error, "failed to create resolved link proxy: {}", error))?;
+    let have_dns: BTreeSet<IpAddr> = dns_addresses(
+        link.dns()
+            .await
+            .map_err(|error| tracing::error!(message_id = "Jw2sTq6N", ?error, "failed to get resolved tun DNS: {}", error))?,
+    )
+    .collect();
+    let domains = link
+        .domains()
+        .await
+        .map_err(|error| tracing::error!(message_id = "Xb9cLr3F", ?error, "failed to get resolved tun DNS domains: {}", error))?;
+    let want_dns: BTreeSet<IpAddr> = dns.iter().copied().collect();
+    Ok(have_dns != want_dns || !domains.contains(&(".".to_string(), true)))
+}
+
+/// DNS servers resolved uses for the link, e.g. the network's resolvers for a physical interface.
+pub async fn link_dns(interface: &NetworkInterface) -> Result<Vec<IpAddr>, ()> {
+    let proxy = zbus_connect().await?;
+    let link_path = proxy
+        .get_link(interface.index.into())
+        .await
+        .map_err(|error| tracing::error!(message_id = "Cq4nVw7R", ?error, "failed to get resolved link: {}", error))?;
+    let link = LinkProxy::builder(proxy.inner().connection())
+        .path(link_path)
+        .map_err(|error| tracing::error!(message_id = "Kd8tZm3X", ?error, "invalid resolved link path: {}", error))?
+        .cache_properties(zbus::proxy::CacheProperties::No)
+        .build()
+        .await
+        .map_err(|error| tracing::error!(message_id = "Ph2wGs6L", ?error, "failed to create resolved link proxy: {}", error))?;
+    let dns = link
+        .dns()
+        .await
+        .map_err(|error| tracing::error!(message_id = "Yv5cRb9J", ?error, "failed to get resolved link DNS: {}", error))?;
+    Ok(dns_addresses(dns).collect())
+}
+
+fn dns_addresses(dns: Vec<(i32, Vec<u8>)>) -> impl Iterator<Item = IpAddr> {
+    dns.into_iter().filter_map(|(_family, address)| match address.len() {
+        4 => <[u8; 4]>::try_from(address).ok().map(|octets| Ipv4Addr::from(octets).into()),
+        16 => <[u8; 16]>::try_from(address).ok().map(|octets| Ipv6Addr::from(octets).into()),
+        _ => None,
+    })
+}
@@ -0,0 +1,247 @@
+//! DNS settings are applied once per `set_os_network_config`, but the DNS manager or other tools may rewrite the tun link DNS servers or routing domain afterwards (e.g. NetworkManager reapplying a connection, `networkctl reload`, a resolved restart), silently moving DNS out of the tunnel. While DNS is configured, the watchdog subscribes to D-Bus changes of the active DNS manager and re-applies our settings if they were lost, similar to the route enforcer. Interventions are counted in debug info.
+
+use crate::service::os::linux::dns::{DnsManager, networkd, resolved};
+use crate::service::os::linux::network_manager;
+use futures::stream::BoxStream;
+use futures::{FutureExt, StreamExt};
+use obscuravpn_client::manager::Manager;
+use obscuravpn_client::net::NetworkInterface;
+use obscuravpn_client::network_config::OsNetworkConfig;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::select;
+use tokio::sync::watch::{Receiver, Sender, channel};
+use tokio::time::sleep;
+
+const DNS_WATCHDOG_ERROR_BACKOFF: Duration = Duration::from_secs(1);
+const DNS_WATCHDOG_APPLY_COOLDOWN: Duration = Duration::from_secs(1);
+/// Not every property is guaranteed to emit change signals, so check periodically as well.
+const DNS_WATCHDOG_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug)]
+pub struct DnsTarget {
+    pub dns_manager: DnsManager,
+    pub network_config: OsNetworkConfig,
+}
+
+pub struct DnsWatchdog {
+    /// Send `Some` after successfully applying DNS settings and `None` before reverting them, the watchdog only repairs.
+    pub target: Sender<Option<DnsTarget>>,
+    /// Number of times DNS settings were re-applied since the service started.
+    pub interventions: Receiver<u64>,
+}
+
+pub fn spawn_dns_watchdog(tun: NetworkInterface) -> DnsWatchdog {
+    let (sender, mut receiver) = channel(None);
+    let (interventions_sender, interventions) = channel(0);
+    tokio::spawn(async move {
+        let mut desired = receiver.clone();
+        loop {
+            select! {
+                _ = async { let _ = receiver.wait_for(|_| false).await; } => {
+                    tracing::warn!(message_id = "Ks4wPd7N", "DNS watchdog sender dropped/closed");
+                    return;
+                }
+                result = watch_dns(&tun, &mut desired, &interventions_sender) => if result.is_err() {
+                    sleep(DNS_WATCHDOG_ERROR_BACKOFF).await
+                },
+            }
+        }
+    });
+    DnsWatchdog { target: sender, interventions }
+}
+
+/// Keeps the manager's intervention count, which is reported in debug info, up to date.
+pub async fn report_dns_watchdog_interventions(manager: Arc<Manager>, mut interventions: Receiver<u64>) {
+    while interventions.changed().await.is_ok() {
+        manager.set_dns_watchdog_interventions(*interventions.borrow_and_update());
+    }
+}
+
+/// Watches the DNS manager of the current target. Returns `Ok` if the DNS manager changed, so the caller can resubscribe.
+async fn watch_dns(tun: &NetworkInterface, desired: &mut Receiver<Option<DnsTarget>>, interventions: &Sender<u64>) -> Result<(), ()> {
+    // Don't touch DBUS until DNS is actually configured.
+    let dns_manager = match &*desired.wait_for(Option::is_some).await.map_err(|_| ())? {
+        Some(target) => target.dns_manager,
+        None => return Ok(()),
+    };
+    let signals = subscribe(tun, dns_manager).await?;
+    repair_dns(
+        dns_manager,
+        desired,
+        signals,
+        interventions,
+        async |target| dns_dirty(tun, target).await,
+        async |target| apply(tun, target).await,
+    )
+    .await
+}
+
+/// Re-applies the target whenever `dirty` reports it was changed, checking after every signal and periodically.
+async fn repair_dns(
+    dns_manager: DnsManager,
+    desired: &mut Receiver<Option<DnsTarget>>,
+    mut signals: BoxStream<'static, ()>,
+    interventions: &Sender<u64>,
+    mut dirty: impl AsyncFnMut(&DnsTarget) -> Result<bool, ()>,
+    mut apply: impl AsyncFnMut(&DnsTarget) -> Result<(), ()>,
+) -> Result<(), ()> {
+    loop {
+        while let Some(Some(_)) = signals.next().now_or_never() {}
+        let target = desired.borrow_and_update().clone();
+        if let Some(target) = target {
+            if target.dns_manager != dns_manager {
+                return Ok(());
+            }
+            if dirty(&target).await? {
+                interventions.send_modify(|interventions| *interventions += 1);
+                let interventions = *interventions.borrow();
+                tracing::warn!(
+                    message_id = "Bh6tXc2R",
+                    ?dns_manager,
+                    interventions,
+                    "tun DNS settings were changed, re-applying"
+                );
+                apply(&target).await?;
+                sleep(DNS_WATCHDOG_APPLY_COOLDOWN).await;
+                continue;
+            }
+        }
+        select! {
+            Ok(()) = desired.changed() => {}
+            signal = signals.next() => {
+                if signal.is_none() {
+                    tracing::error!(message_id = "Wq9mFj5L", "DNS watchdog signal stream closed");
+                    return Err(());
+                }
+            }
+            () = sleep(DNS_WATCHDOG_POLL_INTERVAL) => {}
+        }
+    }
+}
+
+/// Subscribes to signals of the DNS manager's D-Bus service, including restarts of the service.
+async fn subscribe(tun: &NetworkInterface, dns_manager: DnsManager) -> Result<BoxStream<'static, ()>, ()> {
+    let (service, path) = match dns_manager {
+        DnsManager::Resolved => (resolved::SERVICE, None),
+        DnsManager::Networkd => (networkd::SERVICE, None),
+        DnsManager::NetworkManager => (network_manager::SERVICE, Some(network_manager::device_path(tun).await?)),
+        DnsManager::Disabled | DnsManager::ResolvConf => return Ok(futures::stream::pending().boxed()),
+    };
+    let conn = zbus::Connection::system()
+        .await
+        .map_err(|error| tracing::error!(message_id = "Zp5nRw3V", ?error, "failed to create DBUS system connection: {}", error))?;
+    let mut changes_rule = zbus::MatchRule::builder().msg_type(zbus::message::Type::Signal).sender(service);
+    if let Some(path) = path {
+        changes_rule = changes_rule.and_then(|builder| builder.path(path));
+    }
+    let changes_rule = changes_rule
+        .map(|builder| builder.build())
+        .map_err(|error| tracing::error!(message_id = "Tn3bVy8C", ?error, "failed to build DNS watchdog match rule: {}", error))?;
+    let owner_rule = zbus::MatchRule::builder()
+        .msg_type(zbus::message::Type::Signal)
+        .sender("org.freedesktop.DBus")
+        .and_then(|builder| builder.member("NameOwnerChanged"))
+        .and_then(|builder| builder.arg(0, service))
+        .map(|builder| builder.build())
+        .map_err(|error| {
+            tracing::error!(
+                message_id = "Mc7rQe4G",
+                ?error,
+                "failed to build DNS watchdog owner match rule: {}",
+                error
+            )
+        })?;
+    let changes = zbus::MessageStream::for_match_rule(changes_rule, &conn, None)
+        .await
+        .map_err(|error| tracing::error!(message_id = "Ej2xKs6P", ?error, "failed to subscribe to DNS manager changes: {}", error))?;
+    let owner_changes = zbus::MessageStream::for_match_rule(owner_rule, &conn, None).await.map_err(|error| {
+        tracing::error!(
+            message_id = "Af5nUw9D",
+            ?error,
+            "failed to subscribe to DNS manager owner changes: {}",
+            error
+        )
+    })?;
+    tracing::info!(message_id = "Yt4vMb9S", ?dns_manager, "DNS watchdog subscribed to DNS manager changes");
+    Ok(futures::stream::select(changes, owner_changes).map(|_| ()).boxed())
+}
+
+async fn dns_dirty(tun: &NetworkInterface, target: &DnsTarget) -> Result<bool, ()> {
+    let dns = &target.network_config.dns;
+    match target.dns_manager {
+        DnsManager::Resolved => resolved::dns_dirty(tun, dns).await,
+        DnsManager::Networkd => networkd::dns_dirty(tun, dns).await,
+        DnsManager::NetworkManager => network_manager::dns_dirty(tun, &target.network_config).await,
+        DnsManager::Disabled | DnsManager::ResolvConf => Ok(false),
+    }
+}
+
+async fn apply(tun: &NetworkInterface, target: &DnsTarget) -> Result<(), ()> {
+    let dns = &target.network_config.dns;
+    match target.dns_manager {
+        DnsManager::Resolved => resolved::set_dns(tun, dns).await,
+        DnsManager::Networkd => networkd::set_dns(tun, dns).await,
+        DnsManager::NetworkManager => network_manager::set_dns(tun, &target.network_config).await,
+        DnsManager::Disabled | DnsManager::ResolvConf => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(dns_manager: DnsManager) -> DnsTarget {
+        let network_config = OsNetworkConfig {
+            dns: vec!["10.64.0.1".parse().unwrap()],
+            ipv4: "10.75.76.2".parse().unwrap(),
+            ipv6: "fd00::2/128".parse().unwrap(),
+            mtu: 1280,
+            use_system_dns: false,
+            local_network_access: false,
+        };
+        DnsTarget { dns_manager, network_config }
+    }
+
+    #[tokio::test]
+    async fn test_repair_dns_counts_interventions() {
+        let (_target, mut desired) = channel(Some(target(DnsManager::Resolved)));
+        let (interventions, mut interventions_receiver) = channel(0);
+        let mut dirty = [true, false].into_iter();
+        let mut applied = 0;
+        let repair = repair_dns(
+            DnsManager::Resolved,
+            &mut desired,
+            futures::stream::pending().boxed(),
+            &interventions,
+            async |_| Ok(dirty.next().unwrap_or(false)),
+            async |_| {
+                applied += 1;
+                Ok(())
+            },
+        );
+        select! {
+            _ = repair => panic!("repair loop returned"),
+            _ = interventions_receiver.wait_for(|interventions| *interventions == 1) => {}
+        }
+        assert_eq!(applied, 1);
+    }
+
+    #[tokio::test]
+    async fn test_repair_dns_returns_on_manager_change() {
+        let (target_sender, mut desired) = channel(Some(target(DnsManager::Resolved)));
+        target_sender.send_replace(Some(target(DnsManager::NetworkManager)));
+        let (interventions, interventions_receiver) = channel(0);
+        let result = repair_dns(
+            DnsManager::Resolved,
+            &mut desired,
+            futures::stream::pending().boxed(),
+            &interventions,
+            async |_| Ok(true),
+            async |_| Ok(()),
+        )
+        .await;
+        assert_eq!(result, Ok(()));
+        assert_eq!(*interventions_receiver.borrow(), 0);
+    }
+}
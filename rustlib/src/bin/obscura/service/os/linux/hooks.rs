@@ -0,0 +1,373 @@
+//! Runs executables in `/etc/obscura/hooks.d/` on tunnel transitions, similar to OpenVPN's up and down scripts.
+//!
+//! Hooks run one at a time in name order, with the event as first argument: `connected`, `exit-changed`, `disconnected` or `failed`. Reconnecting to the same exit with the same tunnel addresses doesn't run hooks, if the exit or addresses change `exit-changed` is sent instead of `connected`. Hooks don't inherit the service's environment, they only get a fixed `PATH` and details as environment variables:
+//! - `OBSCURA_EVENT`: the event, same as the first argument.
+//! - `OBSCURA_TUNNEL_INTERFACE`: name of the tunnel interface.
+//! - `OBSCURA_EXIT_ID`, `OBSCURA_EXIT_COUNTRY`, `OBSCURA_EXIT_CITY`: the exit, on `connected` and `exit-changed`.
+//! - `OBSCURA_TUNNEL_IPV4`, `OBSCURA_TUNNEL_IPV6`, `OBSCURA_DNS`: addresses of the tunnel and space separated DNS servers, on `connected` and `exit-changed`.
+//! - `OBSCURA_PREVIOUS_EXIT_ID`: the exit before, on `exit-changed` and `disconnected` if there was one.
+//! - `OBSCURA_ERROR`: `ConnectErrorCode` of the failed attempt, on `failed`. Sent once per distinct error while connecting.
+//!
+//! Hooks must be regular files, not symlinks, owned by root and not writable by others, since they run as root. The same applies to the hooks directory, so hooks can't be replaced after they were checked. Hooks are killed after a timeout and their output is logged. Hooks may start background processes (e.g. restart a daemon), only output written before the hook exits is logged.
+
+use crate::service::os::linux::tun::TUN_NAME;
+use obscuravpn_client::errors::ConnectErrorCode;
+use obscuravpn_client::network_config::TunnelNetworkConfig;
+use obscuravpn_client::tunnel_state::TunnelState;
+use std::io::ErrorKind;
+use std::os::unix::fs::{MetadataExt as _, PermissionsExt as _};
+use std::path::{Path, PathBuf};
+use std::pin::pin;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt as _};
+use tokio::sync::{mpsc, watch};
+use tokio::time::timeout;
+use tokio::{join, select};
+use uuid::Uuid;
+
+const HOOKS_DIR: &str = "/etc/obscura/hooks.d";
+const HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+/// Output beyond this is dropped from the log.
+const OUTPUT_LIMIT: u64 = 4096;
+/// How long output still buffered in the pipes is read after the hook exited. Background processes started by the hook may keep the pipes open.
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_millis(100);
+const HOOK_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+#[derive(Debug, PartialEq, Eq)]
+struct HookEvent {
+    name: &'static str,
+    env: Vec<(&'static str, String)>,
+}
+
+/// The parts of `TunnelState` hooks care about.
+enum Snapshot {
+    Disconnected,
+    Connecting {
+        connect_error: Option<ConnectErrorCode>,
+    },
+    Connected {
+        tunnel_id: Uuid,
+        exit: ExitInfo,
+        network_config: TunnelNetworkConfig,
+    },
+}
+
+struct ExitInfo {
+    id: String,
+    country: String,
+    city: String,
+}
+
+impl Snapshot {
+    fn new(tunnel_state: &TunnelState) -> Self {
+        match tunnel_state {
+            TunnelState::Disconnected => Self::Disconnected,
+            TunnelState::Connecting { connect_error, .. } => {
+                Self::Connecting { connect_error: connect_error.as_ref().map(|error_at| ConnectErrorCode::from(&error_at.error)) }
+            }
+            TunnelState::Connected { tunnel_id, exit, network_config, .. } => Self::Connected {
+                tunnel_id: *tunnel_id,
+                exit: ExitInfo { id: exit.id.clone(), country: exit.city_code.country_code.0.clone(), city: exit.city_name.clone() },
+                network_config: network_config.clone(),
+            },
+        }
+    }
+}
+
+/// Turns tunnel state changes into hook events.
+#[derive(Default)]
+struct Transitions {
+    active: bool,
+    tunnel_id: Option<Uuid>,
+    exit_id: Option<String>,
+    network_config: Option<TunnelNetworkConfig>,
+    connect_error: Option<ConnectErrorCode>,
+}
+
+impl Transitions {
+    fn next(&mut self, snapshot: Snapshot) -> Option<HookEvent> {
+        match snapshot {
+            Snapshot::Disconnected => {
+                let previous = std::mem::take(self);
+                previous
+                    .active
+                    .then(|| HookEvent::new("disconnected", previous.exit_id.map(|id| ("OBSCURA_PREVIOUS_EXIT_ID", id))))
+            }
+            Snapshot::Connecting { connect_error } => {
+                self.active = true;
+                let changed = connect_error != self.connect_error;
+                self.connect_error = connect_error;
+                let error = connect_error.filter(|_| changed)?;
+                Some(HookEvent::new("failed", [("OBSCURA_ERROR", <&'static str>::from(error).to_owned())]))
+            }
+            Snapshot::Connected { tunnel_id, exit, network_config } => {
+                self.active = true;
+                self.connect_error = None;
+                if self.tunnel_id == Some(tunnel_id) {
+                    return None;
+                }
+                self.tunnel_id = Some(tunnel_id);
+                // Stays set while reconnecting, so hooks only see `connected` after `disconnected`.
+                let previous_exit_id = self.exit_id.replace(exit.id.clone());
+                let previous_network_config = self.network_config.replace(network_config.clone());
+                if previous_exit_id.as_ref() == Some(&exit.id) && previous_network_config.as_ref() == Some(&network_config) {
+                    return None;
+                }
+                let name = if previous_exit_id.is_some() { "exit-changed" } else { "connected" };
+                let dns: Vec<String> = network_config.dns.iter().map(ToString::to_string).collect();
+                let env = [
+                    ("OBSCURA_EXIT_ID", exit.id),
+                    ("OBSCURA_EXIT_COUNTRY", exit.country),
+                    ("OBSCURA_EXIT_CITY", exit.city),
+                    ("OBSCURA_TUNNEL_IPV4", network_config.ipv4.to_string()),
+                    ("OBSCURA_TUNNEL_IPV6", network_config.ipv6.ip().to_string()),
+                    ("OBSCURA_DNS", dns.join(" ")),
+                ];
+                Some(HookEvent::new(
+                    name,
+                    env.into_iter().chain(previous_exit_id.map(|id| ("OBSCURA_PREVIOUS_EXIT_ID", id))),
+                ))
+            }
+        }
+    }
+}
+
+impl HookEvent {
+    fn new(name: &'static str, env: impl IntoIterator<Item = (&'static str, String)>) -> Self {
+        let env = [("OBSCURA_EVENT", name.to_owned()), ("OBSCURA_TUNNEL_INTERFACE", TUN_NAME.to_owned())]
+            .into_iter()
+            .chain(env)
+            .collect();
+        Self { name, env }
+    }
+}
+
+/// Watches tunnel state transitions and runs hooks for them. Events are queued, so slow hooks don't cause transitions to be missed.
+pub async fn run_hooks(mut tunnel_state: watch::Receiver<TunnelState>) {
+    let (event_sender, mut events) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+            run_hooks_for(&event).await;
+        }
+    });
+    let mut transitions = Transitions::default();
+    loop {
+        let snapshot = Snapshot::new(&tunnel_state.borrow_and_update());
+        if let Some(event) = transitions.next(snapshot) {
+            tracing::info!(message_id = "Gq5wTz3H", event = event.name, "tunnel transition for hooks");
+            _ = event_sender.send(event);
+        }
+        if tunnel_state.changed().await.is_err() {
+            break;
+        }
+    }
+    tracing::info!(message_id = "Ak7mRd2V", "stop running hooks");
+}
+
+async fn run_hooks_for(event: &HookEvent) {
+    for hook in hook_paths(Path::new(HOOKS_DIR)).await {
+        run_hook(&hook, event).await;
+    }
+}
+
+/// Executable files in name order, skipping hidden files and editor backups.
+async fn hook_paths(dir: &Path) -> Vec<PathBuf> {
+    match tokio::fs::symlink_metadata(dir).await {
+        Ok(metadata) if !metadata.is_dir() || metadata.uid() != 0 || metadata.permissions().mode() & 0o022 != 0 => {
+            tracing::warn!(
+                message_id = "Wm6cRt2J",
+                ?dir,
+                "not running hooks from directory which is not a directory owned by root or writable by others"
+            );
+            return Vec::new();
+        }
+        Ok(_) => {}
+        Err(error) if error.kind() == ErrorKind::NotFound => return Vec::new(),
+        Err(error) => {
+            tracing::error!(message_id = "Zf3kNv8D", ?error, ?dir, "failed to read hooks directory metadata: {error}");
+            return Vec::new();
+        }
+    }
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Vec::new(),
+        Err(error) => {
+            tracing::error!(message_id = "Cx4bNs9L", ?error, ?dir, "failed to read hooks directory: {error}");
+            return Vec::new();
+        }
+    };
+    let mut hooks = Vec::new();
+    loop {
+        let entry = match entries.next_entry().await {
+            Ok(Some(entry)) => entry,
+            Ok(None) => break,
+            Err(error) => {
+                tracing::error!(message_id = "Pw8fKj1Y", ?error, ?dir, "failed to read hooks directory entry: {error}");
+                break;
+            }
+        };
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with('.') || name.ends_with('~') {
+            continue;
+        }
+        let path = entry.path();
+        // Symlinks aren't followed, their targets could be replaced by whoever controls the directories on the way.
+        let metadata = match tokio::fs::symlink_metadata(&path).await {
+            Ok(metadata) => metadata,
+            Err(error) => {
+                tracing::warn!(message_id = "Ve2hQc6S", ?error, ?path, "skipping hook with unreadable metadata: {error}");
+                continue;
+            }
+        };
+        let mode = metadata.permissions().mode();
+        if !metadata.is_file() || mode & 0o111 == 0 {
+            tracing::warn!(message_id = "Hj9tLm4B", ?path, "skipping hook which is not an executable regular file");
+            continue;
+        }
+        if metadata.uid() != 0 || mode & 0o022 != 0 {
+            tracing::warn!(
+                message_id = "Ns3kYw7F",
+                ?path,
+                "skipping hook which is not owned by root or writable by others"
+            );
+            continue;
+        }
+        hooks.push(path);
+    }
+    hooks.sort();
+    hooks
+}
+
+async fn run_hook(hook: &Path, event: &HookEvent) {
+    let mut command = tokio::process::Command::new(hook);
+    command
+        .arg(event.name)
+        .env_clear()
+        .env("PATH", HOOK_PATH)
+        .envs(event.env.iter().map(|(key, value)| (*key, value)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(error) => {
+            tracing::error!(message_id = "Tb6pDx8Q", ?error, ?hook, event = event.name, "failed to run hook: {error}");
+            return;
+        }
+    };
+    let (stdout_pipe, stderr_pipe) = (child.stdout.take(), child.stderr.take());
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    let status = {
+        let mut read_output = pin!(async {
+            join!(read_limited(stdout_pipe, &mut stdout), read_limited(stderr_pipe, &mut stderr));
+        });
+        let mut output_read = false;
+        // Waits for the hook to exit rather than for the pipes to close.
+        let wait = async {
+            select! {
+                status = child.wait() => status,
+                () = &mut read_output => {
+                    output_read = true;
+                    child.wait().await
+                }
+            }
+        };
+        let status = match timeout(HOOK_TIMEOUT, wait).await {
+            Ok(Ok(status)) => status,
+            Ok(Err(error)) => {
+                tracing::error!(
+                    message_id = "Fq8wLc3R",
+                    ?error,
+                    ?hook,
+                    event = event.name,
+                    "failed to wait for hook: {error}"
+                );
+                return;
+            }
+            Err(_) => {
+                tracing::warn!(message_id = "Yr1cGv5N", ?hook, event = event.name, timeout = ?HOOK_TIMEOUT, "killed hook after timeout");
+                return;
+            }
+        };
+        if !output_read {
+            let _ = timeout(OUTPUT_DRAIN_TIMEOUT, &mut read_output).await;
+        }
+        status
+    };
+    let stdout = String::from_utf8_lossy(&stdout).trim_end().to_owned();
+    let stderr = String::from_utf8_lossy(&stderr).trim_end().to_owned();
+    if status.success() {
+        tracing::info!(message_id = "Ew4sMk7Z", ?hook, event = event.name, stdout, stderr, "hook finished");
+    } else {
+        tracing::warn!(message_id = "Lz2nBq9T", ?hook, event = event.name, status =% status, stdout, stderr, "hook failed");
+    }
+}
+
+/// Keeps up to `OUTPUT_LIMIT` bytes and discards the rest, so the hook doesn't block on a full pipe. Output read so far is kept if this is cancelled.
+async fn read_limited(pipe: Option<impl AsyncRead + Unpin>, output: &mut Vec<u8>) {
+    let Some(pipe) = pipe else {
+        return;
+    };
+    let mut pipe = pipe.take(OUTPUT_LIMIT);
+    if pipe.read_to_end(output).await.is_ok() {
+        let _ = tokio::io::copy(&mut pipe.into_inner(), &mut tokio::io::sink()).await;
+    }
+}
+
+#[test]
+fn test_transitions() {
+    let connected_with = |tunnel_id: u128, exit_id: &str, ipv4: &str| Snapshot::Connected {
+        tunnel_id: Uuid::from_u128(tunnel_id),
+        exit: ExitInfo { id: exit_id.into(), country: "de".into(), city: "Frankfurt".into() },
+        network_config: TunnelNetworkConfig {
+            dns: vec!["10.64.0.99".parse().unwrap()],
+            ipv4: ipv4.parse().unwrap(),
+            ipv6: "fc00:bbbb:bbbb:bb01::1/128".parse().unwrap(),
+            mtu: 1280,
+        },
+    };
+    let connected = |tunnel_id: u128, exit_id: &str| connected_with(tunnel_id, exit_id, "10.64.1.2");
+    let name = |event: Option<HookEvent>| event.map(|event| event.name);
+    let mut transitions = Transitions::default();
+    assert_eq!(name(transitions.next(Snapshot::Disconnected)), None);
+    assert_eq!(name(transitions.next(Snapshot::Connecting { connect_error: None })), None);
+    let failed = Snapshot::Connecting { connect_error: Some(ConnectErrorCode::Other) };
+    assert_eq!(name(transitions.next(failed)), Some("failed"));
+    let failed = Snapshot::Connecting { connect_error: Some(ConnectErrorCode::Other) };
+    assert_eq!(name(transitions.next(failed)), None);
+
+    let event = transitions.next(connected(1, "de-fra-001")).unwrap();
+    assert_eq!(event.name, "connected");
+    assert!(event.env.contains(&("OBSCURA_EXIT_ID", "de-fra-001".into())));
+    assert!(event.env.contains(&("OBSCURA_TUNNEL_IPV6", "fc00:bbbb:bbbb:bb01::1".into())));
+    assert_eq!(name(transitions.next(connected(1, "de-fra-001"))), None);
+
+    assert_eq!(name(transitions.next(Snapshot::Connecting { connect_error: None })), None);
+    assert_eq!(name(transitions.next(connected(2, "de-fra-001"))), None);
+    let event = transitions.next(connected_with(3, "de-fra-001", "10.64.1.3")).unwrap();
+    assert_eq!(event.name, "exit-changed");
+    assert!(event.env.contains(&("OBSCURA_TUNNEL_IPV4", "10.64.1.3".into())));
+    let event = transitions.next(connected(4, "de-fra-002")).unwrap();
+    assert_eq!(event.name, "exit-changed");
+    assert!(event.env.contains(&("OBSCURA_PREVIOUS_EXIT_ID", "de-fra-001".into())));
+
+    let event = transitions.next(Snapshot::Disconnected).unwrap();
+    assert_eq!(event.name, "disconnected");
+    assert!(event.env.contains(&("OBSCURA_PREVIOUS_EXIT_ID", "de-fra-002".into())));
+    assert_eq!(name(transitions.next(Snapshot::Disconnected)), None);
+}
+
+#[tokio::test]
+async fn test_run_hook_background_process() {
+    let dir = tempfile::tempdir().unwrap();
+    let hook = dir.path().join("hook");
+    std::fs::write(&hook, "#!/bin/sh\nsleep 60 &\necho started\n").unwrap();
+    std::fs::set_permissions(&hook, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let event = HookEvent { name: "connected", env: Vec::new() };
+    // The background process keeps the output pipes open, the hook must still finish once the script exits.
+    tokio::time::timeout(Duration::from_secs(5), run_hook(&hook, &event)).await.unwrap();
+}
@@ -0,0 +1,424 @@
+//! Opt-in HTTP control API for clients which can't use the IPC socket, e.g. container sidecars, see `obscura service --http-api`. Addresses reachable from other hosts additionally require `--http-api-allow-remote`. Callers authenticate with the bearer token in `<state dir>/http-api-token`, which only root and the operator group can read.
+//!
+//! - `GET /v1/status` returns the status.
+//! - `GET /v1/events` streams events as server-sent events, `?trafficIntervalMs=<ms>` includes periodic traffic stats.
+//! - `POST /v1/commands/<command>` runs a `ManagerCmd`, e.g. `/v1/commands/setAutoConnect` with body `{"enable":true}`. The body may be empty for commands without arguments.
+//!
+//! Successful results are returned as JSON with status 200, errors as `{"error":"<code>"}` with a 4xx or 5xx status.
+
+use crate::service::os::MAX_IPC_MESSAGE_LEN;
+use crate::service::os::linux::ipc::{CommandSender, SubscriptionSender, run_json_command, subscribe};
+use crate::service::os::linux::start_error::LinuxServiceStartError;
+use bytes::Bytes;
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt as _, Full, Limited, StreamBody};
+use hyper::body::Frame;
+use hyper::header::{AUTHORIZATION, CACHE_CONTROL, CONTENT_TYPE, HeaderValue};
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::{TokioIo, TokioTimer};
+use obscuravpn_client::int_helper::u32_into_usize;
+use obscuravpn_client::manager_cmd::{AccessLevel, ManagerCmd, ManagerCmdErrorCode};
+use obscuravpn_client::manager_event::EventSubscription;
+use rand::Rng as _;
+use rand::distributions::Alphanumeric;
+use std::convert::Infallible;
+use std::io::{ErrorKind, Write as _};
+use std::net::SocketAddr;
+use std::os::unix::fs::OpenOptionsExt as _;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+const TOKEN_FILE: &str = "http-api-token";
+/// The token file is readable by the operator group, so the token grants no more than membership would.
+const TOKEN_ACCESS: AccessLevel = AccessLevel::Operator;
+/// Idle event streams get comment lines, so intermediate proxies don't time out and closed connections are noticed.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+/// Closes connections which don't send complete request headers in time, so idle or slow clients can't hold connections open indefinitely.
+const HEADER_READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+type Body = BoxBody<Bytes, Infallible>;
+
+pub struct HttpApiConfig {
+    pub listen: SocketAddr,
+    /// Whether `listen` may be reachable from other hosts.
+    pub allow_remote: bool,
+    pub state_dir: PathBuf,
+}
+
+pub struct HttpApi {
+    listener: TcpListener,
+    token: String,
+}
+
+impl HttpApi {
+    /// Fails if the address can't be bound, since the API was explicitly requested.
+    pub async fn bind(config: HttpApiConfig) -> Result<Self, LinuxServiceStartError> {
+        if !config.listen.ip().is_loopback() && !config.allow_remote {
+            tracing::error!(
+                message_id = "Kp4wZr8N",
+                listen =% config.listen,
+                "refusing to serve HTTP API on non-loopback address without explicit opt-in"
+            );
+            return Err(LinuxServiceStartError::HttpApiNotLoopback(config.listen));
+        }
+        let token_path = config.state_dir.join(TOKEN_FILE);
+        let token = load_or_create_token(&token_path).map_err(|error| {
+            tracing::error!(
+                message_id = "Jh4nWc8X",
+                ?error,
+                ?token_path,
+                "failed to load or create HTTP API token: {error}"
+            );
+            anyhow::Error::new(error).context("failed to load or create HTTP API token")
+        })?;
+        let listener = TcpListener::bind(config.listen).await.map_err(|error| {
+            tracing::error!(message_id = "Bv7kQs2M", ?error, listen =% config.listen, "failed to bind HTTP API: {error}");
+            anyhow::Error::new(error).context("failed to bind HTTP API")
+        })?;
+        if !config.listen.ip().is_loopback() {
+            tracing::warn!(
+                message_id = "Td3xRm6P",
+                listen =% config.listen,
+                "HTTP API is reachable from other hosts, the bearer token is sent unencrypted"
+            );
+        }
+        tracing::info!(message_id = "Ln9cGy5F", listen =% config.listen, "serving HTTP API");
+        Ok(Self { listener, token })
+    }
+
+    pub async fn serve(self, commands: CommandSender, subscriptions: SubscriptionSender) {
+        let handler = Arc::new(Handler { token: self.token, commands, subscriptions });
+        while !handler.commands.is_disconnected() {
+            let (stream, remote) = match self.listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(error) => {
+                    // Usually running out of file descriptors, which may resolve once other connections close.
+                    tracing::error!(message_id = "Xk2pFt7W", ?error, "HTTP API accept failed: {error}");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            };
+            let handler = handler.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |request| {
+                    let handler = handler.clone();
+                    async move { Ok::<_, Infallible>(handler.handle(request).await) }
+                });
+                if let Err(error) = hyper::server::conn::http1::Builder::new()
+                    .timer(TokioTimer::new())
+                    .header_read_timeout(HEADER_READ_TIMEOUT)
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await
+                {
+                    tracing::info!(message_id = "Qc6vHn1D", ?error, %remote, "HTTP API connection failed: {error}");
+                }
+            });
+        }
+        tracing::info!(message_id = "Rm8tBw4J", "stop serving HTTP API");
+    }
+}
+
+struct Handler {
+    token: String,
+    commands: CommandSender,
+    subscriptions: SubscriptionSender,
+}
+
+impl Handler {
+    async fn handle<B>(&self, request: Request<B>) -> Response<Body>
+    where
+        B: hyper::body::Body,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        if !self.authenticated(&request) {
+            return error_response(StatusCode::UNAUTHORIZED, "unauthorized");
+        }
+        let method = request.method().clone();
+        let path = request.uri().path().to_owned();
+        match (method, path.as_str()) {
+            (Method::GET, "/v1/status") => self.run(ManagerCmd::GetStatus { known_version: None }).await,
+            (Method::GET, "/v1/events") => self.events(request.uri().query()).await,
+            (Method::POST, path) => match path.strip_prefix("/v1/commands/") {
+                Some(name) => self.command(name, request.into_body()).await,
+                None => error_response(StatusCode::NOT_FOUND, "notFound"),
+            },
+            _ => error_response(StatusCode::NOT_FOUND, "notFound"),
+        }
+    }
+
+    fn authenticated<B>(&self, request: &Request<B>) -> bool {
+        let token = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        token.is_some_and(|token| constant_time_eq(token.as_bytes(), self.token.as_bytes()))
+    }
+
+    async fn command<B>(&self, name: &str, body: B) -> Response<Body>
+    where
+        B: hyper::body::Body,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let body = match Limited::new(body, u32_into_usize(MAX_IPC_MESSAGE_LEN)).collect().await {
+            Ok(body) => body.to_bytes(),
+            Err(error) => {
+                tracing::info!(message_id = "Wy5dKp9N", ?error, "failed to read HTTP API request body: {error}");
+                return error_response(StatusCode::BAD_REQUEST, "invalidBody");
+            }
+        };
+        let args = if body.is_empty() {
+            Ok(serde_json::json!({}))
+        } else {
+            serde_json::from_slice(&body)
+        };
+        let cmd = args.and_then(|args: serde_json::Value| {
+            serde_json::from_value::<ManagerCmd>(serde_json::Value::Object([(name.to_owned(), args)].into_iter().collect()))
+        });
+        match cmd {
+            // Events need a long-lived response, see `/v1/events`.
+            Ok(ManagerCmd::SubscribeEvents { .. }) => error_response(StatusCode::NOT_FOUND, "notFound"),
+            Ok(cmd) => self.run(cmd).await,
+            Err(error) => {
+                tracing::info!(message_id = "Fp3sLz8C", ?error, name, "invalid HTTP API command: {error}");
+                error_response(StatusCode::BAD_REQUEST, "invalidCommand")
+            }
+        }
+    }
+
+    async fn run(&self, cmd: ManagerCmd) -> Response<Body> {
+        if TOKEN_ACCESS < cmd.required_access() {
+            tracing::warn!(message_id = "Hd6rNq2T", command = cmd.name(), "rejecting unauthorized HTTP API command");
+            return error_response(StatusCode::FORBIDDEN, ManagerCmdErrorCode::InsufficientPermissions.into());
+        }
+        let message = match serde_json::to_vec(&cmd) {
+            Ok(message) => message,
+            Err(error) => {
+                tracing::error!(message_id = "Zg1mYv4K", ?error, "failed to encode command: {error}");
+                return error_response(StatusCode::INTERNAL_SERVER_ERROR, ManagerCmdErrorCode::Other.into());
+            }
+        };
+        let response = match run_json_command(&self.commands, message, false).await {
+            Ok(Some(response)) => response,
+            Ok(None) | Err(()) => return error_response(StatusCode::SERVICE_UNAVAILABLE, "serviceUnavailable"),
+        };
+        match serde_json::from_slice::<Result<serde_json::Value, ManagerCmdErrorCode>>(&response) {
+            Ok(Ok(value)) => json_response(StatusCode::OK, value.to_string()),
+            Ok(Err(ManagerCmdErrorCode::Other)) => error_response(StatusCode::INTERNAL_SERVER_ERROR, ManagerCmdErrorCode::Other.into()),
+            Ok(Err(code)) => error_response(StatusCode::UNPROCESSABLE_ENTITY, code.into()),
+            Err(error) => {
+                tracing::error!(message_id = "Sb8wCe3R", ?error, "failed to decode command response: {error}");
+                error_response(StatusCode::INTERNAL_SERVER_ERROR, ManagerCmdErrorCode::Other.into())
+            }
+        }
+    }
+
+    async fn events(&self, query: Option<&str>) -> Response<Body> {
+        let mut subscription = EventSubscription::default();
+        for (key, value) in url::form_urlencoded::parse(query.unwrap_or_default().as_bytes()) {
+            if key == "trafficIntervalMs" {
+                match value.parse() {
+                    Ok(interval) => subscription.traffic_interval_ms = Some(interval),
+                    Err(_) => return error_response(StatusCode::BAD_REQUEST, "invalidQuery"),
+                }
+            }
+        }
+        let Ok(Some(events)) = subscribe(&self.subscriptions, subscription).await else {
+            return error_response(StatusCode::SERVICE_UNAVAILABLE, "serviceUnavailable");
+        };
+        let keepalive = tokio::time::interval(KEEPALIVE_INTERVAL);
+        let frames = futures::stream::unfold((events, keepalive), |(mut events, mut keepalive)| async move {
+            let data = tokio::select! {
+                event = events.recv() => match serde_json::to_string(&event?) {
+                    Ok(json) => format!("data: {json}\n\n"),
+                    Err(error) => {
+                        tracing::error!(message_id = "Uv2jXr5G", ?error, "failed to encode event: {error}");
+                        return None;
+                    }
+                },
+                _ = keepalive.tick() => ":\n\n".to_owned(),
+            };
+            Some((Ok(Frame::data(Bytes::from(data))), (events, keepalive)))
+        });
+        let mut response = Response::new(StreamBody::new(frames).boxed());
+        response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
+        response.headers_mut().insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        response
+    }
+}
+
+fn json_response(status: StatusCode, json: String) -> Response<Body> {
+    let mut response = Response::new(Full::new(Bytes::from(json)).boxed());
+    *response.status_mut() = status;
+    response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+fn error_response(status: StatusCode, error: &'static str) -> Response<Body> {
+    json_response(status, serde_json::json!({ "error": error }).to_string())
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Keeps the token across restarts, so clients don't have to reload it. Delete the file to rotate the token.
+fn load_or_create_token(path: &Path) -> std::io::Result<String> {
+    match std::fs::read_to_string(path) {
+        Ok(token) if token.trim().is_empty() => return Err(std::io::Error::other("token file is empty")),
+        Ok(token) => return Ok(token.trim().to_owned()),
+        Err(error) if error.kind() == ErrorKind::NotFound => {}
+        Err(error) => return Err(error),
+    }
+    let token: String = rand::thread_rng().sample_iter(&Alphanumeric).take(43).map(char::from).collect();
+    let mut file = std::fs::OpenOptions::new().write(true).create_new(true).mode(0o640).open(path)?;
+    writeln!(file, "{token}")?;
+    Ok(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const TOKEN: &str = "secret";
+
+    /// Answers every command with `response` and returns the received commands.
+    fn handler(response: serde_json::Value) -> (Handler, flume::Receiver<serde_json::Value>) {
+        let (commands, command_receiver): (CommandSender, _) = flume::unbounded();
+        let (subscriptions, _) = flume::unbounded();
+        let (received_sender, received) = flume::unbounded();
+        tokio::spawn(async move {
+            while let Ok((message, _redact_account, respond)) = command_receiver.recv_async().await {
+                _ = received_sender.send(serde_json::from_slice(&message).unwrap());
+                respond(response.to_string().into_bytes());
+            }
+        });
+        (Handler { token: TOKEN.to_owned(), commands, subscriptions }, received)
+    }
+
+    fn request(method: Method, path: &str, authorization: Option<&str>, body: &'static str) -> Request<Full<Bytes>> {
+        let mut request = Request::builder().method(method).uri(path);
+        if let Some(authorization) = authorization {
+            request = request.header(AUTHORIZATION, authorization);
+        }
+        request.body(Full::new(Bytes::from_static(body.as_bytes()))).unwrap()
+    }
+
+    async fn body_json(response: Response<Body>) -> serde_json::Value {
+        serde_json::from_slice(&response.into_body().collect().await.unwrap().to_bytes()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_unauthenticated() {
+        let (handler, received) = handler(json!({ "Ok": null }));
+        for authorization in [None, Some("Bearer wrong"), Some("Bearer secre"), Some("Basic secret"), Some("secret")] {
+            let response = handler.handle(request(Method::GET, "/v1/status", authorization, "")).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{authorization:?}");
+            assert_eq!(body_json(response).await, json!({ "error": "unauthorized" }));
+        }
+        assert!(received.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_status() {
+        let (handler, received) = handler(json!({ "Ok": { "version": "1" } }));
+        let response = handler.handle(request(Method::GET, "/v1/status", Some("Bearer secret"), "")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body_json(response).await, json!({ "version": "1" }));
+        assert_eq!(received.recv_async().await.unwrap(), json!({ "getStatus": { "knownVersion": null } }));
+    }
+
+    #[tokio::test]
+    async fn test_command() {
+        let (handler, received) = handler(json!({ "Ok": null }));
+        let response = handler
+            .handle(request(
+                Method::POST,
+                "/v1/commands/setAutoConnect",
+                Some("Bearer secret"),
+                r#"{"enable":true}"#,
+            ))
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(received.recv_async().await.unwrap(), json!({ "setAutoConnect": { "enable": true } }));
+
+        let response = handler
+            .handle(request(Method::POST, "/v1/commands/ping", Some("Bearer secret"), ""))
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(received.recv_async().await.unwrap(), json!({ "ping": {} }));
+    }
+
+    #[tokio::test]
+    async fn test_command_errors() {
+        let (handler, received) = handler(json!({ "Err": "configSaveError" }));
+        let response = handler
+            .handle(request(
+                Method::POST,
+                "/v1/commands/setAutoConnect",
+                Some("Bearer secret"),
+                r#"{"enable":true}"#,
+            ))
+            .await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body_json(response).await, json!({ "error": "configSaveError" }));
+        received.recv_async().await.unwrap();
+
+        let cases = [
+            (
+                Method::POST,
+                "/v1/commands/setApiUrl",
+                r#"{"url":null}"#,
+                StatusCode::FORBIDDEN,
+                "insufficientPermissions",
+            ),
+            (
+                Method::POST,
+                "/v1/commands/setAutoConnect",
+                "{",
+                StatusCode::BAD_REQUEST,
+                "invalidCommand",
+            ),
+            (Method::POST, "/v1/commands/unknown", "", StatusCode::BAD_REQUEST, "invalidCommand"),
+            (
+                Method::POST,
+                "/v1/commands/subscribeEvents",
+                r#"{"subscription":{}}"#,
+                StatusCode::NOT_FOUND,
+                "notFound",
+            ),
+            (Method::POST, "/v1/status", "", StatusCode::NOT_FOUND, "notFound"),
+            (Method::GET, "/v1/commands/ping", "", StatusCode::NOT_FOUND, "notFound"),
+        ];
+        for (method, path, body, status, error) in cases {
+            let response = handler.handle(request(method, path, Some("Bearer secret"), body)).await;
+            assert_eq!(response.status(), status, "{path}");
+            assert_eq!(body_json(response).await, json!({ "error": error }), "{path}");
+        }
+        assert!(received.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_bind_non_loopback() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = HttpApiConfig { listen: "0.0.0.0:0".parse().unwrap(), allow_remote: false, state_dir: dir.path().to_owned() };
+        assert!(matches!(HttpApi::bind(config).await, Err(LinuxServiceStartError::HttpApiNotLoopback(_))));
+        let config = HttpApiConfig { listen: "127.0.0.1:0".parse().unwrap(), allow_remote: false, state_dir: dir.path().to_owned() };
+        assert!(HttpApi::bind(config).await.is_ok());
+    }
+}
+
+#[test]
+fn test_load_or_create_token() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(TOKEN_FILE);
+    let token = load_or_create_token(&path).unwrap();
+    assert_eq!(token.len(), 43);
+    assert_eq!(load_or_create_token(&path).unwrap(), token);
+    std::fs::write(&path, "\n").unwrap();
+    assert!(load_or_create_token(&path).is_err());
+}
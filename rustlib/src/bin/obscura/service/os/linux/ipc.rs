@@ -1,21 +1,32 @@
 use crate::service::os::MAX_IPC_MESSAGE_LEN;
+use crate::service::os::linux::dbus;
+use crate::service::os::linux::http_api::{HttpApi, HttpApiConfig};
+use crate::service::os::linux::peer_access::Peer;
+use crate::service::os::linux::polkit;
 use crate::service::os::linux::service_lock::ServiceLock;
 use crate::service::os::linux::start_error::LinuxServiceStartError;
 use flume::{Receiver, Sender, bounded};
-use obscuravpn_client::int_helper::u32_into_usize;
-use obscuravpn_client::linux::ipc::{LinuxIpcHeader, SOCKET_PATH};
-use obscuravpn_client::version::release_version;
-use std::fs;
+use obscuravpn_client::linux::ipc::{LinuxIpcClientHello, LinuxIpcHeader, SOCKET_PATH, read_frame, write_frame};
+use obscuravpn_client::manager_cmd::{ManagerCmd, ManagerCmdErrorCode};
+use obscuravpn_client::manager_event::{EventSubscription, ManagerEvent};
+use std::fs::{self, Permissions};
 use std::io::ErrorKind;
+use std::os::unix::fs::PermissionsExt;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, oneshot};
+
+type EventsFn = Box<dyn FnOnce(mpsc::Receiver<ManagerEvent>) + Send>;
+pub type CommandSender = Sender<(Vec<u8>, bool, Box<dyn FnOnce(Vec<u8>) + Send>)>;
+pub type SubscriptionSender = Sender<(EventSubscription, EventsFn)>;
 
 pub struct ServiceIpc {
-    receiver: Receiver<(Vec<u8>, Box<dyn FnOnce(Vec<u8>) + Send>)>,
+    receiver: Receiver<(Vec<u8>, bool, Box<dyn FnOnce(Vec<u8>) + Send>)>,
+    subscriptions: Receiver<(EventSubscription, EventsFn)>,
 }
 
 impl ServiceIpc {
-    pub async fn new(_lock: &ServiceLock) -> Result<Self, LinuxServiceStartError> {
+    pub async fn new(_lock: &ServiceLock, http_api: Option<HttpApiConfig>) -> Result<Self, LinuxServiceStartError> {
         fs::remove_file(SOCKET_PATH).or_else(|error| match error.kind() {
             ErrorKind::NotFound => Ok(()),
             kind => {
@@ -34,8 +45,19 @@ impl ServiceIpc {
                 _ => anyhow::Error::new(error).context("failed to create IPC socket").into(),
             }
         })?;
+        // Any local user may connect, commands are authorized individually based on the peer's credentials.
+        fs::set_permissions(SOCKET_PATH, Permissions::from_mode(0o777)).map_err(|error| {
+            tracing::error!(message_id = "Tm4wGc8J", ?error, "failed to set socket permissions: {error}");
+            anyhow::Error::new(error).context("failed to set IPC socket permissions")
+        })?;
         // ensure that `Self::next()` is cancel safe by decoupling it from the incremental progress on socket streams.
-        let (sender, receiver) = bounded::<(Vec<u8>, Box<dyn FnOnce(Vec<u8>) + Send>)>(0);
+        let (sender, receiver) = bounded::<(Vec<u8>, bool, Box<dyn FnOnce(Vec<u8>) + Send>)>(0);
+        let (subscription_sender, subscriptions) = bounded::<(EventSubscription, EventsFn)>(0);
+        tokio::spawn(dbus::serve(sender.clone(), subscription_sender.clone()));
+        if let Some(config) = http_api {
+            let http_api = HttpApi::bind(config).await?;
+            tokio::spawn(http_api.serve(sender.clone(), subscription_sender.clone()));
+        }
         tokio::spawn(async move {
             while !sender.is_disconnected() {
                 let Ok((stream, _)) = socket.accept().await.map_err(|error| {
@@ -44,68 +66,179 @@ impl ServiceIpc {
                 });
 
                 let sender = sender.clone();
+                let subscription_sender = subscription_sender.clone();
                 tokio::spawn(async move {
-                    let _: Result<(), ()> = Self::handle_stream(stream, sender).await;
+                    let _: Result<(), ()> = Self::handle_stream(stream, sender, subscription_sender).await;
                 });
             }
             tracing::info!(message_id = "dYp5Tr25", "stop listening for IPC connections");
         });
-        Ok(Self { receiver })
+        Ok(Self { receiver, subscriptions })
     }
 
-    pub async fn next(&self) -> (Vec<u8>, Box<dyn FnOnce(Vec<u8>) + Send>) {
+    /// Returns the next command and whether the account must be redacted from its response, see `Peer::may_see_account`.
+    pub async fn next(&self) -> (Vec<u8>, bool, Box<dyn FnOnce(Vec<u8>) + Send>) {
         self.receiver.recv_async().await.expect("uds task death is not recoverable")
     }
 
-    async fn handle_stream(mut stream: UnixStream, sender: Sender<(Vec<u8>, Box<dyn FnOnce(Vec<u8>) + Send>)>) -> Result<(), ()> {
+    /// Returns the next event subscription. The manager's event receiver must be passed to the returned function.
+    pub async fn next_event_subscription(&self) -> (EventSubscription, EventsFn) {
+        self.subscriptions.recv_async().await.expect("uds task death is not recoverable")
+    }
+
+    async fn handle_stream(mut stream: UnixStream, sender: CommandSender, subscription_sender: SubscriptionSender) -> Result<(), ()> {
         tracing::info!(message_id = "M0sAFoC7", "handling new socket stream");
+        let peer = Peer::new(&stream).await;
 
-        LinuxIpcHeader { version: release_version().to_owned() }
+        LinuxIpcHeader { polkit: polkit::available().await, ..LinuxIpcHeader::current(peer.access) }
             .write(&mut stream)
             .await
             .map_err(|error| {
                 tracing::error!(message_id = "cV2mXk8T", ?error, "failed to write IPC header to socket stream: {error}");
             })?;
 
-        let mut len = [0u8; 4];
-        stream.read_exact(&mut len).await.map_err(|error| {
-            tracing::error!(
-                message_id = "hfdWDTcp",
-                ?error,
-                "failed to read message length from socket stream: {error}"
-            );
-        })?;
-        let len = u32::from_be_bytes(len);
-        if len > MAX_IPC_MESSAGE_LEN {
-            tracing::error!(message_id = "k9XmPq2R", len, "message on socket stream too long");
-            return Err(());
+        let mut message = Self::read_message(&mut stream).await?;
+        // Clients predating the hello send a single command and expect an unframed response.
+        let framed = message.as_deref().and_then(LinuxIpcClientHello::parse).is_some();
+        if framed {
+            message = Self::read_message(&mut stream).await?;
         }
-        let mut message: Vec<u8> = vec![0; u32_into_usize(len)];
-        stream.read_exact(&mut message).await.map_err(|error| {
-            tracing::error!(message_id = "GFf8wiV3", ?error, "failed to read message from socket stream: {error}");
-        })?;
-        let response_fn = move |response: Vec<u8>| {
-            tokio::spawn(async move {
-                stream.write_all(&response).await.map_err(|error| {
-                    tracing::error!(message_id = "XijfChPl", ?error, "failed to write response to socket stream: {error}");
-                })?;
-                stream.shutdown().await.map_err(|error| {
-                    tracing::error!(message_id = "RRCdeq0M", ?error, "failed to close socket write stream: {error}");
-                })?;
-                // Sockets closed for writing on both sides don't linger, even if there's unread data, so we need to wait for the client to signal it's done reading.
-                let n = stream.read(&mut [0u8; 1]).await.map_err(|error| {
-                    tracing::error!(message_id = "g90YsnwQ", ?error, "failed to read clean EOF from socket stream: {error}");
-                })?;
-                if n == 0 {
-                    tracing::info!(message_id = "CiLg0uHK", "client closed socket stream as expected");
+        // Clients may send further commands before receiving responses, they are handled one at a time to keep responses in order.
+        while let Some(command) = message {
+            // Commands which fail to parse are passed on, so the client gets the usual error response.
+            let mut redact_account = false;
+            let mut response = None;
+            if let Ok(cmd) = serde_json::from_slice::<ManagerCmd>(&command) {
+                if let ManagerCmd::SubscribeEvents { subscription } = cmd {
+                    return Self::stream_events(stream, peer, subscription, subscription_sender).await;
+                }
+                if peer.authorize(&cmd).await {
+                    redact_account = matches!(cmd, ManagerCmd::GetStatus { .. }) && !peer.may_see_account().await;
                 } else {
-                    tracing::error!(message_id = "MldiAfVK", "client sent {n} more bytes than announced on socket stream");
+                    response = Some(
+                        serde_json::to_vec(&Err::<(), _>(ManagerCmdErrorCode::InsufficientPermissions)).map_err(|error| {
+                            tracing::error!(message_id = "Rk5wDn3X", ?error, "failed to encode authorization error: {error}");
+                        })?,
+                    );
                 }
-                Result::<(), ()>::Ok(())
-            });
-        };
-        _ = sender.send_async((message, Box::new(response_fn))).await;
+            }
+            let response = match response {
+                Some(response) => response,
+                None => match run_json_command(&sender, command, redact_account).await? {
+                    Some(response) => response,
+                    None => break,
+                },
+            };
+            if !framed {
+                return Self::write_unframed_response(stream, &response).await;
+            }
+            write_frame(&mut stream, &response).await.map_err(|error| {
+                tracing::error!(message_id = "XijfChPl", ?error, "failed to write response to socket stream: {error}");
+            })?;
+            message = Self::read_message(&mut stream).await?;
+        }
         tracing::info!(message_id = "lx2Z8pCr", "finished handling socket stream");
         Ok(())
     }
+
+    /// Returns `None` once the client closed the stream.
+    async fn read_message(stream: &mut UnixStream) -> Result<Option<Vec<u8>>, ()> {
+        let message = read_frame(stream, MAX_IPC_MESSAGE_LEN).await.map_err(|error| {
+            tracing::error!(message_id = "GFf8wiV3", ?error, "failed to read message from socket stream: {error}");
+        })?;
+        if message.is_none() {
+            tracing::info!(message_id = "CiLg0uHK", "client closed socket stream as expected");
+        }
+        Ok(message)
+    }
+
+    /// Responds to clients which didn't send a hello, which read the response until the stream is closed.
+    async fn write_unframed_response(mut stream: UnixStream, response: &[u8]) -> Result<(), ()> {
+        stream.write_all(response).await.map_err(|error| {
+            tracing::error!(message_id = "RRCdeq0M", ?error, "failed to write response to socket stream: {error}");
+        })?;
+        stream.shutdown().await.map_err(|error| {
+            tracing::error!(message_id = "hfdWDTcp", ?error, "failed to close socket write stream: {error}");
+        })?;
+        // Sockets closed for writing on both sides don't linger, even if there's unread data, so we need to wait for the client to signal it's done reading.
+        let n = stream.read(&mut [0u8; 1]).await.map_err(|error| {
+            tracing::error!(message_id = "g90YsnwQ", ?error, "failed to read clean EOF from socket stream: {error}");
+        })?;
+        if n != 0 {
+            tracing::error!(message_id = "MldiAfVK", "client sent {n} more bytes than announced on socket stream");
+        }
+        tracing::info!(message_id = "Ge2vMx8S", "finished handling socket stream of client without hello");
+        Ok(())
+    }
+
+    /// Writes events as frames until either side goes away. Slow clients don't block the manager, it drops events instead, see `ManagerEvent::Lagged`.
+    async fn stream_events(
+        stream: UnixStream,
+        peer: Peer,
+        subscription: EventSubscription,
+        subscription_sender: SubscriptionSender,
+    ) -> Result<(), ()> {
+        tracing::info!(message_id = "Pf6tGw1Z", ?subscription, "streaming events on socket stream");
+        let Some(mut events) = subscribe(&subscription_sender, subscription).await? else {
+            return Ok(());
+        };
+        let may_see_account = peer.may_see_account().await;
+        let (mut reader, mut writer) = stream.into_split();
+        let mut byte = [0u8; 1];
+        loop {
+            let event = tokio::select! {
+                event = events.recv() => event,
+                // Clients don't send anything after subscribing, so this only completes once they close the stream.
+                _ = reader.read(&mut byte) => None,
+            };
+            let Some(mut event) = event else {
+                break;
+            };
+            if let ManagerEvent::Status(status) = &mut event
+                && !may_see_account
+            {
+                status.redact_account();
+            }
+            let json = serde_json::to_vec(&event).map_err(|error| {
+                tracing::error!(message_id = "Hs7bVp3C", ?error, "failed to encode event: {error}");
+            })?;
+            write_frame(&mut writer, &json).await.map_err(|error| {
+                tracing::info!(message_id = "Nt5dJw9Q", ?error, "failed to write event to socket stream: {error}");
+            })?;
+        }
+        tracing::info!(message_id = "Ec3zLm7K", "finished streaming events on socket stream");
+        Ok(())
+    }
+}
+
+/// Passes a JSON encoded command to the service loop and returns the JSON encoded response. Returns `None` if the service doesn't accept commands anymore.
+pub async fn run_json_command(sender: &CommandSender, message: Vec<u8>, redact_account: bool) -> Result<Option<Vec<u8>>, ()> {
+    let (response_sender, response_receiver) = oneshot::channel();
+    let response_fn = move |response: Vec<u8>| {
+        _ = response_sender.send(response);
+    };
+    if sender.send_async((message, redact_account, Box::new(response_fn))).await.is_err() {
+        tracing::info!(message_id = "Wd3nRz6Y", "not accepting commands anymore");
+        return Ok(None);
+    }
+    let response = response_receiver.await.map_err(|error| {
+        tracing::error!(message_id = "Ja8sLp4X", ?error, "command was dropped without response: {error}");
+    })?;
+    Ok(Some(response))
+}
+
+/// Returns `None` if the service doesn't accept event subscriptions anymore.
+pub async fn subscribe(sender: &SubscriptionSender, subscription: EventSubscription) -> Result<Option<mpsc::Receiver<ManagerEvent>>, ()> {
+    let (events_sender, events_receiver) = oneshot::channel();
+    let events_fn: EventsFn = Box::new(move |events| {
+        _ = events_sender.send(events);
+    });
+    if sender.send_async((subscription, events_fn)).await.is_err() {
+        tracing::info!(message_id = "Mz2cHq5V", "not accepting event subscriptions anymore");
+        return Ok(None);
+    }
+    let events = events_receiver.await.map_err(|error| {
+        tracing::error!(message_id = "Ry4nXk8D", ?error, "event subscription was dropped: {error}");
+    })?;
+    Ok(Some(events))
 }
@@ -0,0 +1,300 @@
+//! Leak self-test, run on request while connected. Every check inspects live OS state instead of trusting what we believe we configured:
+//! - DNS probes: uniquely named hosts (never cached) are resolved through the OS resolver. With systemd-resolved the answering link is reported directly, otherwise the resolver route check below determines the path.
+//! - Resolver routes: unmarked traffic to the tunnel resolvers and every resolv.conf nameserver must be routed into the tun device.
+//! - Kill switch and capture rules: the nftables chain and policy routing rules must be present.
+//! - Direct routes: unmarked traffic to sample IPv4, IPv6 and STUN (WebRTC) destinations must be routed into the tun device or be unroutable.
+
+use crate::service::os::linux::LinuxOsImpl;
+use crate::service::os::linux::dns::DnsManager;
+use crate::service::os::linux::netfilter::{KillSwitchChain, NftTable};
+use crate::service::os::linux::routes::traffic_capture_routes::has_capture_rule;
+use futures::StreamExt;
+use obscuravpn_client::leak_test::{LeakTestCheck, LeakTestOutcome, LeakTestReport};
+use obscuravpn_client::manager::{Manager, VpnStatus};
+use obscuravpn_client::net::NetworkInterface;
+use obscuravpn_client::tokio::AbortOnDrop;
+use rtnetlink::packet_route::route::{RouteAttribute, RouteType};
+use rtnetlink::{IpVersion, RouteMessageBuilder};
+use std::collections::BTreeSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+use tokio::net::lookup_host;
+use tokio::time::timeout;
+use uuid::Uuid;
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Wildcard DNS services answering `<anything>.<a.b.c.d>.<domain>` with `a.b.c.d`, which allows uncacheable probe names with a known answer.
+const PROBE_DOMAINS: [&str; 2] = ["sslip.io", "nip.io"];
+const PROBE_ANSWER: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+const DIRECT_ROUTE_SAMPLES_IPV4: [Ipv4Addr; 2] = [Ipv4Addr::new(1, 1, 1, 1), Ipv4Addr::new(8, 8, 8, 8)];
+const DIRECT_ROUTE_SAMPLES_IPV6: [Ipv6Addr; 2] = [
+    Ipv6Addr::new(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1111),
+    Ipv6Addr::new(0x2001, 0x4860, 0x4860, 0, 0, 0, 0, 0x8888),
+];
+const STUN_SERVERS: [(&str, u16); 2] = [("stun.l.google.com", 19302), ("stun.cloudflare.com", 3478)];
+
+/// See https://www.freedesktop.org/software/systemd/man/latest/org.freedesktop.resolve1.html
+#[zbus::proxy(
+    interface = "org.freedesktop.resolve1.Manager",
+    default_service = "org.freedesktop.resolve1",
+    default_path = "/org/freedesktop/resolve1"
+)]
+trait Resolver {
+    fn resolve_hostname(&self, ifindex: i32, name: &str, family: i32, flags: u64) -> zbus::Result<(Vec<(i32, i32, Vec<u8>)>, String, u64)>;
+}
+
+impl LinuxOsImpl {
+    pub async fn run_leak_test(&self, manager: &Manager) -> LeakTestReport {
+        tracing::info!(message_id = "Nq3xTb7F", "running leak test");
+        let tun = self.tun.interface();
+        let mut checks = Vec::new();
+
+        let vpn_status = manager.subscribe().borrow().vpn_status.clone();
+        let connected = matches!(vpn_status, VpnStatus::Connected { .. });
+        checks.push(match &vpn_status {
+            VpnStatus::Connected { exit, .. } => LeakTestCheck::new("connected", LeakTestOutcome::Pass, vec![format!("connected to {}", exit.id)]),
+            _ => LeakTestCheck::new("connected", LeakTestOutcome::Fail, vec!["the VPN is not connected".to_owned()]),
+        });
+        if !connected {
+            return LeakTestReport::new(checks);
+        }
+
+        let tunnel_dns = match &*self.current_network_config.lock().await {
+            Ok(Some(config)) if !config.use_system_dns => config.dns.clone(),
+            Ok(Some(_)) | Ok(None) | Err(()) => Vec::new(),
+        };
+        let (connection, handle, _) = match rtnetlink::new_connection() {
+            Ok(connection) => connection,
+            Err(error) => {
+                tracing::error!(
+                    message_id = "Rv6nJk2T",
+                    ?error,
+                    "failed to create netlink connection for leak test: {error}"
+                );
+                checks.push(LeakTestCheck::new(
+                    "routes",
+                    LeakTestOutcome::Skipped,
+                    vec![format!("failed to create netlink connection: {error}")],
+                ));
+                return LeakTestReport::new(checks);
+            }
+        };
+        let _connection = AbortOnDrop::spawn(connection);
+        checks.push(dns_probes(&tun, self.dns_manager).await);
+        checks.push(resolver_routes(&handle, &tun, &tunnel_dns).await);
+        checks.push(kill_switch(&mut *self.nft.lock().await).await);
+        checks.push(capture_rules(&handle).await);
+        let ipv4_samples = DIRECT_ROUTE_SAMPLES_IPV4.map(IpAddr::from).to_vec();
+        checks.push(direct_routes("ipv4-routes", &handle, &tun, &ipv4_samples).await);
+        let ipv6_samples = DIRECT_ROUTE_SAMPLES_IPV6.map(IpAddr::from).to_vec();
+        checks.push(direct_routes("ipv6-routes", &handle, &tun, &ipv6_samples).await);
+        checks.push(webrtc_routes(&handle, &tun).await);
+
+        let report = LeakTestReport::new(checks);
+        tracing::info!(message_id = "Gv8kPw2D", ?report, "leak test finished");
+        report
+    }
+}
+
+async fn dns_probes(tun: &NetworkInterface, dns_manager: DnsManager) -> LeakTestCheck {
+    let mut outcome = LeakTestOutcome::Pass;
+    let mut details = Vec::new();
+    for domain in PROBE_DOMAINS {
+        let name = format!("obscura-leak-test-{}.{PROBE_ANSWER}.{domain}", Uuid::new_v4().simple());
+        let result = match dns_manager {
+            DnsManager::Resolved | DnsManager::Networkd => timeout(PROBE_TIMEOUT, resolve_with_resolved(tun, &name)).await,
+            DnsManager::Disabled | DnsManager::NetworkManager | DnsManager::ResolvConf => timeout(PROBE_TIMEOUT, resolve_with_system(&name)).await,
+        };
+        match result.unwrap_or_else(|_| Err("timed out".to_owned())) {
+            Ok(detail) => details.push(format!("{name}: {detail}")),
+            Err(detail) => {
+                outcome = LeakTestOutcome::Fail;
+                details.push(format!("{name}: {detail}"));
+            }
+        }
+    }
+    LeakTestCheck::new("dns-probes", outcome, details)
+}
+
+async fn resolve_with_resolved(tun: &NetworkInterface, name: &str) -> Result<String, String> {
+    let conn = zbus::Connection::system()
+        .await
+        .map_err(|error| format!("failed to connect to DBUS: {error}"))?;
+    let proxy = ResolverProxy::new(&conn)
+        .await
+        .map_err(|error| format!("failed to create resolved proxy: {error}"))?;
+    let (addresses, _canonical, _flags) = proxy
+        .resolve_hostname(0, name, libc::AF_INET, 0)
+        .await
+        .map_err(|error| format!("not answered: {error}"))?;
+    if addresses.is_empty() {
+        return Err("empty answer".to_owned());
+    }
+    let tun_index = i32::from(tun.index);
+    for (ifindex, _family, address) in addresses {
+        let address = <[u8; 4]>::try_from(address.as_slice()).ok().map(Ipv4Addr::from);
+        if address != Some(PROBE_ANSWER) {
+            return Err(format!("unexpected answer {address:?}"));
+        }
+        if ifindex != tun_index {
+            return Err(format!("answered via interface index {ifindex} instead of {} ({tun_index})", tun.name));
+        }
+    }
+    Ok(format!("answered via {}", tun.name))
+}
+
+async fn resolve_with_system(name: &str) -> Result<String, String> {
+    let addrs: BTreeSet<IpAddr> = lookup_host((name, 0))
+        .await
+        .map_err(|error| format!("not answered: {error}"))?
+        .map(|addr| addr.ip())
+        .collect();
+    if !addrs.contains(&IpAddr::from(PROBE_ANSWER)) {
+        return Err(format!("unexpected answer {addrs:?}"));
+    }
+    Ok("answered, resolver path verified by resolver routes".to_owned())
+}
+
+async fn resolver_routes(handle: &rtnetlink::Handle, tun: &NetworkInterface, tunnel_dns: &[IpAddr]) -> LeakTestCheck {
+    let mut resolvers: BTreeSet<IpAddr> = tunnel_dns.iter().copied().collect();
+    match tokio::fs::read_to_string("/etc/resolv.conf").await {
+        Ok(resolv_conf) => resolvers.extend(parse_nameservers(&resolv_conf).filter(|ip| !ip.is_loopback())),
+        Err(error) => tracing::warn!(message_id = "Lb5sKr9W", ?error, "failed to read resolv.conf for leak test: {error}"),
+    }
+    if resolvers.is_empty() {
+        return LeakTestCheck::new(
+            "resolver-routes",
+            LeakTestOutcome::Skipped,
+            vec!["no non-local resolvers configured".to_owned()],
+        );
+    }
+    let resolvers: Vec<IpAddr> = resolvers.into_iter().collect();
+    direct_routes("resolver-routes", handle, tun, &resolvers).await
+}
+
+fn parse_nameservers(resolv_conf: &str) -> impl Iterator<Item = IpAddr> + '_ {
+    resolv_conf.lines().filter_map(|line| {
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next()) {
+            // Strip IPv6 zone index (e.g. `fe80::1%eth0`)
+            (Some("nameserver"), Some(ip)) => ip.split('%').next()?.parse().ok(),
+            _ => None,
+        }
+    })
+}
+
+async fn webrtc_routes(handle: &rtnetlink::Handle, tun: &NetworkInterface) -> LeakTestCheck {
+    let mut samples = BTreeSet::new();
+    for server in STUN_SERVERS {
+        match timeout(PROBE_TIMEOUT, lookup_host(server)).await {
+            Ok(Ok(addrs)) => samples.extend(addrs.map(|addr| addr.ip())),
+            Ok(Err(error)) => tracing::warn!(message_id = "Xc2nHv6J", ?error, host = server.0, "failed to resolve STUN server: {error}"),
+            Err(_) => tracing::warn!(message_id = "Dt7qWm3Z", host = server.0, "timed out resolving STUN server"),
+        }
+    }
+    if samples.is_empty() {
+        return LeakTestCheck::new(
+            "webrtc-routes",
+            LeakTestOutcome::Skipped,
+            vec!["failed to resolve STUN servers".to_owned()],
+        );
+    }
+    let samples: Vec<IpAddr> = samples.into_iter().collect();
+    direct_routes("webrtc-routes", handle, tun, &samples).await
+}
+
+async fn direct_routes(name: &str, handle: &rtnetlink::Handle, tun: &NetworkInterface, destinations: &[IpAddr]) -> LeakTestCheck {
+    let tun_index = u32::from(tun.index);
+    let mut outcome = LeakTestOutcome::Pass;
+    let mut details = Vec::new();
+    for destination in destinations {
+        match route_interface(handle, *destination).await {
+            Ok(Some(index)) if index == tun_index => details.push(format!("{destination}: routed via {}", tun.name)),
+            Ok(Some(index)) => {
+                outcome = LeakTestOutcome::Fail;
+                details.push(format!("{destination}: routed via interface index {index}, bypassing {}", tun.name));
+            }
+            Ok(None) => details.push(format!("{destination}: unroutable")),
+            Err(error) => {
+                if outcome == LeakTestOutcome::Pass {
+                    outcome = LeakTestOutcome::Skipped;
+                }
+                details.push(format!("{destination}: route lookup failed: {error}"));
+            }
+        }
+    }
+    LeakTestCheck::new(name, outcome, details)
+}
+
+/// Returns the output interface index for unmarked traffic to `destination`, or `None` if there is no usable route. Equivalent to `ip route get <destination>`.
+async fn route_interface(handle: &rtnetlink::Handle, destination: IpAddr) -> Result<Option<u32>, rtnetlink::Error> {
+    let message = match destination {
+        IpAddr::V4(destination) => RouteMessageBuilder::<Ipv4Addr>::new().destination_prefix(destination, 32).build(),
+        IpAddr::V6(destination) => RouteMessageBuilder::<Ipv6Addr>::new().destination_prefix(destination, 128).build(),
+    };
+    let route = match handle.route().get(message).execute().next().await {
+        Some(Ok(route)) => route,
+        Some(Err(rtnetlink::Error::NetlinkError(error))) if matches!(error.to_io().raw_os_error(), Some(libc::ENETUNREACH | libc::EHOSTUNREACH)) => {
+            return Ok(None);
+        }
+        Some(Err(error)) => return Err(error),
+        None => return Ok(None),
+    };
+    if matches!(route.header.kind, RouteType::Unreachable | RouteType::BlackHole | RouteType::Prohibit) {
+        return Ok(None);
+    }
+    Ok(route.attributes.iter().find_map(|attribute| match attribute {
+        RouteAttribute::Oif(index) => Some(*index),
+        _ => None,
+    }))
+}
+
+async fn kill_switch(nft: &mut NftTable) -> LeakTestCheck {
+    const NAME: &str = "kill-switch";
+    match nft.kill_switch_chain().await {
+        Ok(Some(chain)) if chain.drops_at_postrouting() => {
+            LeakTestCheck::new(NAME, LeakTestOutcome::Pass, vec!["postrouting chain with drop policy present".to_owned()])
+        }
+        Ok(Some(KillSwitchChain { hook, policy })) => LeakTestCheck::new(
+            NAME,
+            LeakTestOutcome::Fail,
+            vec![format!("unexpected chain hook {hook:?}, policy {policy:?}")],
+        ),
+        Ok(None) => LeakTestCheck::new(NAME, LeakTestOutcome::Fail, vec!["chain missing".to_owned()]),
+        Err(()) => LeakTestCheck::new(NAME, LeakTestOutcome::Skipped, vec!["failed to read chain".to_owned()]),
+    }
+}
+
+async fn capture_rules(handle: &rtnetlink::Handle) -> LeakTestCheck {
+    const NAME: &str = "capture-rules";
+    let mut outcome = LeakTestOutcome::Pass;
+    let mut details = Vec::new();
+    for (family, ip_version) in [("IPv4", IpVersion::V4), ("IPv6", IpVersion::V6)] {
+        match has_capture_rule(handle, ip_version).await {
+            Ok(true) => details.push(format!("{family}: capture rule present")),
+            Ok(false) => {
+                outcome = LeakTestOutcome::Fail;
+                details.push(format!("{family}: capture rule missing"));
+            }
+            Err(error) => {
+                if outcome == LeakTestOutcome::Pass {
+                    outcome = LeakTestOutcome::Skipped;
+                }
+                details.push(format!("{family}: rule lookup failed: {error}"));
+            }
+        }
+    }
+    LeakTestCheck::new(NAME, outcome, details)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_nameservers() {
+        let resolv_conf = "# comment\nnameserver 10.64.0.1\nnameserver fe80::1%eth0\nsearch example.com\nnameserver\nnameserver bogus\n";
+        let nameservers: Vec<IpAddr> = parse_nameservers(resolv_conf).collect();
+        assert_eq!(nameservers, ["10.64.0.1".parse::<IpAddr>().unwrap(), "fe80::1".parse().unwrap()]);
+    }
+}
@@ -1,15 +1,28 @@
+mod captive_portal;
+mod dbus;
 pub mod dns;
 mod fd_store;
+pub mod hooks;
+pub mod http_api;
 pub mod ipc;
+mod leak_test;
 mod netfilter;
+pub mod network_identity;
 mod network_manager;
+mod peer_access;
+mod polkit;
+pub mod provisioning;
 pub mod routes;
 mod service_lock;
 pub mod start_error;
 pub mod tun;
 
-use crate::service::os::linux::dns::{DnsManager, DnsManagerArg, choose_dns_manager, resolved};
+use crate::service::os::linux::captive_portal::ActiveBypass;
+use crate::service::os::linux::dns::resolv_conf::ResolvConf;
+use crate::service::os::linux::dns::watchdog::{DnsTarget, DnsWatchdog, spawn_dns_watchdog};
+use crate::service::os::linux::dns::{DnsManager, DnsManagerArg, choose_dns_manager, networkd, resolved};
 use crate::service::os::linux::fd_store::FdStore;
+use crate::service::os::linux::http_api::HttpApiConfig;
 use crate::service::os::linux::ipc::ServiceIpc;
 use crate::service::os::linux::netfilter::NftTable;
 use crate::service::os::linux::routes::preferred_interface::watch_preferred_network_interface;
@@ -18,18 +31,31 @@ use crate::service::os::linux::service_lock::ServiceLock;
 use crate::service::os::linux::tun::Tun;
 use bytes::Bytes;
 use obscuravpn_client::manager_cmd::{ManagerCmd, ManagerCmdErrorCode, ManagerCmdOk};
+use obscuravpn_client::manager_event::{EventSubscription, ManagerEvent};
 use obscuravpn_client::net::NetworkInterface;
 use obscuravpn_client::network_config::OsNetworkConfig;
 use obscuravpn_client::os::os_trait::Os;
 use obscuravpn_client::quicwg::QuicWgConnPacketSender;
 pub use start_error::LinuxServiceStartError;
 use std::net::IpAddr;
-use tokio::sync::Mutex;
 use tokio::sync::watch::{Receiver, Sender};
+use tokio::sync::{Mutex, mpsc};
+
+/// Settings locked by the administrator, see `Policy`. Only read when the service starts.
+pub const POLICY_PATH: &str = "/etc/obscura/policy.json";
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum TrafficPolicy {
-    Engage { local_network_access: bool, dns: Vec<IpAddr> },
+    Engage {
+        local_network_access: bool,
+        dns: Vec<IpAddr>,
+    },
+    /// Traffic isn't captured, but the kill switch only lets DNS to the network's resolvers and HTTP and HTTPS to the portal through, so a browser can log in to the portal. Plain HTTP is let through to any address while the portal's addresses are unknown.
+    BypassCaptivePortal {
+        local_network_access: bool,
+        portal: Vec<IpAddr>,
+        resolvers: Vec<IpAddr>,
+    },
     Disengage,
 }
 
@@ -39,24 +65,39 @@ pub struct LinuxOsImpl {
     routing: Sender<TrafficPolicy>,
     preferred_network_interface: Receiver<Option<NetworkInterface>>,
     current_network_config: tokio::sync::Mutex<Result<Option<OsNetworkConfig>, ()>>,
-    dns_manager_arg: DnsManagerArg,
+    /// Chosen once at start, so DNS is reset through the same manager it was set with.
+    dns_manager: DnsManager,
+    resolv_conf: ResolvConf,
+    dns_watchdog: Sender<Option<DnsTarget>>,
+    dns_watchdog_interventions: Receiver<u64>,
+    captive_portal_bypass: std::sync::Mutex<Option<ActiveBypass>>,
     ipc: ServiceIpc,
     _lock: ServiceLock,
 }
 
 impl LinuxOsImpl {
-    pub async fn new(dns_manager_arg: DnsManagerArg) -> Result<Self, LinuxServiceStartError> {
+    pub async fn new(
+        dns_manager_arg: DnsManagerArg,
+        runtime_dir: Option<&str>,
+        is_restart: bool,
+        http_api: Option<HttpApiConfig>,
+    ) -> Result<Self, LinuxServiceStartError> {
         let lock: ServiceLock = ServiceLock::new()?;
-        choose_dns_manager(dns_manager_arg)
-            .await
-            .map_err(|()| LinuxServiceStartError::NoDnsManager)?;
-        let ipc = ServiceIpc::new(&lock).await?;
+        let resolv_conf = ResolvConf::new(runtime_dir);
+        if !is_restart {
+            resolv_conf.recover();
+        }
+        let ipc = ServiceIpc::new(&lock, http_api).await?;
 
         let mut fd_store = FdStore::take_from_systemd();
         let nft = NftTable::create_or_adopt(&mut fd_store).map_err(|()| LinuxServiceStartError::NftablesSetup)?;
         fd_store.remove_unclaimed();
         let tun = Tun::create()?;
+        let dns_manager = choose_dns_manager(dns_manager_arg, &resolv_conf, &tun.interface())
+            .await
+            .map_err(|()| LinuxServiceStartError::NoDnsManager)?;
         let routing = spawn_route_enforcer(tun.interface()).await;
+        let DnsWatchdog { target: dns_watchdog, interventions: dns_watchdog_interventions } = spawn_dns_watchdog(tun.interface());
         let preferred_network_interface = watch_preferred_network_interface().await;
         let _ = enable_src_valid_mark();
         notify_ready();
@@ -68,46 +109,30 @@ impl LinuxOsImpl {
             routing,
             preferred_network_interface,
             current_network_config: Ok(None).into(),
-            dns_manager_arg,
+            dns_manager,
+            resolv_conf,
+            dns_watchdog,
+            dns_watchdog_interventions,
+            captive_portal_bypass: None.into(),
         })
     }
 
     pub fn network_interface(&self) -> Receiver<Option<NetworkInterface>> {
         self.preferred_network_interface.clone()
     }
+
+    pub fn dns_watchdog_interventions(&self) -> Receiver<u64> {
+        self.dns_watchdog_interventions.clone()
+    }
 }
 
 impl Os for LinuxOsImpl {
     async fn set_os_network_config(&self, network_config: OsNetworkConfig, tunnel: QuicWgConnPacketSender) -> Result<(), ()> {
         let mut current_network_config = self.current_network_config.lock().await;
-        let tun = self.tun.interface();
-
-        // Attempt all config steps regardless of individual failures to minimize leaks until intentionally disconnecting. E.g. DNS queries shouldn't leak because route setup failed.
-        let mut result = Ok(());
-        let policy = TrafficPolicy::Engage {
-            local_network_access: network_config.local_network_access,
-            dns: if network_config.use_system_dns {
-                vec![]
-            } else {
-                network_config.dns.clone()
-            },
-        };
-        result = result.and(self.routing.send(policy.clone()).map_err(|error| {
-            tracing::error!(message_id = "bK3wNr8T", ?error, "route enforcer is not running");
-        }));
-        match choose_dns_manager(self.dns_manager_arg).await? {
-            DnsManager::NetworkManager => result = result.and(network_manager::set_dns(&tun, &network_config).await),
-            dns_manager => {
-                if dns_manager.is_resolved() {
-                    if network_config.use_system_dns {
-                        result = result.and(resolved::reset_dns(&tun).await);
-                    } else {
-                        result = result.and(resolved::set_dns(&tun, &network_config.dns).await);
-                    }
-                }
-            }
+        if tunnel.has_tunnel() {
+            self.end_captive_portal_bypass();
         }
-        result = result.and(self.nft.lock().await.apply_ruleset(policy, &tun.name).await);
+        let mut result = self.apply_network_config(&network_config).await;
         result = result.and(self.tun.set_config(network_config.mtu, network_config.ipv4, network_config.ipv6));
         *current_network_config = result.map(|_| Some(network_config));
 
@@ -122,8 +147,11 @@ impl Os for LinuxOsImpl {
         result = result.and(self.routing.send(TrafficPolicy::Disengage).map_err(|error| {
             tracing::error!(message_id = "fZ8pQm2W", ?error, "route enforcer is not running");
         }));
-        match choose_dns_manager(self.dns_manager_arg).await? {
+        self.dns_watchdog.send_replace(None);
+        match self.dns_manager {
             DnsManager::NetworkManager => result = result.and(network_manager::reset_dns(&tun).await),
+            DnsManager::Networkd => result = result.and(networkd::reset_dns(&tun).await),
+            DnsManager::ResolvConf => result = result.and(self.resolv_conf.reset_dns()),
             dns_manager => {
                 if dns_manager.is_resolved() {
                     result = result.and(resolved::reset_dns(&tun).await);
@@ -141,11 +169,60 @@ impl Os for LinuxOsImpl {
 }
 
 impl LinuxOsImpl {
+    /// Applies routing, DNS and firewall for an engaged tunnel. While a captive portal bypass is active, traffic isn't captured and the system DNS is used instead, see `TrafficPolicy::BypassCaptivePortal`.
+    async fn apply_network_config(&self, network_config: &OsNetworkConfig) -> Result<(), ()> {
+        let tun = self.tun.interface();
+        let captive_portal = self.active_captive_portal_bypass();
+        let network_config = &OsNetworkConfig { use_system_dns: network_config.use_system_dns || captive_portal.is_some(), ..network_config.clone() };
+
+        // Attempt all config steps regardless of individual failures to minimize leaks until intentionally disconnecting. E.g. DNS queries shouldn't leak because route setup failed.
+        let mut result = Ok(());
+        let local_network_access = network_config.local_network_access;
+        let policy = match captive_portal {
+            Some((portal, resolvers)) => TrafficPolicy::BypassCaptivePortal { local_network_access, portal, resolvers },
+            None => TrafficPolicy::Engage {
+                local_network_access,
+                dns: if network_config.use_system_dns {
+                    vec![]
+                } else {
+                    network_config.dns.clone()
+                },
+            },
+        };
+        result = result.and(self.routing.send(policy.clone()).map_err(|error| {
+            tracing::error!(message_id = "bK3wNr8T", ?error, "route enforcer is not running");
+        }));
+        self.dns_watchdog.send_replace(None);
+        let use_system_dns = network_config.use_system_dns;
+        let dns_result = match self.dns_manager {
+            DnsManager::NetworkManager => network_manager::set_dns(&tun, network_config).await,
+            DnsManager::Networkd if use_system_dns => networkd::reset_dns(&tun).await,
+            DnsManager::Networkd => networkd::set_dns(&tun, &network_config.dns).await,
+            DnsManager::ResolvConf if use_system_dns => self.resolv_conf.reset_dns(),
+            DnsManager::ResolvConf => self.resolv_conf.set_dns(&network_config.dns),
+            DnsManager::Resolved if use_system_dns => resolved::reset_dns(&tun).await,
+            DnsManager::Resolved => resolved::set_dns(&tun, &network_config.dns).await,
+            DnsManager::Disabled => Ok(()),
+        };
+        // Only settings that were applied can be enforced.
+        if dns_result.is_ok() && !use_system_dns {
+            self.dns_watchdog
+                .send_replace(Some(DnsTarget { dns_manager: self.dns_manager, network_config: network_config.clone() }));
+        }
+        result = result.and(dns_result);
+        result.and(self.nft.lock().await.apply_ruleset(policy, &tun.name).await)
+    }
+
     /// Returns next manager command. Blocks until a command is available. The response function is called with the command result.
     pub async fn next_manager_command(&self) -> (ManagerCmd, Box<dyn FnOnce(Result<ManagerCmdOk, ManagerCmdErrorCode>) + Send>) {
         loop {
-            let (json_cmd, response_fn) = self.ipc.next().await;
-            let response_fn = move |result: Result<ManagerCmdOk, ManagerCmdErrorCode>| {
+            let (json_cmd, redact_account, response_fn) = self.ipc.next().await;
+            let response_fn = move |mut result: Result<ManagerCmdOk, ManagerCmdErrorCode>| {
+                if let Ok(ManagerCmdOk::GetStatus(status)) = &mut result
+                    && redact_account
+                {
+                    status.redact_account();
+                }
                 let json_response = serde_json::to_vec(&result)
                     .map_err(|error| {
                         tracing::error!(message_id = "8Jj0yWQt", ?error, "failed to encode command result: {}", error);
@@ -160,6 +237,11 @@ impl LinuxOsImpl {
             }
         }
     }
+
+    /// Returns next event subscription. Blocks until a subscription is available. The function is called with the subscribed event receiver.
+    pub async fn next_event_subscription(&self) -> (EventSubscription, Box<dyn FnOnce(mpsc::Receiver<ManagerEvent>) + Send>) {
+        self.ipc.next_event_subscription().await
+    }
 }
 
 fn notify_ready() {
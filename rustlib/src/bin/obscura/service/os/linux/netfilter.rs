@@ -34,6 +34,13 @@
 //!         oifname "obscuravpn" accept
 //!         # Tunnel resolver traffic may only leave via the tun device.
 //!         ip daddr 10.64.0.1 drop
+//!         # Captive portal bypass, rendered instead of the tunnel resolver rules while a bypass is active. Traffic isn't captured meanwhile, so this reaches the physical network.
+//!         # DNS only to the network's resolvers, none are accepted if they are unknown.
+//!         ip daddr 192.168.1.1 udp dport 53 accept
+//!         ip daddr 192.168.1.1 tcp dport 53 accept
+//!         # HTTP and HTTPS only to the portal's addresses. While they are unknown, HTTP to any address is accepted instead.
+//!         ip daddr 192.0.2.1 tcp dport 80 accept
+//!         ip daddr 192.0.2.1 tcp dport 443 accept
 //!         # Link scope DHCPv4 traffic.
 //!         ip daddr 255.255.255.255 udp sport 68 udp dport 67 accept
 //!         # Link scope DHCPv6 traffic.
@@ -81,6 +88,7 @@ const NLM_F_CREATE: u16 = try_c_int_into_u16(libc::NLM_F_CREATE).unwrap();
 const NLM_F_APPEND: u16 = try_c_int_into_u16(libc::NLM_F_APPEND).unwrap();
 const NLMSG_ERROR: u16 = try_c_int_into_u16(libc::NLMSG_ERROR).unwrap();
 const NLA_F_NESTED: u16 = try_c_int_into_u16(libc::NLA_F_NESTED).unwrap();
+const NLA_TYPE_MASK: u16 = try_c_int_into_u16(libc::NLA_TYPE_MASK).unwrap();
 
 const NFNETLINK_V0: u8 = try_c_int_into_u8(libc::NFNETLINK_V0).unwrap();
 const NFNL_SUBSYS_NFTABLES: u16 = try_c_int_into_u16(libc::NFNL_SUBSYS_NFTABLES).unwrap();
@@ -88,6 +96,7 @@ const NFNL_MSG_BATCH_BEGIN: u16 = try_c_int_into_u16(libc::NFNL_MSG_BATCH_BEGIN)
 const NFNL_MSG_BATCH_END: u16 = try_c_int_into_u16(libc::NFNL_MSG_BATCH_END).unwrap();
 const NFT_MSG_NEWTABLE: u16 = (NFNL_SUBSYS_NFTABLES << 8) | try_c_int_into_u16(libc::NFT_MSG_NEWTABLE).unwrap();
 const NFT_MSG_NEWCHAIN: u16 = (NFNL_SUBSYS_NFTABLES << 8) | try_c_int_into_u16(libc::NFT_MSG_NEWCHAIN).unwrap();
+const NFT_MSG_GETCHAIN: u16 = (NFNL_SUBSYS_NFTABLES << 8) | try_c_int_into_u16(libc::NFT_MSG_GETCHAIN).unwrap();
 const NFT_MSG_NEWRULE: u16 = (NFNL_SUBSYS_NFTABLES << 8) | try_c_int_into_u16(libc::NFT_MSG_NEWRULE).unwrap();
 const NFT_MSG_DESTROYTABLE: u16 = (NFNL_SUBSYS_NFTABLES << 8) | 26;
 
@@ -164,6 +173,7 @@ const NFTA_BITWISE_LEN: u16 = 3;
 const NFTA_BITWISE_MASK: u16 = 4;
 const NFTA_BITWISE_XOR: u16 = 5;
 
+const IPPROTO_TCP: u8 = try_c_int_into_u8(libc::IPPROTO_TCP).unwrap();
 const IPPROTO_UDP: u8 = try_c_int_into_u8(libc::IPPROTO_UDP).unwrap();
 const IPPROTO_ICMPV6: u8 = try_c_int_into_u8(libc::IPPROTO_ICMPV6).unwrap();
 
@@ -174,6 +184,10 @@ const ND_NEIGHBOR_ADVERT: u8 = 136;
 const IPV4_DADDR_OFFSET: u32 = 16;
 const IPV6_DADDR_OFFSET: u32 = 24;
 
+const PORT_DNS: u16 = 53;
+const PORT_HTTP: u16 = 80;
+const PORT_HTTPS: u16 = 443;
+
 const FD_NAME_NFT: &str = "nft";
 
 const TABLE_NAME: &str = "obscura";
@@ -186,6 +200,19 @@ pub struct NftTable {
     last_unchecked_seq: Option<u32>,
 }
 
+/// The kill switch chain as reported by the kernel.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct KillSwitchChain {
+    pub hook: Option<u32>,
+    pub policy: Option<u32>,
+}
+
+impl KillSwitchChain {
+    pub fn drops_at_postrouting(&self) -> bool {
+        self.hook == Some(NF_INET_POST_ROUTING) && self.policy == Some(NF_DROP)
+    }
+}
+
 impl NftTable {
     pub fn create_or_adopt(fd_store: &mut FdStore) -> Result<Self, ()> {
         let socket = match fd_store.take(FD_NAME_NFT) {
@@ -284,6 +311,50 @@ impl NftTable {
         Ok(())
     }
 
+    /// Reads the kill switch chain back from the kernel, `None` if it doesn't exist.
+    pub async fn kill_switch_chain(&mut self) -> Result<Option<KillSwitchChain>, ()> {
+        self.discard_stale_replies();
+        let seq = self.next_seq();
+        let mut get = Msg::new(NFT_MSG_GETCHAIN, NLM_F_REQUEST | NLM_F_ACK, seq, NFPROTO_INET, 0);
+        get.attr_str(NFTA_CHAIN_TABLE, TABLE_NAME);
+        get.attr_str(NFTA_CHAIN_NAME, CHAIN_KILL_SWITCH);
+        self.send_batch(&get.finish()).await?;
+        let mut chain = None;
+        let mut buf = vec![0u8; 1 << 16];
+        loop {
+            let n = self.recv(&mut buf).await?;
+            for (msg_type, msg_seq, payload) in netlink_messages(&buf[..n])? {
+                if msg_seq != seq {
+                    continue;
+                }
+                if msg_type == NFT_MSG_NEWCHAIN {
+                    chain = Some(parse_chain(payload));
+                } else if msg_type == NLMSG_ERROR {
+                    self.last_unchecked_seq = None;
+                    return match error_code(payload)? {
+                        0 => Ok(chain),
+                        code if -code == libc::ENOENT => Ok(None),
+                        code => {
+                            tracing::error!(message_id = "Hx7kWq3N", error = ?io::Error::from_raw_os_error(-code), "failed to get kill switch chain");
+                            Err(())
+                        }
+                    };
+                }
+            }
+        }
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> Result<usize, ()> {
+        self.socket
+            .async_io(Interest::READABLE, |socket| {
+                recv(socket.as_raw_fd(), buf, MsgFlags::empty()).map_err(io::Error::from)
+            })
+            .await
+            .map_err(|error| {
+                tracing::error!(message_id = "zR9mBc5X", ?error, "failed to receive nftables reply");
+            })
+    }
+
     async fn send_batch(&mut self, batch: &[u8]) -> Result<(), ()> {
         let sent = self
             .socket
@@ -341,30 +412,10 @@ impl NftTable {
         };
         let mut buf = vec![0u8; 1 << 16];
         loop {
-            let n = self
-                .socket
-                .async_io(Interest::READABLE, |socket| {
-                    recv(socket.as_raw_fd(), &mut buf, MsgFlags::empty()).map_err(io::Error::from)
-                })
-                .await
-                .map_err(|error| {
-                    tracing::error!(message_id = "zR9mBc5X", ?error, "failed to receive nftables reply");
-                })?;
-            let mut offset = 0;
-            while offset + 16 <= n {
-                let len = u32_into_usize(u32::from_ne_bytes(buf[offset..offset + 4].try_into().unwrap()));
-                let msg_type = u16::from_ne_bytes(buf[offset + 4..offset + 6].try_into().unwrap());
-                let seq = u32::from_ne_bytes(buf[offset + 8..offset + 12].try_into().unwrap());
-                if len < 16 || len > n - offset {
-                    tracing::error!(message_id = "eK4sVw7H", len, offset, n, "truncated netlink message");
-                    return Err(());
-                }
+            let n = self.recv(&mut buf).await?;
+            for (msg_type, seq, payload) in netlink_messages(&buf[..n])? {
                 if msg_type == NLMSG_ERROR {
-                    if len < 20 {
-                        tracing::error!(message_id = "tG2xLp6M", len, "truncated netlink error message");
-                        return Err(());
-                    }
-                    let code = i32::from_ne_bytes(buf[offset + 16..offset + 20].try_into().unwrap());
+                    let code = error_code(payload)?;
                     if code != 0 {
                         tracing::error!(message_id = "dQ8nFy3C", seq, error = ?io::Error::from_raw_os_error(-code), "nftables batch rejected");
                         return Err(());
@@ -373,12 +424,73 @@ impl NftTable {
                         return Ok(());
                     }
                 }
-                offset += len.next_multiple_of(4);
             }
         }
     }
 }
 
+/// Splits received netlink messages into type, seq and payload (everything after nlmsghdr).
+fn netlink_messages(buf: &[u8]) -> Result<Vec<(u16, u32, &[u8])>, ()> {
+    let mut messages = Vec::new();
+    let mut offset = 0;
+    while offset + 16 <= buf.len() {
+        let len = u32_into_usize(u32::from_ne_bytes(buf[offset..offset + 4].try_into().unwrap()));
+        let msg_type = u16::from_ne_bytes(buf[offset + 4..offset + 6].try_into().unwrap());
+        let seq = u32::from_ne_bytes(buf[offset + 8..offset + 12].try_into().unwrap());
+        if len < 16 || len > buf.len() - offset {
+            tracing::error!(message_id = "eK4sVw7H", len, offset, n = buf.len(), "truncated netlink message");
+            return Err(());
+        }
+        messages.push((msg_type, seq, &buf[offset + 16..offset + len]));
+        offset += len.next_multiple_of(4);
+    }
+    Ok(messages)
+}
+
+/// Error code of an `NLMSG_ERROR` payload, 0 for acks.
+fn error_code(payload: &[u8]) -> Result<i32, ()> {
+    match payload.get(..4) {
+        Some(code) => Ok(i32::from_ne_bytes(code.try_into().unwrap())),
+        None => {
+            tracing::error!(message_id = "tG2xLp6M", len = payload.len(), "truncated netlink error message");
+            Err(())
+        }
+    }
+}
+
+/// Splits netlink attributes into type (without flags) and payload, ignoring anything malformed.
+fn attributes(mut data: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut attributes = Vec::new();
+    while data.len() >= 4 {
+        let len = usize::from(u16::from_ne_bytes([data[0], data[1]]));
+        let kind = u16::from_ne_bytes([data[2], data[3]]) & NLA_TYPE_MASK;
+        if len < 4 || len > data.len() {
+            break;
+        }
+        attributes.push((kind, &data[4..len]));
+        data = &data[len.next_multiple_of(4).min(data.len())..];
+    }
+    attributes
+}
+
+/// Parses the payload of a `NFT_MSG_NEWCHAIN` message: nfgenmsg followed by chain attributes.
+fn parse_chain(payload: &[u8]) -> KillSwitchChain {
+    let be_u32 = |data: &[u8]| <[u8; 4]>::try_from(data).ok().map(u32::from_be_bytes);
+    let mut chain = KillSwitchChain::default();
+    for (kind, data) in attributes(payload.get(4..).unwrap_or_default()) {
+        match kind {
+            NFTA_CHAIN_HOOK => {
+                chain.hook = attributes(data)
+                    .into_iter()
+                    .find_map(|(kind, data)| if kind == NFTA_HOOK_HOOKNUM { be_u32(data) } else { None })
+            }
+            NFTA_CHAIN_POLICY => chain.policy = be_u32(data),
+            _ => {}
+        }
+    }
+    chain
+}
+
 struct Chain {
     name: &'static str,
     hook: u32,
@@ -407,13 +519,17 @@ fn chains(policy: &TrafficPolicy, tun_name: &str) -> Vec<Chain> {
         },
     ];
     match policy {
-        TrafficPolicy::Engage { local_network_access, dns } => chains.push(kill_switch_chain(*local_network_access, dns, tun_name)),
+        TrafficPolicy::Engage { local_network_access, dns } => chains.push(kill_switch_chain(*local_network_access, dns, None, tun_name)),
+        TrafficPolicy::BypassCaptivePortal { local_network_access, portal, resolvers } => {
+            chains.push(kill_switch_chain(*local_network_access, &[], Some((portal, resolvers)), tun_name))
+        }
         TrafficPolicy::Disengage => {}
     }
     chains
 }
 
-fn kill_switch_chain(local_network_access: bool, dns: &[IpAddr], tun_name: &str) -> Chain {
+/// `captive_portal` are the portal's addresses and the network's resolvers while bypassing for a captive portal.
+fn kill_switch_chain(local_network_access: bool, dns: &[IpAddr], captive_portal: Option<(&[IpAddr], &[IpAddr])>, tun_name: &str) -> Chain {
     use Expr::*;
     let mut rules = vec![
         vec![MetaLoad(NFT_META_OIFNAME), CmpEq(b"lo\0".to_vec()), Accept],
@@ -426,6 +542,21 @@ fn kill_switch_chain(local_network_access: bool, dns: &[IpAddr], tun_name: &str)
             IpAddr::V6(ip) => daddr_rule(AF_INET6, IPV6_DADDR_OFFSET, ip.octets().to_vec(), None, Drop),
         });
     }
+    if let Some((portal, resolvers)) = captive_portal {
+        for ip in resolvers {
+            rules.extend([dport_rule(IPPROTO_UDP, PORT_DNS, Some(*ip)), dport_rule(IPPROTO_TCP, PORT_DNS, Some(*ip))]);
+        }
+        // Without known addresses, the portal can only be reached through plain HTTP requests it intercepts.
+        if portal.is_empty() {
+            rules.push(dport_rule(IPPROTO_TCP, PORT_HTTP, None));
+        }
+        for ip in portal {
+            rules.extend([
+                dport_rule(IPPROTO_TCP, PORT_HTTP, Some(*ip)),
+                dport_rule(IPPROTO_TCP, PORT_HTTPS, Some(*ip)),
+            ]);
+        }
+    }
     rules.extend([
         dhcp_rule(AF_INET, IPV4_DADDR_OFFSET, Ipv4Addr::BROADCAST.octets().to_vec(), 68, 67),
         dhcp_rule(
@@ -494,6 +625,32 @@ fn dhcp_rule(nfproto: u8, daddr_offset: u32, daddr: Vec<u8>, sport: u16, dport:
     ]
 }
 
+fn dport_rule(l4proto: u8, dport: u16, daddr: Option<IpAddr>) -> Vec<Expr> {
+    use Expr::*;
+    let mut exprs = Vec::new();
+    if let Some(daddr) = daddr {
+        let (nfproto, offset, daddr) = match daddr {
+            IpAddr::V4(ip) => (AF_INET, IPV4_DADDR_OFFSET, ip.octets().to_vec()),
+            IpAddr::V6(ip) => (AF_INET6, IPV6_DADDR_OFFSET, ip.octets().to_vec()),
+        };
+        let len = u32::try_from(daddr.len()).unwrap();
+        exprs.extend([
+            MetaLoad(NFT_META_NFPROTO),
+            CmpEq(vec![nfproto]),
+            Payload { base: NFT_PAYLOAD_NETWORK_HEADER, offset, len },
+            CmpEq(daddr),
+        ]);
+    }
+    exprs.extend([
+        MetaLoad(NFT_META_L4PROTO),
+        CmpEq(vec![l4proto]),
+        Payload { base: NFT_PAYLOAD_TRANSPORT_HEADER, offset: 2, len: 2 },
+        CmpEq(dport.to_be_bytes().to_vec()),
+        Accept,
+    ]);
+    exprs
+}
+
 fn daddr_rule(nfproto: u8, offset: u32, network: Vec<u8>, mask: Option<Vec<u8>>, verdict: Expr) -> Vec<Expr> {
     use Expr::*;
     let len = u32::try_from(network.len()).unwrap();
@@ -516,6 +673,7 @@ fn nul_terminated(value: &str) -> Vec<u8> {
     bytes
 }
 
+#[derive(Debug, PartialEq, Eq)]
 enum Expr {
     MetaLoad(u32),
     MetaSetMark,
@@ -644,3 +802,61 @@ impl Msg {
         self.buf
     }
 }
+
+#[test]
+fn test_parse_chain() {
+    let mut msg = Msg::new(NFT_MSG_NEWCHAIN, 0, 7, NFPROTO_INET, 0);
+    msg.attr_str(NFTA_CHAIN_TABLE, TABLE_NAME);
+    msg.attr_str(NFTA_CHAIN_NAME, CHAIN_KILL_SWITCH);
+    msg.nested(NFTA_CHAIN_HOOK, |hook| {
+        hook.attr_u32_be(NFTA_HOOK_HOOKNUM, NF_INET_POST_ROUTING);
+        hook.attr_u32_be(NFTA_HOOK_PRIORITY, NF_IP_PRI_FILTER.cast_unsigned());
+    });
+    msg.attr_u32_be(NFTA_CHAIN_POLICY, NF_DROP);
+    let buf = msg.finish();
+    let messages = netlink_messages(&buf).unwrap();
+    assert_eq!(messages.len(), 1);
+    let (msg_type, seq, payload) = messages[0];
+    assert_eq!((msg_type, seq), (NFT_MSG_NEWCHAIN, 7));
+    let chain = parse_chain(payload);
+    assert_eq!(chain, KillSwitchChain { hook: Some(NF_INET_POST_ROUTING), policy: Some(NF_DROP) });
+    assert!(chain.drops_at_postrouting());
+    assert!(!KillSwitchChain { hook: Some(NF_INET_POST_ROUTING), policy: Some(NF_ACCEPT) }.drops_at_postrouting());
+    assert!(netlink_messages(&buf[..buf.len() - 4]).is_err());
+}
+
+#[test]
+fn test_bypass_captive_portal_chain() {
+    let portal: IpAddr = "192.0.2.1".parse().unwrap();
+    let resolver: IpAddr = "2001:db8::53".parse().unwrap();
+    let kill_switch_rules = |portal: Vec<IpAddr>| {
+        let policy = TrafficPolicy::BypassCaptivePortal { local_network_access: false, portal, resolvers: vec![resolver] };
+        chains(&policy, "obscura")
+            .into_iter()
+            .find(|chain| chain.name == CHAIN_KILL_SWITCH)
+            .unwrap()
+            .rules
+    };
+
+    let rules = kill_switch_rules(vec![portal]);
+    for rule in [
+        dport_rule(IPPROTO_UDP, PORT_DNS, Some(resolver)),
+        dport_rule(IPPROTO_TCP, PORT_DNS, Some(resolver)),
+        dport_rule(IPPROTO_TCP, PORT_HTTP, Some(portal)),
+        dport_rule(IPPROTO_TCP, PORT_HTTPS, Some(portal)),
+    ] {
+        assert!(rules.contains(&rule), "{rule:?}");
+    }
+    for rule in [
+        dport_rule(IPPROTO_UDP, PORT_DNS, None),
+        dport_rule(IPPROTO_TCP, PORT_DNS, None),
+        dport_rule(IPPROTO_TCP, PORT_HTTP, None),
+        dport_rule(IPPROTO_TCP, PORT_HTTPS, None),
+    ] {
+        assert!(!rules.contains(&rule), "{rule:?}");
+    }
+
+    let rules = kill_switch_rules(Vec::new());
+    assert!(rules.contains(&dport_rule(IPPROTO_TCP, PORT_HTTP, None)));
+    assert!(!rules.iter().any(|rule| rule.contains(&Expr::CmpEq(PORT_HTTPS.to_be_bytes().to_vec()))));
+}
@@ -0,0 +1,169 @@
+//! Identifies the network behind the preferred network interface, so the manager can apply `NetworkRule`s. The gateway is only looked up for IPv4.
+
+use crate::service::os::linux::network_manager;
+use obscuravpn_client::manager::Manager;
+use obscuravpn_client::net::NetworkInterface;
+use obscuravpn_client::network_rules::NetworkIdentity;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::select;
+use tokio::sync::watch::Receiver;
+use tokio::time::sleep;
+
+/// The gateway's neighbour entry only appears once traffic was sent to it, which usually happens right after joining the network.
+const GATEWAY_MAC_ATTEMPTS: usize = 3;
+const GATEWAY_MAC_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// If the neighbour entry didn't appear in time, it is looked up again for a while, any traffic to the internet resolves it eventually.
+const GATEWAY_MAC_POLL_ATTEMPTS: usize = 30;
+const GATEWAY_MAC_POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// Set in `/proc/net/arp` for resolved entries.
+const ATF_COM: u32 = 0x2;
+
+pub async fn watch_network_identity(manager: Arc<Manager>, mut network_interface: Receiver<Option<NetworkInterface>>) {
+    loop {
+        let interface = network_interface.borrow_and_update().clone();
+        let mut network_identity = match &interface {
+            Some(interface) => Some(identify(interface).await),
+            None => None,
+        };
+        manager.set_network_identity(network_identity.clone());
+        let mut gateway_mac_polls = 0;
+        loop {
+            let gateway_mac_pending = gateway_mac_polls < GATEWAY_MAC_POLL_ATTEMPTS
+                && network_identity
+                    .as_ref()
+                    .is_some_and(|identity| identity.gateway_ip.is_some() && identity.gateway_mac.is_none());
+            select! {
+                changed = network_interface.changed() => {
+                    if changed.is_err() {
+                        tracing::info!(
+                            message_id = "Fz6vKc2X",
+                            "preferred network interface watch closed, stop identifying networks"
+                        );
+                        return;
+                    }
+                    break;
+                }
+                () = sleep(GATEWAY_MAC_POLL_INTERVAL), if gateway_mac_pending => {
+                    gateway_mac_polls += 1;
+                    if let (Some(interface), Some(identity)) = (&interface, &mut network_identity)
+                        && let Some(IpAddr::V4(gateway_ip)) = identity.gateway_ip
+                        && let Some(gateway_mac) = gateway_mac(gateway_ip, interface)
+                    {
+                        tracing::info!(message_id = "Uh4gMb7W", gateway_mac, "gateway MAC resolved");
+                        identity.gateway_mac = Some(gateway_mac);
+                        manager.set_network_identity(network_identity.clone());
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn identify(interface: &NetworkInterface) -> NetworkIdentity {
+    let wireless = Path::new("/sys/class/net").join(&interface.name).join("wireless").exists();
+    let gateway_ip = read_proc(Path::new("/proc/net/route")).and_then(|routes| default_gateway(&routes, &interface.name));
+    let mut gateway_mac = None;
+    if let Some(gateway_ip) = gateway_ip {
+        for attempt in 1..=GATEWAY_MAC_ATTEMPTS {
+            gateway_mac = self::gateway_mac(gateway_ip, interface);
+            if gateway_mac.is_some() || attempt == GATEWAY_MAC_ATTEMPTS {
+                break;
+            }
+            sleep(GATEWAY_MAC_RETRY_DELAY).await;
+        }
+    }
+    let connection = network_manager::connection_identity(interface).await;
+    let (connection_uuid, ssid) = connection.map(|connection| (connection.uuid, connection.ssid)).unwrap_or_default();
+    NetworkIdentity {
+        interface_name: interface.name.clone(),
+        wireless,
+        gateway_ip: gateway_ip.map(Into::into),
+        gateway_mac,
+        connection_uuid,
+        ssid,
+    }
+}
+
+fn gateway_mac(gateway_ip: Ipv4Addr, interface: &NetworkInterface) -> Option<String> {
+    read_proc(Path::new("/proc/net/arp")).and_then(|neighbours| neighbour_mac(&neighbours, gateway_ip, &interface.name))
+}
+
+fn read_proc(path: &Path) -> Option<String> {
+    std::fs::read_to_string(path)
+        .map_err(|error| tracing::error!(message_id = "Nc8hWs3J", ?error, ?path, "failed to read network information: {error}"))
+        .ok()
+}
+
+/// Gateway of the default route with the lowest metric via the interface, from the `/proc/net/route` format.
+fn default_gateway(routes: &str, interface_name: &str) -> Option<Ipv4Addr> {
+    routes
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let [iface, destination, gateway, _flags, _refcnt, _use, metric, mask, ..] = line.split_whitespace().collect::<Vec<_>>()[..] else {
+                return None;
+            };
+            if iface != interface_name || destination != "00000000" || mask != "00000000" {
+                return None;
+            }
+            // Addresses are printed as the native integer representation of the network order bytes.
+            let gateway = Ipv4Addr::from(u32::from_str_radix(gateway, 16).ok()?.to_ne_bytes());
+            let metric: u32 = metric.parse().ok()?;
+            (!gateway.is_unspecified()).then_some((metric, gateway))
+        })
+        .min()
+        .map(|(_metric, gateway)| gateway)
+}
+
+/// Hardware address of a resolved neighbour, from the `/proc/net/arp` format.
+fn neighbour_mac(neighbours: &str, ip: Ipv4Addr, interface_name: &str) -> Option<String> {
+    neighbours.lines().skip(1).find_map(|line| {
+        let [address, _hw_type, flags, hw_address, _mask, device] = line.split_whitespace().collect::<Vec<_>>()[..] else {
+            return None;
+        };
+        let flags = u32::from_str_radix(flags.strip_prefix("0x")?, 16).ok()?;
+        let resolved = flags & ATF_COM != 0;
+        (device == interface_name && address.parse::<Ipv4Addr>().is_ok_and(|address| address == ip) && resolved)
+            .then(|| hw_address.to_ascii_lowercase())
+    })
+}
+
+#[test]
+fn test_default_gateway() {
+    let native = |ip: [u8; 4]| format!("{:08X}", u32::from_ne_bytes(ip));
+    let routes = format!(
+        "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+wlan0\t00000000\t{}\t0003\t0\t0\t600\t00000000\t0\t0\t0
+eth0\t00000000\t{}\t0003\t0\t0\t100\t00000000\t0\t0\t0
+eth0\t00000000\t{}\t0003\t0\t0\t50\t00000000\t0\t0\t0
+eth0\t{}\t00000000\t0001\t0\t0\t100\t{}\t0\t0\t0
+tun0\t00000000\t00000000\t0001\t0\t0\t0\t00000000\t0\t0\t0
+",
+        native([192, 168, 1, 1]),
+        native([10, 0, 0, 1]),
+        native([10, 0, 0, 2]),
+        native([10, 0, 0, 0]),
+        native([255, 255, 0, 0]),
+    );
+    assert_eq!(default_gateway(&routes, "wlan0"), Some(Ipv4Addr::new(192, 168, 1, 1)));
+    assert_eq!(default_gateway(&routes, "eth0"), Some(Ipv4Addr::new(10, 0, 0, 2)));
+    assert_eq!(default_gateway(&routes, "tun0"), None);
+    assert_eq!(default_gateway(&routes, "eth1"), None);
+}
+
+#[test]
+fn test_neighbour_mac() {
+    let neighbours = "IP address       HW type     Flags       HW address            Mask     Device
+192.168.1.1      0x1         0x2         00:11:22:AA:BB:CC     *        wlan0
+10.0.0.1         0x1         0x0         00:00:00:00:00:00     *        eth0
+";
+    assert_eq!(
+        neighbour_mac(neighbours, Ipv4Addr::new(192, 168, 1, 1), "wlan0").as_deref(),
+        Some("00:11:22:aa:bb:cc")
+    );
+    assert_eq!(neighbour_mac(neighbours, Ipv4Addr::new(192, 168, 1, 1), "eth0"), None);
+    assert_eq!(neighbour_mac(neighbours, Ipv4Addr::new(10, 0, 0, 1), "eth0"), None);
+}
@@ -10,6 +10,8 @@ use std::net::{IpAddr, Ipv4Addr};
 /// Version 1.56.0 on RHEL 10 removes the externally set IPv4 capture route from our routing table around Reapply (despite preserve-external-ip flag).
 const MIN_VERSION: Version = Version::new(1, 52, 1);
 
+pub const SERVICE: &str = "org.freedesktop.NetworkManager";
+
 /// See https://networkmanager.dev/docs/api/latest/gdbus-org.freedesktop.NetworkManager.html
 #[zbus::proxy(
     interface = "org.freedesktop.NetworkManager",
@@ -78,12 +80,119 @@ pub async fn detect() -> bool {
     version >= MIN_VERSION
 }
 
+pub struct ConnectionIdentity {
+    pub uuid: Option<String>,
+    pub ssid: Option<String>,
+}
+
+/// Identifies the connection profile applied to a network interface. `None` if network manager isn't running or doesn't manage the interface, which is expected and therefore not logged as error.
+pub async fn connection_identity(interface: &NetworkInterface) -> Option<ConnectionIdentity> {
+    use zbus::zvariant::Value;
+
+    let conn = zbus::Connection::system()
+        .await
+        .map_err(|error| tracing::warn!(message_id = "Mw3qTf7A", ?error, "failed to create DBUS system connection: {}", error))
+        .ok()?;
+    let nm_proxy = NetworkManagerProxy::new(&conn)
+        .await
+        .map_err(|error| tracing::warn!(message_id = "Yh8cRv2P", ?error, "failed to create network manager zbus proxy: {}", error))
+        .ok()?;
+    let device_path = nm_proxy
+        .get_device_by_ip_iface(&interface.name)
+        .await
+        .map_err(|error| {
+            tracing::info!(
+                message_id = "Qs5nXb9W",
+                ?error,
+                interface.name,
+                "no network manager device for interface: {}",
+                error
+            )
+        })
+        .ok()?;
+    let device = DeviceProxy::new(&conn, device_path)
+        .await
+        .map_err(|error| {
+            tracing::warn!(
+                message_id = "Ek4jZm6D",
+                ?error,
+                "failed to create network manager device proxy: {}",
+                error
+            )
+        })
+        .ok()?;
+    let (applied, _version_id) = device
+        .get_applied_connection(0)
+        .await
+        .map_err(|error| {
+            tracing::info!(
+                message_id = "Tb7wLp3G",
+                ?error,
+                interface.name,
+                "no applied network manager connection: {}",
+                error
+            )
+        })
+        .ok()?;
+    let setting = |section: &str, key: &str| applied.get(section).and_then(|settings| settings.get(key));
+    let uuid = setting("connection", "uuid").and_then(|uuid| match &**uuid {
+        Value::Str(uuid) => Some(uuid.to_string()),
+        _ => None,
+    });
+    let ssid = setting("802-11-wireless", "ssid").and_then(|ssid| match &**ssid {
+        Value::Array(ssid) => ssid
+            .iter()
+            .map(|byte| match byte {
+                Value::U8(byte) => Some(*byte),
+                _ => None,
+            })
+            .collect::<Option<Vec<u8>>>()
+            .map(|ssid| String::from_utf8_lossy(&ssid).into_owned()),
+        _ => None,
+    });
+    Some(ConnectionIdentity { uuid, ssid })
+}
+
 pub async fn set_dns(tun: &NetworkInterface, network_config: &OsNetworkConfig) -> Result<(), ()> {
     let (nm_proxy, _nm_version) = NetworkManagerProxy::connect().await?;
     let proxy = nm_proxy.device_proxy(tun).await?;
     apply_device_settings(tun, &proxy, network_config, true).await
 }
 
+/// Object path of the tun device, to watch it for changes.
+pub async fn device_path(tun: &NetworkInterface) -> Result<zbus::zvariant::OwnedObjectPath, ()> {
+    let (nm_proxy, _nm_version) = NetworkManagerProxy::connect().await?;
+    let proxy = nm_proxy.device_proxy(tun).await?;
+    Ok(proxy.inner().path().to_owned().into())
+}
+
+/// Returns true if the DNS settings applied to the tun device differ from what `set_dns` would apply.
+pub async fn dns_dirty(tun: &NetworkInterface, network_config: &OsNetworkConfig) -> Result<bool, ()> {
+    let (nm_proxy, _nm_version) = NetworkManagerProxy::connect().await?;
+    let proxy = nm_proxy.device_proxy(tun).await?;
+    let (applied, _version_id) = proxy.get_applied_connection(0).await.map_err(|error| {
+        tracing::error!(
+            message_id = "Qn5wHs8K",
+            ?error,
+            "failed to get applied network manager device settings: {}",
+            error
+        )
+    })?;
+    let wanted = build_device_settings(tun, network_config, true)
+        .map_err(|error| tracing::error!(message_id = "Vd3kPm7X", ?error, "failed to build network manager DNS settings: {}", error))?;
+    for section in ["ipv4", "ipv6"] {
+        for key in ["dns", "dns-search"] {
+            let want = wanted.get(section).and_then(|settings| settings.get(key));
+            let have = applied.get(section).and_then(|settings| settings.get(key));
+            if want != have {
+                tracing::info!(message_id = "Gt8bNc2W", section, key, ?want, ?have, "network manager DNS settings differ");
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
 pub async fn reset_dns(tun: &NetworkInterface) -> Result<(), ()> {
     let (nm_proxy, _nm_version) = NetworkManagerProxy::connect().await?;
     let proxy = nm_proxy.device_proxy(tun).await?;
@@ -0,0 +1,218 @@
+//! Authorization of IPC peers based on the credentials recorded by the kernel or the D-Bus daemon when they connected.
+
+use crate::service::os::linux::polkit::{self, PolkitSubject};
+use nix::unistd::{Gid, Group, Uid, User, getgrouplist};
+use obscuravpn_client::int_helper::u32_into_usize;
+use obscuravpn_client::manager_cmd::{AccessLevel, ManagerCmd};
+use std::ffi::CString;
+use std::iter::once;
+use std::os::fd::AsRawFd;
+use tokio::net::UnixStream;
+use tokio::sync::OnceCell;
+use zbus::fdo::DBusProxy;
+use zbus::names::{BusName, UniqueName};
+
+/// Members may control the tunnel and the account, see `obscura add-operator`.
+const OPERATOR_GROUP: &str = "obscura";
+/// Members may run admin commands, which otherwise require root.
+const ADMIN_GROUP: &str = "obscura-admin";
+
+pub struct Peer {
+    /// Granted by group membership, commands requiring more may still be authorized by polkit, see [`Peer::authorize`].
+    pub access: AccessLevel,
+    polkit_subject: Option<PolkitSubject>,
+    /// Checked once per connection, because status updates are streamed to the peer.
+    may_see_account: OnceCell<bool>,
+}
+
+impl Peer {
+    fn with_access(access: AccessLevel, polkit_subject: Option<PolkitSubject>) -> Self {
+        Self { access, polkit_subject, may_see_account: OnceCell::new() }
+    }
+
+    /// Falls back to read-only access if the peer's credentials can't be determined.
+    pub async fn new(stream: &UnixStream) -> Self {
+        let cred = match stream.peer_cred() {
+            Ok(cred) => cred,
+            Err(error) => {
+                tracing::error!(message_id = "Qh5vNc2M", ?error, "failed to get peer credentials: {error}");
+                return Self::with_access(AccessLevel::ReadOnly, None);
+            }
+        };
+        let polkit_subject = PolkitSubject::process(cred).await.ok();
+        let supplementary_groups = peer_groups(stream).unwrap_or_else(|error| {
+            tracing::error!(message_id = "Lr8cJw3D", ?error, "failed to get peer groups: {error}");
+            Vec::new()
+        });
+        let groups = once(cred.gid()).chain(supplementary_groups).collect();
+        let access = access_for(cred.uid(), groups).await;
+        tracing::info!(
+            message_id = "Bg9wRf4S",
+            uid = cred.uid(),
+            pid = ?cred.pid(),
+            ?access,
+            "authorized IPC peer"
+        );
+        Self::with_access(access, polkit_subject)
+    }
+
+    /// Identifies the sender of a D-Bus message by the credentials the bus recorded when it connected. Falls back to read-only access if they can't be determined.
+    pub async fn from_dbus(connection: &zbus::Connection, sender: &UniqueName<'_>) -> Self {
+        let polkit_subject = Some(PolkitSubject::BusName(sender.to_string()));
+        let credentials = match DBusProxy::new(connection).await {
+            Ok(proxy) => proxy.get_connection_credentials(BusName::from(sender.to_owned())).await,
+            Err(error) => Err(error.into()),
+        };
+        let credentials = match credentials {
+            Ok(credentials) => credentials,
+            Err(error) => {
+                tracing::error!(message_id = "Nw2cXr7K", ?error, %sender, "failed to get D-Bus sender credentials: {error}");
+                return Self::with_access(AccessLevel::ReadOnly, polkit_subject);
+            }
+        };
+        let Some(uid) = credentials.unix_user_id() else {
+            tracing::error!(message_id = "Ej6tBm4V", %sender, "D-Bus sender credentials lack user id");
+            return Self::with_access(AccessLevel::ReadOnly, polkit_subject);
+        };
+        let groups = match credentials.unix_group_ids() {
+            Some(groups) => groups.clone(),
+            // Older buses don't report groups, fall back to the user's configured groups.
+            None => tokio::task::spawn_blocking(move || user_groups(uid)).await.unwrap_or_else(|error| {
+                tracing::error!(message_id = "Va3hPq9S", ?error, "failed to look up user groups: {error}");
+                Vec::new()
+            }),
+        };
+        let access = access_for(uid, groups).await;
+        tracing::info!(
+            message_id = "Ck8pLd5R",
+            %sender,
+            uid,
+            pid = ?credentials.process_id(),
+            ?access,
+            "authorized D-Bus sender"
+        );
+        Self::with_access(access, polkit_subject)
+    }
+
+    /// May wait for the user to authenticate in a polkit prompt.
+    pub async fn authorize(&self, cmd: &ManagerCmd) -> bool {
+        let required_access = cmd.required_access();
+        if self.access >= required_access {
+            return true;
+        }
+        if let Some(action) = polkit::action(cmd)
+            && let Some(subject) = &self.polkit_subject
+            && polkit::available().await
+            && polkit::check_authorization(subject, action, true).await
+        {
+            return true;
+        }
+        tracing::warn!(
+            message_id = "Yc7dMv2K",
+            command = cmd.name(),
+            ?required_access,
+            access =? self.access,
+            "rejecting unauthorized command"
+        );
+        false
+    }
+
+    /// The account ID and account info give full control over the account. Peers which may only observe the tunnel don't get to see them, unless polkit authorizes them to manage the account without authenticating.
+    pub async fn may_see_account(&self) -> bool {
+        if self.access >= AccessLevel::Operator {
+            return true;
+        }
+        *self
+            .may_see_account
+            .get_or_init(|| async {
+                match &self.polkit_subject {
+                    Some(subject) if polkit::available().await => polkit::check_authorization(subject, polkit::ACTION_MANAGE_ACCOUNT, false).await,
+                    Some(_) | None => false,
+                }
+            })
+            .await
+    }
+}
+
+async fn access_for(uid: u32, groups: Vec<libc::gid_t>) -> AccessLevel {
+    if uid == 0 {
+        return AccessLevel::Admin;
+    }
+    let groups: Vec<Gid> = groups.into_iter().map(Gid::from_raw).collect();
+    tokio::task::spawn_blocking(move || access_for_groups(&groups))
+        .await
+        .unwrap_or_else(|error| {
+            tracing::error!(message_id = "Zt2kPm6H", ?error, "failed to resolve peer groups: {error}");
+            AccessLevel::ReadOnly
+        })
+}
+
+fn user_groups(uid: u32) -> Vec<libc::gid_t> {
+    let user = match User::from_uid(Uid::from_raw(uid)) {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            tracing::error!(message_id = "Sx4wNf8G", uid, "user does not exist");
+            return Vec::new();
+        }
+        Err(error) => {
+            tracing::error!(message_id = "Hb7qTk2C", ?error, uid, "failed to look up user: {error}");
+            return Vec::new();
+        }
+    };
+    let Ok(name) = CString::new(user.name) else {
+        tracing::error!(message_id = "Lm5dRw3J", uid, "user name contains nul byte");
+        return Vec::new();
+    };
+    match getgrouplist(&name, user.gid) {
+        Ok(groups) => groups.into_iter().map(Gid::as_raw).collect(),
+        Err(error) => {
+            tracing::error!(message_id = "Qp9vGc6T", ?error, uid, "failed to look up user groups: {error}");
+            Vec::new()
+        }
+    }
+}
+
+fn access_for_groups(groups: &[Gid]) -> AccessLevel {
+    let is_member = |name: &str| match Group::from_name(name) {
+        Ok(group) => group.is_some_and(|group| groups.contains(&group.gid)),
+        Err(error) => {
+            tracing::error!(message_id = "Hk3sYd7N", ?error, "failed to look up group {name:?}: {error}");
+            false
+        }
+    };
+    if is_member(ADMIN_GROUP) {
+        AccessLevel::Admin
+    } else if is_member(OPERATOR_GROUP) {
+        AccessLevel::Operator
+    } else {
+        AccessLevel::ReadOnly
+    }
+}
+
+/// Supplementary groups of the peer at the time it connected. Unlike reading `/proc/<pid>/status`, this isn't racy if the peer exits and its PID is reused.
+fn peer_groups(stream: &UnixStream) -> std::io::Result<Vec<libc::gid_t>> {
+    let mut groups: Vec<libc::gid_t> = vec![0; 32];
+    loop {
+        let mut len: libc::socklen_t = size_of_val(groups.as_slice()).try_into().map_err(std::io::Error::other)?;
+        // SAFETY: the buffer is valid for writes of `len` bytes, which the kernel updates to the number of bytes written or required.
+        let ret = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERGROUPS,
+                groups.as_mut_ptr().cast(),
+                &mut len,
+            )
+        };
+        let count = u32_into_usize(len) / size_of::<libc::gid_t>();
+        if ret == 0 {
+            groups.truncate(count);
+            return Ok(groups);
+        }
+        let error = std::io::Error::last_os_error();
+        if error.raw_os_error() != Some(libc::ERANGE) || count <= groups.len() {
+            return Err(error);
+        }
+        groups.resize(count, 0);
+    }
+}
@@ -0,0 +1,204 @@
+//! Authorization of commands by polkit for peers which aren't operators, see `linux/common/net.obscura.vpn.policy`. Which users are authorized, and whether they have to authenticate first, is configured by polkit rules.
+
+use obscuravpn_client::manager_cmd::ManagerCmd;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::net::unix::UCred;
+use tokio::sync::OnceCell;
+use zbus::zvariant::{Type, Value};
+
+const ACTION_CONNECT: &str = "net.obscura.vpn.connect";
+pub const ACTION_MANAGE_ACCOUNT: &str = "net.obscura.vpn.manage-account";
+
+/// Shows an authentication prompt in the peer's session if the policy requires authentication.
+const FLAG_ALLOW_USER_INTERACTION: u32 = 1;
+
+const AVAILABILITY_RETRY: Duration = Duration::from_secs(30);
+
+/// See https://www.freedesktop.org/software/polkit/docs/latest/eggdbus-interface-org.freedesktop.PolicyKit1.Authority.html
+#[zbus::proxy(
+    interface = "org.freedesktop.PolicyKit1.Authority",
+    default_service = "org.freedesktop.PolicyKit1",
+    default_path = "/org/freedesktop/PolicyKit1/Authority"
+)]
+trait Authority {
+    fn check_authorization(
+        &self,
+        subject: &Subject<'_>,
+        action_id: &str,
+        details: HashMap<&str, &str>,
+        flags: u32,
+        cancellation_id: &str,
+    ) -> zbus::Result<(bool, bool, HashMap<String, String>)>;
+    #[zbus(property)]
+    fn backend_name(&self) -> zbus::Result<String>;
+}
+
+/// Identifies the process polkit authorizes.
+pub enum PolkitSubject {
+    /// Polkit identifies processes by PID and start time. The start time is recorded when the peer connects, so a process reusing the PID after the peer exited isn't mistaken for the peer.
+    Process { pid: u32, uid: u32, start_time: u64 },
+    /// Unique name of a D-Bus connection.
+    BusName(String),
+}
+
+impl PolkitSubject {
+    /// Must be called as soon as the peer connected, see [`PolkitSubject::Process`].
+    pub async fn process(cred: UCred) -> Result<Self, ()> {
+        let Some(pid) = cred.pid() else {
+            tracing::error!(message_id = "Pn3vXq8G", "cannot check polkit authorization without peer pid");
+            return Err(());
+        };
+        let pid = u32::try_from(pid).map_err(|error| tracing::error!(message_id = "Gc6mRt1W", ?error, pid, "invalid peer pid: {error}"))?;
+        let start_time = process_start_time(pid).await?;
+        Ok(Self::Process { pid, uid: cred.uid(), start_time })
+    }
+}
+
+#[derive(Debug, Serialize, Type)]
+struct Subject<'a> {
+    kind: &'a str,
+    details: HashMap<&'a str, Value<'a>>,
+}
+
+/// Polkit action authorizing the command, if it may be authorized by polkit at all.
+pub fn action(cmd: &ManagerCmd) -> Option<&'static str> {
+    match cmd {
+        ManagerCmd::ApiAppleAssociateAccount { .. }
+        | ManagerCmd::ApiGetAccountInfo {}
+        | ManagerCmd::ApiGoogleAssociateAccount { .. }
+        | ManagerCmd::ApiGoogleBillingDetails { .. }
+        | ManagerCmd::Login { .. }
+        | ManagerCmd::Logout {}
+        | ManagerCmd::SetInNewAccountFlow { .. } => Some(ACTION_MANAGE_ACCOUNT),
+        ManagerCmd::BenchRelays {}
+        | ManagerCmd::BypassCaptivePortal {}
+        | ManagerCmd::CreateDebugBundle { .. }
+        | ManagerCmd::CreateServiceDebugBundle {}
+        | ManagerCmd::DeleteServiceDebugBundle { .. }
+        | ManagerCmd::GetDebugInfo {}
+        | ManagerCmd::GetLogs { .. }
+        | ManagerCmd::RefreshExitList { .. }
+        | ManagerCmd::RotateWgKey {}
+        | ManagerCmd::RunDiagnostics {}
+        | ManagerCmd::RunLeakTest {}
+        | ManagerCmd::SetAutoConnect { .. }
+        | ManagerCmd::SetDnsContentBlock { .. }
+        | ManagerCmd::SetLocalNetworkAccess { .. }
+        | ManagerCmd::SetNetworkRules { .. }
+        | ManagerCmd::SetPinnedExits { .. }
+        | ManagerCmd::SetTunnelArgs { .. }
+        | ManagerCmd::SetUseSystemDns { .. } => Some(ACTION_CONNECT),
+        // Read-only commands don't need authorization and admin commands always require root or the admin group.
+        ManagerCmd::ApiDeleteAccount {}
+        | ManagerCmd::GetExitList { .. }
+        | ManagerCmd::GetStatus { .. }
+        | ManagerCmd::GetTrafficStats {}
+        | ManagerCmd::Ping {}
+        | ManagerCmd::SetApiDohBootstrapUrls { .. }
+        | ManagerCmd::SetApiHostAlternate { .. }
+        | ManagerCmd::SetApiUrl { .. }
+        | ManagerCmd::SetFeatureFlag { .. }
+        | ManagerCmd::SetSniRelay { .. }
+        | ManagerCmd::SubscribeEvents { .. }
+        | ManagerCmd::TerminateProcess {} => None,
+    }
+}
+
+/// Whether polkit is installed, so clients know that operator commands may be authorized without group membership. Unavailability is only cached for `AVAILABILITY_RETRY`, because polkit may be installed or started after the service.
+pub async fn available() -> bool {
+    static AVAILABLE: OnceCell<()> = OnceCell::const_new();
+    static LAST_UNAVAILABLE: Mutex<Option<Instant>> = Mutex::new(None);
+    if AVAILABLE.initialized() {
+        return true;
+    }
+    if LAST_UNAVAILABLE.lock().unwrap().is_some_and(|at| at.elapsed() < AVAILABILITY_RETRY) {
+        return false;
+    }
+    let available = AVAILABLE
+        .get_or_try_init(|| async {
+            let backend_name = authority()
+                .await?
+                .backend_name()
+                .await
+                .map_err(|error| tracing::info!(message_id = "Jd8rLs2P", ?error, "polkit is unavailable: {error}"))?;
+            tracing::info!(message_id = "Kw4nHc7T", backend_name, "polkit is available");
+            Ok::<(), ()>(())
+        })
+        .await
+        .is_ok();
+    if !available {
+        *LAST_UNAVAILABLE.lock().unwrap() = Some(Instant::now());
+    }
+    available
+}
+
+/// Whether polkit authorizes the peer. With `interactive`, this may wait for the user to authenticate.
+pub async fn check_authorization(subject: &PolkitSubject, action: &'static str, interactive: bool) -> bool {
+    check_authorization_impl(subject, action, interactive).await.unwrap_or(false)
+}
+
+async fn check_authorization_impl(subject: &PolkitSubject, action: &'static str, interactive: bool) -> Result<bool, ()> {
+    let subject = match subject {
+        &PolkitSubject::Process { pid, uid, start_time } => {
+            let uid = i32::try_from(uid).map_err(|error| tracing::error!(message_id = "Tf2dWk9B", ?error, "invalid peer uid: {error}"))?;
+            Subject {
+                kind: "unix-process",
+                details: HashMap::from([
+                    ("pid", Value::from(pid)),
+                    ("start-time", Value::from(start_time)),
+                    ("uid", Value::from(uid)),
+                ]),
+            }
+        }
+        PolkitSubject::BusName(name) => Subject { kind: "system-bus-name", details: HashMap::from([("name", Value::from(name.as_str()))]) },
+    };
+    let flags = if interactive { FLAG_ALLOW_USER_INTERACTION } else { 0 };
+    let (authorized, challenge, _details) = authority()
+        .await?
+        .check_authorization(&subject, action, HashMap::new(), flags, "")
+        .await
+        .map_err(|error| tracing::error!(message_id = "Mb7sQz4L", ?error, action, "polkit authorization check failed: {error}"))?;
+    tracing::info!(
+        message_id = "Ux5hNw3C",
+        action,
+        subject = ?subject.details,
+        authorized,
+        challenge,
+        interactive,
+        "checked polkit authorization"
+    );
+    Ok(authorized)
+}
+
+async fn authority() -> Result<AuthorityProxy<'static>, ()> {
+    let conn = zbus::Connection::system()
+        .await
+        .map_err(|error| tracing::error!(message_id = "Rv9kBd6Y", ?error, "failed to create DBUS system connection: {}", error))?;
+    AuthorityProxy::new(&conn)
+        .await
+        .map_err(|error| tracing::error!(message_id = "Xq1pFm5J", ?error, "failed to create polkit zbus proxy: {}", error))
+}
+
+/// Start time in clock ticks since boot, field 22 of `/proc/<pid>/stat`.
+async fn process_start_time(pid: u32) -> Result<u64, ()> {
+    let stat = tokio::fs::read_to_string(format!("/proc/{pid}/stat"))
+        .await
+        .map_err(|error| tracing::error!(message_id = "Ya4cKv8R", ?error, pid, "failed to read process stat: {error}"))?;
+    parse_start_time(&stat).ok_or_else(|| tracing::error!(message_id = "Dw7gHs2N", pid, "failed to parse process start time"))
+}
+
+fn parse_start_time(stat: &str) -> Option<u64> {
+    // The command name in field 2 is enclosed in parentheses, but may itself contain spaces and parentheses.
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+#[test]
+fn test_parse_start_time() {
+    let stat = "4242 (a) b (c)) S 1 4242 4242 0 -1 4194560 1 0 0 0 0 0 0 0 20 0 1 0 123456 1000 100 18446744073709551615";
+    assert_eq!(parse_start_time(stat), Some(123456));
+    assert_eq!(parse_start_time("4242 (a) S 1"), None);
+}
@@ -0,0 +1,221 @@
+//! Unattended setup for servers and CI images. The account id is read from the `account-id` systemd credential (`LoadCredential=account-id:/path/to/file`) or the `OBSCURA_ACCOUNT_ID` environment variable, and [`INITIAL_SETTINGS_PATH`] is applied once. Failures are reported as systemd status (`systemctl status obscura`) instead of preventing the service from starting. Provisioned services connect on boot if auto-connect is enabled.
+
+use obscuravpn_api::types::AccountId;
+use obscuravpn_client::exit_selection::ExitSelector;
+use obscuravpn_client::manager::{Manager, TunnelArgs};
+use obscuravpn_client::manager_cmd::{ManagerCmd, ManagerCmdErrorCode};
+use serde::Deserialize;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+pub const INITIAL_SETTINGS_PATH: &str = "/etc/obscura/initial-settings.json";
+const ACCOUNT_ID_CREDENTIAL: &str = "account-id";
+const ACCOUNT_ID_ENV: &str = "OBSCURA_ACCOUNT_ID";
+/// Created in the state directory once the initial settings were applied, so later changes by users aren't overwritten on the next start.
+const INITIAL_SETTINGS_APPLIED_MARKER: &str = "initial-settings-applied";
+
+/// Unset fields are left unchanged.
+#[derive(Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct InitialSettings {
+    exit: Option<ExitSelector>,
+    use_system_dns: Option<bool>,
+    local_network_access: Option<bool>,
+    auto_connect: Option<bool>,
+}
+
+impl InitialSettings {
+    fn into_cmds(self) -> Vec<ManagerCmd> {
+        let Self { exit, use_system_dns, local_network_access, auto_connect } = self;
+        let mut cmds = Vec::new();
+        if let Some(exit) = exit {
+            cmds.push(ManagerCmd::SetTunnelArgs { args: Some(TunnelArgs { exit }), active: None });
+        }
+        if let Some(enable) = use_system_dns {
+            cmds.push(ManagerCmd::SetUseSystemDns { enable });
+        }
+        if let Some(enable) = local_network_access {
+            cmds.push(ManagerCmd::SetLocalNetworkAccess { enable });
+        }
+        if let Some(enable) = auto_connect {
+            cmds.push(ManagerCmd::SetAutoConnect { enable });
+        }
+        cmds
+    }
+}
+
+/// Logs in with the provisioned account and applies the initial settings. Runs before any commands are accepted. Returns whether the service is provisioned, i.e. an account or initial settings are provided.
+pub async fn provision(manager: &Manager, state_dir: &Path) -> bool {
+    let mut errors = Vec::new();
+    let mut changed = false;
+    let mut provisioned = Path::new(INITIAL_SETTINGS_PATH).exists();
+    match provision_account(manager).await {
+        Ok(Some(logged_in)) => {
+            provisioned = true;
+            changed |= logged_in;
+        }
+        Ok(None) => {}
+        Err(error) => {
+            provisioned = true;
+            errors.push(error);
+        }
+    }
+    match apply_initial_settings(manager, state_dir).await {
+        Ok(applied) => changed |= applied,
+        Err(error) => errors.push(error),
+    }
+    if errors.is_empty() {
+        if changed {
+            tracing::info!(message_id = "Rc5vTj8W", "provisioned");
+        }
+        // Clears the failure of an earlier attempt, the status is only meant to surface problems.
+        notify_status("");
+    } else {
+        notify_status(&format!("Provisioning failed: {}", errors.join(", ")));
+    }
+    provisioned
+}
+
+fn notify_status(status: &str) {
+    if let Err(error) = sd_notify::notify(&[sd_notify::NotifyState::Status(status)]) {
+        tracing::error!(
+            message_id = "Jd4nWq8K",
+            ?error,
+            "failed to report provisioning status to systemd: {error}"
+        );
+    }
+}
+
+/// Activates the tunnel on a fresh start of a provisioned service if auto-connect is enabled. Restarts restore the previous tunnel state instead, and interactive installations leave auto-connect to the app.
+pub async fn auto_connect(manager: &Manager) {
+    if !manager.subscribe().borrow().auto_connect {
+        return;
+    }
+    tracing::info!(message_id = "Xc7pLm2T", "auto-connecting");
+    if let Err(error) = (ManagerCmd::SetTunnelArgs { args: None, active: Some(true) }).run(manager).await {
+        tracing::error!(message_id = "Ut3bRz6H", ?error, "failed to auto-connect");
+    }
+}
+
+/// Returns whether it logged in, or `None` if no account is provisioned. An already logged in account is kept, even if a different one is provisioned.
+async fn provision_account(manager: &Manager) -> Result<Option<bool>, &'static str> {
+    let Some(account_id) = provisioned_account_id()? else {
+        return Ok(None);
+    };
+    match manager.subscribe().borrow().account_id.as_ref() {
+        Some(current) if *current == account_id => return Ok(Some(false)),
+        Some(_) => {
+            tracing::warn!(message_id = "Hq5sVk9N", "a different account is logged in, ignoring provisioned account");
+            return Ok(Some(false));
+        }
+        None => {}
+    }
+    // Not validated, so the service also starts without network access. An invalid account shows up as an error when connecting.
+    match (ManagerCmd::Login { account_id, validate: false }).run(manager).await {
+        Ok(_) => {
+            tracing::info!(message_id = "Ne8wGc3Y", "logged in with provisioned account");
+            Ok(Some(true))
+        }
+        Err(error) => {
+            tracing::error!(message_id = "Bv2kTs7M", ?error, "failed to log in with provisioned account");
+            Err("login failed")
+        }
+    }
+}
+
+/// The credential takes precedence over the environment, since the environment is visible to all processes of the unit.
+fn provisioned_account_id() -> Result<Option<AccountId>, &'static str> {
+    let account_id = match std::env::var_os("CREDENTIALS_DIRECTORY") {
+        Some(dir) => read_optional(&PathBuf::from(dir).join(ACCOUNT_ID_CREDENTIAL)).map_err(|()| "account id credential unreadable")?,
+        None => None,
+    };
+    let account_id = match account_id {
+        Some(account_id) => account_id,
+        None => match std::env::var(ACCOUNT_ID_ENV) {
+            Ok(account_id) => account_id,
+            Err(std::env::VarError::NotPresent) => return Ok(None),
+            Err(error) => {
+                tracing::error!(message_id = "Pw6rDn4F", ?error, "invalid {ACCOUNT_ID_ENV}: {error}");
+                return Err("invalid account id");
+            }
+        },
+    };
+    let account_id = account_id.trim();
+    if account_id.is_empty() {
+        tracing::error!(message_id = "Kr3vYb8S", "provisioned account id is empty");
+        return Err("invalid account id");
+    }
+    Ok(Some(AccountId::from_string_unchecked(account_id.to_owned())))
+}
+
+/// Returns whether settings were applied. The marker is only created after all settings were applied, so a failed attempt is retried on the next start. Settings locked by the policy are skipped, since retrying wouldn't help, and reported once.
+async fn apply_initial_settings(manager: &Manager, state_dir: &Path) -> Result<bool, &'static str> {
+    let marker = state_dir.join(INITIAL_SETTINGS_APPLIED_MARKER);
+    if marker.exists() {
+        return Ok(false);
+    }
+    let path = Path::new(INITIAL_SETTINGS_PATH);
+    let Some(json) = read_optional(path).map_err(|()| "initial settings unreadable")? else {
+        return Ok(false);
+    };
+    let settings: InitialSettings = serde_json::from_str(&json).map_err(|error| {
+        tracing::error!(message_id = "Zm9tQh2C", ?error, ?path, "failed to parse initial settings: {error}");
+        "invalid initial settings"
+    })?;
+    tracing::info!(message_id = "Gy4cWp7L", ?settings, "applying initial settings");
+    let mut result = Ok(true);
+    let mut locked = false;
+    for cmd in settings.into_cmds() {
+        let name = cmd.name();
+        match cmd.run(manager).await {
+            Ok(_) => {}
+            Err(ManagerCmdErrorCode::PolicyLocked) => {
+                tracing::warn!(message_id = "Dk7sWn2P", command = name, "skipping initial setting locked by policy");
+                locked = true;
+            }
+            Err(error) => {
+                tracing::error!(message_id = "Tf8nKx3R", ?error, "failed to apply initial setting");
+                result = Err("failed to apply initial settings");
+            }
+        }
+    }
+    result?;
+    if let Err(error) = std::fs::write(&marker, []) {
+        tracing::error!(
+            message_id = "Vs2hMd6Q",
+            ?error,
+            ?marker,
+            "failed to create initial settings marker: {error}"
+        );
+        return Err("failed to record initial settings");
+    }
+    if locked {
+        return Err("initial settings locked by policy were skipped");
+    }
+    Ok(true)
+}
+
+fn read_optional(path: &Path) -> Result<Option<String>, ()> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+        Err(error) => {
+            tracing::error!(message_id = "Lb5qFw9J", ?error, ?path, "failed to read provisioning file: {error}");
+            Err(())
+        }
+    }
+}
+
+#[test]
+fn test_initial_settings() {
+    let settings: InitialSettings =
+        serde_json::from_str(r#"{"exit":{"country":{"country_code":"de"}},"useSystemDns":false,"autoConnect":true}"#).unwrap();
+    let cmds = settings.into_cmds();
+    assert_eq!(cmds.len(), 3);
+    assert!(matches!(&cmds[0], ManagerCmd::SetTunnelArgs { args: Some(_), active: None }));
+    assert!(matches!(cmds[1], ManagerCmd::SetUseSystemDns { enable: false }));
+    assert!(matches!(cmds[2], ManagerCmd::SetAutoConnect { enable: true }));
+
+    assert_eq!(serde_json::from_str::<InitialSettings>("{}").unwrap(), InitialSettings::default());
+    assert!(serde_json::from_str::<InitialSettings>(r#"{"autoconnect":true}"#).is_err());
+}
@@ -107,7 +107,7 @@ const FAMILIES: [(IpVersion, AddressFamily); 2] = [(IpVersion::V4, AddressFamily
 fn wanted_resolver_rules(policy: &TrafficPolicy, family: AddressFamily) -> BTreeSet<IpAddr> {
     match policy {
         TrafficPolicy::Engage { dns, local_network_access: _ } => dns.iter().copied().filter(|ip| address_family(*ip) == family).collect(),
-        TrafficPolicy::Disengage => BTreeSet::new(),
+        TrafficPolicy::BypassCaptivePortal { .. } | TrafficPolicy::Disengage => BTreeSet::new(),
     }
 }
 
@@ -294,6 +294,17 @@ fn is_suppress_rule(rule: &RuleMessage) -> bool {
         && !rule.attributes.iter().any(|attribute| matches!(attribute, RuleAttribute::FwMark(_)))
 }
 
+/// Returns whether the capture rule is installed, checked by the leak test.
+pub async fn has_capture_rule(handle: &rtnetlink::Handle, ip_version: IpVersion) -> Result<bool, rtnetlink::Error> {
+    let mut rule_dump = handle.rule().get(ip_version).execute();
+    while let Some(rule) = rule_dump.next().await {
+        if is_capture_rule(&rule?) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
 fn capture_rule(family: AddressFamily) -> RuleMessage {
     let mut rule = RuleMessage::default();
     rule.header.family = family;
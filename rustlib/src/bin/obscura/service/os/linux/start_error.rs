@@ -8,6 +8,10 @@ pub enum LinuxServiceStartError {
     NoDnsManager,
     #[error("Failed to set up nftables.")]
     NftablesSetup,
+    #[error("Invalid policy in {}, see the service log for details.", super::POLICY_PATH)]
+    InvalidPolicy,
+    #[error("Refusing to serve the HTTP API on {0}, which is reachable from other hosts, without --http-api-allow-remote.")]
+    HttpApiNotLoopback(std::net::SocketAddr),
     #[error("Unexpected error. Details: {0}")]
     Unexpected(#[from] anyhow::Error),
 }
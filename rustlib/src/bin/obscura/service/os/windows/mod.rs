@@ -7,11 +7,13 @@ pub mod tun;
 use bytes::Bytes;
 use ipc::ServiceIpc;
 use obscuravpn_client::manager_cmd::{ManagerCmd, ManagerCmdErrorCode, ManagerCmdOk};
+use obscuravpn_client::manager_event::{EventSubscription, ManagerEvent};
 use obscuravpn_client::net::NetworkInterface;
 use obscuravpn_client::network_config::OsNetworkConfig;
 use obscuravpn_client::os::os_trait::Os;
 use obscuravpn_client::quicwg::QuicWgConnPacketSender;
 pub use start_error::WindowsServiceStartError;
+use tokio::sync::mpsc;
 use tokio::sync::watch::Receiver;
 use tun::Tun;
 mod adapters;
@@ -52,6 +54,11 @@ impl WindowsOsImpl {
         }
     }
 
+    /// Event subscriptions need a streaming transport, which the named pipe IPC doesn't support, so this never returns.
+    pub async fn next_event_subscription(&self) -> (EventSubscription, Box<dyn FnOnce(mpsc::Receiver<ManagerEvent>) + Send>) {
+        std::future::pending().await
+    }
+
     pub fn network_interface(&self) -> Receiver<Option<NetworkInterface>> {
         self.active_adapter_watcher.clone()
     }
@@ -0,0 +1,88 @@
+//! Captive portals (e.g. hotel and airport Wi-Fi) intercept plain HTTP until the user logs in or accepts terms in a browser. They are detected by fetching a page with known content over the physical network, bypassing the tunnel like API and relay traffic.
+
+use crate::debug_bundle::http::client_builder;
+use crate::dns::DnsResolver;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+
+const PROBE_URL: &str = "http://detectportal.firefox.com/success.txt";
+const PROBE_BODY: &[u8] = b"success";
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+/// The expected body is tiny, portal pages are only read far enough to tell them apart.
+const BODY_LIMIT: usize = 1024;
+
+/// Failed connection attempts only probe again once the last probe on the same network is this old.
+pub const PROBE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long a bypass lasts unless the tunnel connects earlier.
+pub const BYPASS_DURATION: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CaptivePortal {
+    /// Where the portal redirected the probe. Unset if the portal replaced the response instead, in which case any plain HTTP page shows the portal.
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CaptivePortalBypass {
+    pub portal: CaptivePortal,
+    pub duration_secs: u64,
+}
+
+/// Fetches the probe page, marked with `FWMARK` on Linux. Returns `Ok(None)` if the expected page was returned. Fails if there was no response at all, e.g. without internet access or if the probe host can't be resolved, which is likely behind a portal unless it is in the DNS cache.
+pub(crate) async fn probe(resolver: Arc<DnsResolver>) -> Result<Option<CaptivePortal>, ()> {
+    let client = client_builder(Some(crate::net::FWMARK))
+        .timeout(PROBE_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .dns_resolver(resolver)
+        .build()
+        .map_err(|error| {
+            tracing::error!(message_id = "Qc4wTm8R", ?error, "failed to build captive portal probe client: {error}");
+        })?;
+    let mut res = client.get(PROBE_URL).send().await.map_err(|error| {
+        tracing::warn!(message_id = "Hn7bXs2K", ?error, "captive portal probe failed: {error}");
+    })?;
+    let status_code = res.status().as_u16();
+    let location = res
+        .headers()
+        .get(reqwest::header::LOCATION)
+        .and_then(|location| location.to_str().ok())
+        .map(str::to_owned);
+    let mut body = Vec::new();
+    while body.len() <= BODY_LIMIT
+        && let Some(chunk) = res.chunk().await.map_err(|error| {
+            tracing::warn!(message_id = "Vd3kPq9J", ?error, "failed to read captive portal probe response: {error}");
+        })?
+    {
+        body.extend_from_slice(&chunk);
+    }
+    let portal = classify(status_code, location, &body);
+    tracing::info!(message_id = "Ly6mGr4N", status_code, ?portal, "captive portal probe finished");
+    Ok(portal)
+}
+
+/// Any response other than the expected page means something on the network intercepted the probe.
+fn classify(status_code: u16, location: Option<String>, body: &[u8]) -> Option<CaptivePortal> {
+    if status_code == 200 && body.trim_ascii() == PROBE_BODY {
+        return None;
+    }
+    let redirected = (300..400).contains(&status_code);
+    Some(CaptivePortal { url: location.filter(|_| redirected) })
+}
+
+#[test]
+fn test_classify() {
+    assert_eq!(classify(200, None, b"success\n"), None);
+    assert_eq!(
+        classify(302, Some("http://192.168.1.1/login".into()), b""),
+        Some(CaptivePortal { url: Some("http://192.168.1.1/login".into()) })
+    );
+    assert_eq!(
+        classify(200, Some("/ignored".into()), b"<html>Accept terms</html>"),
+        Some(CaptivePortal { url: None })
+    );
+    assert_eq!(classify(511, None, b""), Some(CaptivePortal { url: None }));
+}
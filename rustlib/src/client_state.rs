@@ -2,13 +2,17 @@ use super::{
     errors::{ApiError, TunnelConnectError},
     network_config::TunnelNetworkConfig,
 };
+use crate::captive_portal::{self, CaptivePortal};
 use crate::constants::{DEFAULT_API_BACKUP_DOMAIN, DEFAULT_API_URL, DEFAULT_RELAY_SNI};
 use crate::debug_bundle::service::NetworkInfo;
 use crate::debug_bundle::{debug_info::DebugInfo, dns::DebugTaskDns, http::DebugTaskHttp, task::debug_panic_error, task::run_debug_task};
+use crate::diagnostics::{RelayProbe, RelayReachable};
 use crate::dns::DnsResolver;
 use crate::errors::ConfigDirty;
 use crate::manager::TunnelArgs;
 use crate::network_config::DnsContentBlock;
+use crate::network_rules::{NetworkAction, NetworkIdentity, NetworkRule, matching_rule};
+use crate::policy::Policy;
 use crate::tunnel_state::TargetState;
 use crate::{config::ConfigHandle, net::interface_mtu};
 use crate::{config::PinnedLocation, exit_selection::ExitSelectionState};
@@ -19,7 +23,10 @@ use crate::{
     errors::RelaySelectionError,
     quicwg::QuicWgConn,
 };
-use crate::{quicwg::TUNNEL_MTU, relay_selection::race_relay_handshakes};
+use crate::{
+    quicwg::TUNNEL_MTU,
+    relay_selection::{MAX_RELAYS, RelayBench, RelayBenchResult, bench_relay_handshakes, race_relay_handshakes},
+};
 use boringtun::x25519::{PublicKey, StaticSecret};
 use obscuravpn_api::cmd::{CacheWgKey, ETagCmd, ExitList, ListExits2};
 use obscuravpn_api::types::{AccountId, AccountInfo, AuthToken, OneExit};
@@ -62,11 +69,22 @@ pub struct ClientState {
     exit_update_lock: Arc<tokio::sync::Mutex<()>>,
     mtu: Option<u16>,
     network_interface: Option<NetworkInterface>,
+    network_identity: Option<NetworkIdentity>,
+    dns_watchdog_interventions: u64,
+    matched_network_rule: Option<NetworkRule>,
+    captive_portal_probe: Option<CaptivePortalProbe>,
+    policy: Policy,
     relay_update_lock: Arc<tokio::sync::Mutex<()>>,
     wg_key_store: WgKeyStore,
     user_agent: String,
 }
 
+/// Last captive portal probe on the current network interface, see [`ClientStateHandle::known_captive_portal`].
+struct CaptivePortalProbe {
+    at: Instant,
+    portal: Option<CaptivePortal>,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct AccountStatus {
     pub account_info: AccountInfo, // API
@@ -88,6 +106,7 @@ impl ClientState {
         wg_key_store: WgKeyStore,
         user_agent: String,
         force_init_inactive: bool,
+        policy: Policy,
     ) -> Result<ClientStateHandle, ConfigLoadError> {
         let mut config = ConfigHandle::new(config_dir, &wg_key_store)?;
         if force_init_inactive {
@@ -101,6 +120,11 @@ impl ClientState {
                 wg_key_store,
                 mtu: None,
                 network_interface: None,
+                network_identity: None,
+                dns_watchdog_interventions: 0,
+                matched_network_rule: None,
+                captive_portal_probe: None,
+                policy,
                 exit_update_lock: Default::default(),
                 relay_update_lock: Default::default(),
                 user_agent,
@@ -109,23 +133,67 @@ impl ClientState {
         })))
     }
 
+    /// Locked settings of the policy take precedence over the config.
     pub fn target_state(&self) -> TargetState {
+        let policy = &self.policy;
+        let mut tunnel_args = self.config.tunnel_args.clone();
+        if !policy.permits_selector(&tunnel_args.exit) {
+            tunnel_args.exit = ExitSelector::Any {};
+        }
         TargetState {
-            tunnel_args: self.config.tunnel_active.then_some(self.config.tunnel_args.clone()),
+            tunnel_args: (self.config.tunnel_active || policy.lockdown).then_some(tunnel_args),
             network_interface: self.network_interface.clone(),
             dns_content_block: self.config.dns_content_block,
-            use_system_dns: match self.config.dns {
+            use_system_dns: policy.use_system_dns.unwrap_or(match self.config.dns {
                 DnsConfig::Default => false,
                 DnsConfig::System => true,
-            },
-            local_network_access: self.config.local_network_access.is_enabled(),
+            }),
+            local_network_access: policy.local_network_access.unwrap_or(self.config.local_network_access.is_enabled()),
         }
     }
 
+    pub fn policy(&self) -> &Policy {
+        &self.policy
+    }
+
     pub fn config(&self) -> &Config {
         &self.config
     }
 
+    pub fn network_identity(&self) -> Option<&NetworkIdentity> {
+        self.network_identity.as_ref()
+    }
+
+    /// Rule applied for the current network, if any.
+    pub fn matched_network_rule(&self) -> Option<&NetworkRule> {
+        self.matched_network_rule.as_ref()
+    }
+
+    /// Updates the matched rule and returns whether it wants the tunnel active. Nothing is returned if the rules were already applied for this network, unless `force`d, so they don't apply again after restarting the service.
+    fn match_network_rules(&mut self, force: bool) -> Option<bool> {
+        let identity = self.network_identity.clone();
+        self.matched_network_rule = identity
+            .as_ref()
+            .and_then(|identity| matching_rule(&self.config.network_rules, identity))
+            .cloned();
+        let identity = identity?;
+        if !force
+            && let Some(applied) = &self.config.network_rules_identity
+            && applied.same_network(&identity)
+        {
+            // The gateway MAC may only become known after the rules were applied for this network, rules matching it apply once it is.
+            let gateway_mac_resolved = applied.gateway_mac.is_none() && identity.gateway_mac.is_some();
+            if !gateway_mac_resolved || matching_rule(&self.config.network_rules, applied) == self.matched_network_rule.as_ref() {
+                return None;
+            }
+        }
+        self.config.change(|config| config.network_rules_identity = Some(identity));
+        let rule = self.matched_network_rule.as_ref()?;
+        let active = rule.action == NetworkAction::Connect;
+        tracing::info!(message_id = "Ry6tCq1B", rule.name, active, "applying network rule");
+        Some(active)
+    }
+
     pub fn base_url(&self) -> String {
         self.config.api_url.clone().unwrap_or(DEFAULT_API_URL.to_string())
     }
@@ -280,6 +348,18 @@ impl ClientStateHandle {
         })
     }
 
+    pub fn set_api_doh_bootstrap_urls(&self, value: Option<Vec<String>>) {
+        self.change_config(|config| {
+            tracing::info!(
+                message_id = "Wv4nKe8R",
+                api_doh_bootstrap_urls_new =? value,
+                api_doh_bootstrap_urls_old =? config.api_doh_bootstrap_urls,
+                "Changing API DoH bootstrap URLs.",
+            );
+            config.api_doh_bootstrap_urls = value;
+        })
+    }
+
     pub fn set_sni_relay(&self, value: Option<String>) {
         self.change_config(|config| {
             tracing::info!(
@@ -339,18 +419,56 @@ impl ClientStateHandle {
             None
         };
 
-        self.change(|inner| {
+        let probe_captive_portal = self.change(|inner| {
             inner.mtu = mtu.and_then(|mtu| {
                 u16::try_from(mtu)
                     .inspect_err(|_| tracing::warn!(message_id = "uKFfXGSc", mtu, "MTU out of range"))
                     .ok()
             });
-            if network_interface != inner.network_interface {
-                inner.network_interface = network_interface;
-                tracing::info!(message_id = "iew0Ahk9", "Clearing cached API client: network interface changed.");
-                inner.cached_api_client = None;
+            if network_interface == inner.network_interface {
+                return false;
             }
-        })
+            inner.network_interface = network_interface;
+            tracing::info!(message_id = "iew0Ahk9", "Clearing cached API client: network interface changed.");
+            inner.cached_api_client = None;
+            inner.captive_portal_probe = None;
+            // Only Linux can probe the physical network while traffic is captured.
+            cfg!(target_os = "linux") && inner.network_interface.is_some() && inner.target_state().tunnel_args.is_some()
+        });
+        // Probes right away, so the result is usually known by the time the first connection attempt on the new network fails.
+        if probe_captive_portal {
+            let _ = self.known_captive_portal();
+        }
+    }
+
+    /// Applies the first matching network rule if the network changed, see [`NetworkIdentity::same_network`].
+    pub fn set_network_identity(&self, network_identity: Option<NetworkIdentity>) {
+        if self.borrow().network_identity == network_identity {
+            return;
+        }
+        tracing::info!(message_id = "Dk2wHn8E", ?network_identity, "network identity changed");
+        let active = self.change(|inner| {
+            inner.network_identity = network_identity;
+            inner.match_network_rules(false)
+        });
+        if let Some(active) = active {
+            self.set_tunnel_target_state(None, Some(active));
+        }
+    }
+
+    pub fn set_dns_watchdog_interventions(&self, interventions: u64) {
+        self.change(|inner| inner.dns_watchdog_interventions = interventions)
+    }
+
+    /// Applies the first matching rule for the current network right away, like joining the network would.
+    pub fn set_network_rules(&self, rules: Vec<NetworkRule>) {
+        let active = self.change(|inner| {
+            inner.config.change(|config| config.network_rules = rules);
+            inner.match_network_rules(true)
+        });
+        if let Some(active) = active {
+            self.set_tunnel_target_state(None, Some(active));
+        }
     }
 
     pub fn set_auto_connect(&self, enable: bool) {
@@ -426,8 +544,10 @@ impl ClientStateHandle {
             exit_update.await.unwrap().map_err(TunnelConnectError::ApiError)?
         };
 
+        let policy = self.borrow().policy.clone();
+        let exits: Vec<OneExit> = exit_list.value.exits.iter().filter(|exit| policy.allows_exit(exit)).cloned().collect();
         let exit = selection_state
-            .select_next_exit(exit_selector, &exit_list.value.exits, &closest_relay)
+            .select_next_exit(exit_selector, &exits, &closest_relay)
             .map(|e| e.id.clone());
         let Some(exit) = exit else {
             tracing::error!(
@@ -593,6 +713,90 @@ impl ClientStateHandle {
         Ok((relay, handshaking))
     }
 
+    /// Attempts handshakes with all relay ports over one transport. Unlike `select_relay` it doesn't stop after finding good candidates.
+    pub async fn probe_relays(&self, use_tcp_tls: bool) -> RelayProbe {
+        const RELAY_PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+        let relays = match self.relays_for_probing().await {
+            Ok(relays) => relays,
+            Err(error) => return RelayProbe::failed(error),
+        };
+        let (network_interface, sni, quic_frame_padding, force_small_mtu, mtu) = self.relay_handshake_params();
+        let attempted = relays.value.iter().take(MAX_RELAYS).map(|relay| relay.ports.len()).sum();
+        let racing_handshakes = match race_relay_handshakes(
+            network_interface.as_ref(),
+            &relays.value,
+            sni,
+            use_tcp_tls,
+            quic_frame_padding,
+            force_small_mtu,
+            mtu,
+        ) {
+            Ok(racing_handshakes) => racing_handshakes,
+            Err(error) => return RelayProbe::failed(error),
+        };
+        let deadline = Instant::now() + RELAY_PROBE_TIMEOUT;
+        let mut reachable = Vec::new();
+        while let Ok(Ok((relay, port, rtt, handshaking))) = timeout_at(deadline.into(), racing_handshakes.recv_async()).await {
+            spawn(handshaking.abandon());
+            reachable.push(RelayReachable { relay_id: relay.id, port, rtt_ms: rtt.as_millis().try_into().unwrap_or(u64::MAX) });
+        }
+        reachable.sort_by_key(|reachable| reachable.rtt_ms);
+        tracing::info!(
+            message_id = "Rk7dWp3M",
+            use_tcp_tls,
+            attempted,
+            reachable = reachable.len(),
+            "relay probe finished"
+        );
+        RelayProbe { attempted, reachable, error: None }
+    }
+
+    /// Handshakes with every relay port over both transports, for comparing relays rather than connecting.
+    pub async fn bench_relays(&self) -> Result<RelayBench, ApiError> {
+        let relays = self.relays_for_probing().await?;
+        let (network_interface, sni, quic_frame_padding, force_small_mtu, mtu) = self.relay_handshake_params();
+        let bench = |use_tcp_tls| {
+            bench_relay_handshakes(
+                network_interface.as_ref(),
+                &relays.value,
+                sni.clone(),
+                use_tcp_tls,
+                quic_frame_padding,
+                force_small_mtu,
+                mtu,
+            )
+        };
+        let (quic, tcp_tls) = tokio::join!(bench(false), bench(true));
+        let mut results: Vec<RelayBenchResult> = quic.into_iter().chain(tcp_tls).collect();
+        results.sort_by_key(|result| (result.rtt_ms.is_none(), result.rtt_ms));
+        tracing::info!(
+            message_id = "Qf8sJn3L",
+            attempted = results.len(),
+            reachable = results.iter().filter(|result| result.rtt_ms.is_some()).count(),
+            "relay benchmark finished"
+        );
+        Ok(RelayBench { results })
+    }
+
+    /// Prefers a fresh relay list, but a cached one is good enough if the API is unreachable.
+    async fn relays_for_probing(&self) -> Result<ConfigCached<Arc<Vec<OneRelay>>>, ApiError> {
+        match self.maybe_update_relays(Duration::from_secs(60)).await {
+            Ok(relays) => Ok(relays),
+            Err(error) => self.get_cached_relay_list().ok_or(error),
+        }
+    }
+
+    fn relay_handshake_params(&self) -> (Option<NetworkInterface>, String, bool, bool, Option<u16>) {
+        let this = self.borrow();
+        (
+            this.network_interface.clone(),
+            this.config.sni_relay.clone().unwrap_or_else(|| DEFAULT_RELAY_SNI.into()),
+            this.config.feature_flags.quic_frame_padding.unwrap_or(false),
+            this.config.feature_flags.force_small_mtu.unwrap_or(false),
+            this.mtu,
+        )
+    }
+
     pub fn make_api_client(&self, account_id: AccountId) -> Result<Client, ApiError> {
         self.borrow().make_api_client(account_id)
     }
@@ -823,9 +1027,11 @@ impl ClientStateHandle {
         let config;
         let network_interface;
         let network_interface_mtu;
+        let dns_watchdog_interventions;
         {
             let this = self.borrow();
             config = this.config().clone().into();
+            dns_watchdog_interventions = this.dns_watchdog_interventions;
             network_interface = this.network_interface.clone();
             network_interface_mtu = this.network_interface.as_ref().and_then(|interface| interface_mtu(interface).ok());
         }
@@ -888,11 +1094,47 @@ impl ClientStateHandle {
             http_obscura: http_obscura.await.unwrap_or_else(debug_panic_error),
             http_obscura_apple: http_obscura_apple.await.unwrap_or_else(debug_panic_error),
             http_obscura_google: http_obscura_google.await.unwrap_or_else(debug_panic_error),
+            dns_watchdog_interventions,
             network_interface,
             network_interface_mtu,
         }
     }
 
+    /// Probes for a captive portal on the physical network. The probe host is resolved like the API host, so it also resolves behind a portal once it is cached.
+    pub async fn detect_captive_portal(&self) -> Result<Option<CaptivePortal>, ()> {
+        captive_portal::probe(DnsResolver::new(WeakClientStateHandle(Arc::downgrade(&self.0)))).await
+    }
+
+    /// Captive portal found by the last probe on the current network interface. Probes in the background if the last probe is older than `captive_portal::PROBE_INTERVAL`, so connection attempts aren't delayed and a later attempt sees the result. On Linux, a probe also starts when the network interface changes.
+    pub fn known_captive_portal(&self) -> Option<CaptivePortal> {
+        let (portal, probe_at) = self.change(|inner| {
+            let portal = inner.captive_portal_probe.as_ref().and_then(|probe| probe.portal.clone());
+            let stale = inner
+                .captive_portal_probe
+                .as_ref()
+                .is_none_or(|probe| probe.at.elapsed() >= captive_portal::PROBE_INTERVAL);
+            let probe_at = stale.then(Instant::now);
+            if let Some(at) = probe_at {
+                // Keeps the previous result until the probe finishes.
+                inner.captive_portal_probe = Some(CaptivePortalProbe { at, portal: portal.clone() });
+            }
+            (portal, probe_at)
+        });
+        if let Some(at) = probe_at {
+            let this = self.clone();
+            spawn(async move {
+                let portal = this.detect_captive_portal().await.ok().flatten();
+                // Discarded if the network interface changed or another probe started meanwhile.
+                this.change(|inner| {
+                    if let Some(probe) = inner.captive_portal_probe.as_mut().filter(|probe| probe.at == at) {
+                        probe.portal = portal;
+                    }
+                });
+            });
+        }
+        portal
+    }
+
     pub fn update_dns_cache(&self, name: &str, addrs: &[SocketAddr]) {
         self.change_config(|config| {
             config.dns_cache.set(name, addrs);
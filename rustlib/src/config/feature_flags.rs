@@ -28,6 +28,20 @@ impl FeatureFlags {
         self.change(flag, active.then_some(true));
     }
 
+    /// Whether `flag` is active. Unknown flags are inactive.
+    pub fn get(&self, flag: &str) -> bool {
+        let Ok(flag) = FeatureFlagKey::from_str(flag) else {
+            return false;
+        };
+        let value = match flag {
+            FeatureFlagKey::QuicFramePadding => self.quic_frame_padding,
+            FeatureFlagKey::KillSwitch => self.kill_switch,
+            FeatureFlagKey::ForceSmallMtu => self.force_small_mtu,
+            FeatureFlagKey::TcpTlsTunnel => self.tcp_tls_tunnel,
+        };
+        value.unwrap_or(false)
+    }
+
     fn change(&mut self, flag: &str, value: Option<bool>) {
         let Ok(flag) = FeatureFlagKey::from_str(flag) else {
             tracing::error!(message_id = "DMArApj9", "unknown feature flag: {:?}", flag);
@@ -63,4 +77,15 @@ mod test {
             assert_eq!(feature_flags.other.len(), 0)
         }
     }
+
+    #[test]
+    fn check_get() {
+        let mut feature_flags = FeatureFlags::default();
+        for flag in FeatureFlags::KEYS {
+            assert!(!feature_flags.get(flag));
+            feature_flags.set(flag, true);
+            assert!(feature_flags.get(flag));
+        }
+        assert!(!feature_flags.get("unknownFlag"));
+    }
 }
@@ -14,6 +14,7 @@ use crate::config::feature_flags::FeatureFlags;
 use crate::exit_selection::ExitSelector;
 use crate::manager::TunnelArgs;
 use crate::network_config::{DnsConfig, DnsContentBlock};
+use crate::network_rules::{NetworkIdentity, NetworkRule};
 use crate::wg_key_store::{PlaintextWgSecretKey, SealedWgSecretKey, WgKeyStore};
 use boringtun::x25519::StaticSecret;
 use chrono::Utc;
@@ -230,6 +231,8 @@ pub struct Config {
     #[serde(deserialize_with = "crate::serde_safe::deserialize")]
     pub api_host_alternate: Option<String>,
     #[serde(deserialize_with = "crate::serde_safe::deserialize")]
+    pub api_doh_bootstrap_urls: Option<Vec<String>>,
+    #[serde(deserialize_with = "crate::serde_safe::deserialize")]
     pub api_url: Option<String>,
     #[serde(deserialize_with = "crate::serde_safe::deserialize")]
     pub account_id: Option<AccountId>,
@@ -278,6 +281,11 @@ pub struct Config {
     pub dns: DnsConfig,
     #[serde(deserialize_with = "crate::serde_safe::deserialize")]
     pub local_network_access: LocalNetworkAccess,
+    #[serde(deserialize_with = "crate::serde_safe::deserialize")]
+    pub network_rules: Vec<NetworkRule>,
+    /// Network the rules were last applied for, so they aren't applied again when the service restarts on the same network.
+    #[serde(deserialize_with = "crate::serde_safe::deserialize")]
+    pub network_rules_identity: Option<NetworkIdentity>,
     #[serde(skip)]
     pub use_wireguard_key_cache: (), // Removed
     #[serde(deserialize_with = "crate::serde_safe::deserialize")]
@@ -307,6 +315,7 @@ pub enum LocalNetworkAccess {
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ConfigDebug {
     pub api_host_alternate: Option<String>,
+    pub api_doh_bootstrap_urls: Option<Vec<String>>,
     pub api_url: Option<String>,
     pub cached_exits: Option<ConfigCached<Arc<ExitList>>>,
     pub cached_relays: Option<ConfigCached<Arc<Vec<OneRelay>>>>,
@@ -323,6 +332,7 @@ pub struct ConfigDebug {
     pub tunnel_args: TunnelArgs,
     pub dns: DnsConfig,
     pub local_network_access: LocalNetworkAccess,
+    pub network_rules: Vec<NetworkRule>,
     pub has_account_id: bool,
     pub has_cached_auth_token: bool,
     pub auto_connect: bool,
@@ -332,6 +342,7 @@ impl From<Config> for ConfigDebug {
     fn from(config: Config) -> Self {
         let Config {
             api_host_alternate,
+            api_doh_bootstrap_urls,
             api_url,
             account_id,
             dns_content_block,
@@ -352,6 +363,8 @@ impl From<Config> for ConfigDebug {
             wireguard_key_cache: _,
             dns,
             local_network_access,
+            network_rules,
+            network_rules_identity: _,
             use_wireguard_key_cache: (),
             cached_account_status: _,
             auto_connect,
@@ -372,9 +385,11 @@ impl From<Config> for ConfigDebug {
             last_chosen_exit_selector,
             last_exit_selector,
             api_host_alternate,
+            api_doh_bootstrap_urls,
             sni_relay,
             dns,
             local_network_access,
+            network_rules,
             has_account_id: account_id.is_some(),
             has_cached_auth_token: cached_auth_token.is_some(),
             auto_connect,
@@ -562,6 +577,14 @@ impl WireGuardKeyCache {
             None => self.rotate_now_internal(RotationReason::NoKeyPair, key_store),
         }
     }
+    pub fn public_key(&self) -> Option<WgPubkey> {
+        match &self.key_pair {
+            Some(WireGuardKeyCacheKeyPair::Plaintext { secret_key }) => Some(secret_key.public_key()),
+            Some(WireGuardKeyCacheKeyPair::Sealed { sealed_secret_key: _, public_key, secret_key: _ }) => Some(*public_key),
+            Some(WireGuardKeyCacheKeyPair::Detached { public_key, secret_key: _ }) => Some(*public_key),
+            None => None,
+        }
+    }
     pub fn use_key_pair(&mut self, key_store: &WgKeyStore) -> (StaticSecret, WgPubkey) {
         let (secret_key, public_key) = self.ensure_key_pair(key_store);
         let now = SystemTime::now();
@@ -574,12 +597,7 @@ impl WireGuardKeyCache {
     fn rotate_now_internal(&mut self, reason: RotationReason, key_store: &WgKeyStore) -> (StaticSecret, WgPubkey) {
         tracing::info!(message_id = "65KkXAbB", ?reason, "rotating wireguard key pair");
         let mut old_public_keys = std::mem::take(&mut self.old_public_keys);
-        let current_public_key = match &self.key_pair {
-            Some(WireGuardKeyCacheKeyPair::Plaintext { secret_key }) => Some(secret_key.public_key()),
-            Some(WireGuardKeyCacheKeyPair::Sealed { sealed_secret_key: _, public_key, secret_key: _ }) => Some(*public_key),
-            Some(WireGuardKeyCacheKeyPair::Detached { public_key, secret_key: _ }) => Some(*public_key),
-            None => None,
-        };
+        let current_public_key = self.public_key();
         if let Some(current_public_key) = current_public_key {
             old_public_keys.push(current_public_key);
         }
@@ -627,12 +645,7 @@ impl WireGuardKeyCache {
         None
     }
     pub fn registered(&mut self, registered_public_key: WgPubkey, removed_public_keys: &[WgPubkey]) {
-        let current_public_key = match &self.key_pair {
-            Some(WireGuardKeyCacheKeyPair::Plaintext { secret_key }) => Some(secret_key.public_key()),
-            Some(WireGuardKeyCacheKeyPair::Sealed { sealed_secret_key: _, public_key, secret_key: _ }) => Some(*public_key),
-            Some(WireGuardKeyCacheKeyPair::Detached { public_key, secret_key: _ }) => Some(*public_key),
-            None => None,
-        };
+        let current_public_key = self.public_key();
         if Some(registered_public_key) == current_public_key {
             self.registered_at = Some(SystemTime::now());
         }
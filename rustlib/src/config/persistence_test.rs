@@ -22,6 +22,10 @@ use crate::config::cached::ConfigCached;
 use crate::config::load;
 use crate::config::save;
 use crate::exit_selection::ExitSelector;
+use crate::network_rules::NetworkAction;
+use crate::network_rules::NetworkIdentity;
+use crate::network_rules::NetworkMatch;
+use crate::network_rules::NetworkRule;
 use crate::wg_key_store::WgKeyStore;
 
 fn random_config() -> Config {
@@ -50,6 +54,20 @@ fn load_config() {
     assert_eq!(load(dir.as_ref(), &WgKeyStore::Plaintext).unwrap(), config);
 }
 
+#[test]
+fn load_api_doh_bootstrap_urls() {
+    let config = Config { api_doh_bootstrap_urls: Some(vec!["https://doh.example/dns-query".into()]), ..random_config() };
+
+    let dir = tempdir().unwrap();
+
+    save(dir.as_ref(), &config).unwrap();
+
+    assert_eq!(
+        load(dir.as_ref(), &WgKeyStore::Plaintext).unwrap().api_doh_bootstrap_urls,
+        config.api_doh_bootstrap_urls
+    );
+}
+
 #[test]
 fn load_invalid_json() {
     let dir = tempdir().unwrap();
@@ -124,6 +142,7 @@ fn load_no_permission() {
 fn test_ignore_invalid_fields() {
     let example_config = Config {
         api_host_alternate: Some("relay.example".into()),
+        api_doh_bootstrap_urls: Some(vec!["https://doh.example/dns-query".into()]),
         api_url: Some("myapi".into()),
         account_id: Some(AccountId::from_string_unchecked("myaccount".into())),
         old_account_ids: vec![AccountId::from_string_unchecked("oldaccount".into())],
@@ -169,6 +188,12 @@ fn test_ignore_invalid_fields() {
         wireguard_key_cache: Default::default(),
         dns: Default::default(),
         local_network_access: Default::default(),
+        network_rules: vec![NetworkRule {
+            name: "Office".into(),
+            network: NetworkMatch { interface_name: Some("eth0".into()), ..Default::default() },
+            action: NetworkAction::Disconnect,
+        }],
+        network_rules_identity: Some(NetworkIdentity { interface_name: "eth0".into(), ..Default::default() }),
         dns_cache: Default::default(),
         use_wireguard_key_cache: (),
         cached_account_status: Default::default(),
@@ -9,4 +9,5 @@ pub const DEFAULT_API_IP_SEED: IpAddr = IpAddr::V4(Ipv4Addr::new(66, 42, 95, 12)
 pub const DNS_CACHE_SEED: &[(&str, &[SocketAddr])] = &[(DEFAULT_API_DOMAIN, &[SocketAddr::new(DEFAULT_API_IP_SEED, 0)])];
 
 pub const DEFAULT_API_BACKUP_DOMAIN: &str = "crimsonlance.net";
+pub const DEFAULT_API_DOH_BOOTSTRAP_URLS: &[&str] = &["https://1.1.1.1/dns-query", "https://9.9.9.9/dns-query", "https://8.8.8.8/dns-query"];
 pub const DEFAULT_RELAY_SNI: &str = "example.com";
@@ -17,6 +17,8 @@ pub struct DebugInfo {
     pub http_obscura: DebugTask<DebugTaskHttp>,
     pub http_obscura_apple: DebugTask<DebugTaskHttp>,
     pub http_obscura_google: DebugTask<DebugTaskHttp>,
+    #[serde(default)]
+    pub dns_watchdog_interventions: u64,
     pub network_interface: Option<NetworkInterface>,
     pub network_interface_mtu: Option<i32>,
 }
@@ -25,6 +25,10 @@ pub struct DebugTaskHttp {
 }
 
 impl DebugTaskHttp {
+    pub fn status_code(&self) -> Option<u16> {
+        self.status_code
+    }
+
     pub async fn run(url: &'static str, addrs: Option<Vec<IpAddr>>, sni: bool, fwmark: Option<u32>) -> Result<Self, Box<dyn std::error::Error>> {
         if let Some(addrs) = &addrs
             && addrs.is_empty()
@@ -45,7 +49,7 @@ impl DebugTaskHttp {
             url: url.to_owned(),
         };
 
-        let builder = reqwest::Client::builder()
+        let builder = client_builder(fwmark)
             .danger_accept_invalid_certs(true)
             .min_tls_version(reqwest::tls::Version::TLS_1_0)
             .timeout(Duration::from_secs(55))
@@ -54,8 +58,6 @@ impl DebugTaskHttp {
             Some(addrs) => builder.dns_resolver(Arc::new(FixedResolver(addrs))),
             None => builder,
         };
-        #[cfg(target_os = "linux")]
-        let builder = builder.so_mark(fwmark);
         let client = builder.build()?;
 
         let res = match client.get(url).send().await {
@@ -89,6 +91,15 @@ impl DebugTaskHttp {
     }
 }
 
+/// Client builder whose connections are marked with `fwmark` on Linux, so they bypass the tunnel.
+#[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
+pub(crate) fn client_builder(fwmark: Option<u32>) -> reqwest::ClientBuilder {
+    let builder = reqwest::Client::builder();
+    #[cfg(target_os = "linux")]
+    let builder = builder.so_mark(fwmark);
+    builder
+}
+
 struct FixedResolver(Vec<IpAddr>);
 
 impl Resolve for FixedResolver {
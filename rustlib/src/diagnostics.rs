@@ -0,0 +1,231 @@
+//! Connectivity diagnostics combining the debug info probes with relay reachability over both transports, summarized as human-readable verdicts and recommended actions.
+
+use std::net::IpAddr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::feature_flags::FeatureFlagKey;
+use crate::debug_bundle::debug_info::DebugInfo;
+use crate::debug_bundle::dns::DebugTaskDns;
+use crate::debug_bundle::http::DebugTaskHttp;
+use crate::debug_bundle::task::DebugTask;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RelayReachable {
+    pub relay_id: String,
+    pub port: u16,
+    pub rtt_ms: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RelayProbe {
+    /// Number of relay ports a handshake was attempted with.
+    pub attempted: usize,
+    pub reachable: Vec<RelayReachable>,
+    /// Set if the probe could not be started, e.g. because no relay list is available.
+    pub error: Option<String>,
+}
+
+impl RelayProbe {
+    pub fn failed(error: impl ToString) -> Self {
+        Self { attempted: 0, reachable: Vec::new(), error: Some(error.to_string()) }
+    }
+
+    fn works(&self) -> bool {
+        !self.reachable.is_empty()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnosticsReport {
+    /// While connected the DNS and HTTP probes run through the tunnel, only relay probes bypass it.
+    pub vpn_connected: bool,
+    pub debug_info: DebugInfo,
+    pub relays_quic: RelayProbe,
+    pub relays_tcp_tls: RelayProbe,
+    pub verdicts: Vec<String>,
+    /// Actions likely to help, left to the UI to present.
+    #[serde(default)]
+    pub recommendations: Vec<Recommendation>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", rename_all_fields = "camelCase")]
+pub enum Recommendation {
+    EnableFeatureFlag { flag: String },
+}
+
+impl DiagnosticsReport {
+    pub fn new(vpn_connected: bool, debug_info: DebugInfo, relays_quic: RelayProbe, relays_tcp_tls: RelayProbe) -> Self {
+        let mut report = Self {
+            vpn_connected,
+            debug_info,
+            relays_quic,
+            relays_tcp_tls,
+            verdicts: Vec::new(),
+            recommendations: Vec::new(),
+        };
+        report.verdicts = report.verdicts();
+        report.recommendations = report.recommendations();
+        report
+    }
+
+    fn verdicts(&self) -> Vec<String> {
+        let info = &self.debug_info;
+        let mut verdicts = Vec::new();
+        if self.vpn_connected {
+            verdicts.push("VPN is connected, DNS and HTTP checks ran through the tunnel".to_string());
+        }
+
+        let dns_works = dns_addrs(&info.dns_google).is_some() || dns_addrs(&info.dns_apple).is_some();
+        let http_works = http_responds(&info.http_google) || http_responds(&info.http_apple);
+        if !dns_works && !http_works {
+            verdicts.push("No internet connectivity: DNS lookups and HTTPS requests fail".to_string());
+        } else if !dns_works {
+            verdicts.push("DNS is not working, but HTTPS to known addresses works".to_string());
+        }
+
+        if captive_portal_suspected(info) {
+            verdicts.push("Captive portal suspected, sign in to the network in a browser".to_string());
+        }
+
+        if dns_works && dns_addrs(&info.dns_obscura).is_none() {
+            verdicts.push("API DNS blocked: the API hostname does not resolve while other names do".to_string());
+        } else if !http_works_status(&info.http_obscura) {
+            if http_works_status(&info.http_nosni) || http_works_status(&info.http_obscura_google) || http_works_status(&info.http_obscura_apple) {
+                verdicts.push("API blocked by SNI filtering, the API server is reachable with a different SNI".to_string());
+            } else if http_works {
+                verdicts.push("API unreachable while other sites work, the API addresses are likely blocked".to_string());
+            }
+        }
+
+        let relays_unavailable = self.relays_quic.error.is_some() && self.relays_tcp_tls.error.is_some();
+        verdicts.push(
+            match (self.relays_quic.works(), self.relays_tcp_tls.works()) {
+                _ if relays_unavailable => "Relay reachability unknown, no relay list available",
+                (true, true) => "Relays reachable over UDP and TCP/TLS",
+                (false, true) => "UDP blocked, TCP/TLS works",
+                (true, false) => "TCP/TLS to relays blocked, UDP works",
+                (false, false) => "Relays unreachable over UDP and TCP/TLS",
+            }
+            .to_string(),
+        );
+        verdicts
+    }
+
+    fn recommendations(&self) -> Vec<Recommendation> {
+        let mut recommendations = Vec::new();
+        if !self.relays_quic.works() && self.relays_tcp_tls.works() {
+            let flag: &str = FeatureFlagKey::TcpTlsTunnel.into();
+            recommendations.push(Recommendation::EnableFeatureFlag { flag: flag.to_string() });
+        }
+        recommendations
+    }
+}
+
+fn dns_addrs(task: &DebugTask<DebugTaskDns>) -> Option<&[IpAddr]> {
+    task.result.get().map(|dns| dns.addrs.as_slice()).filter(|addrs| !addrs.is_empty())
+}
+
+/// Any HTTP response counts, because probes like `http_nosni` are expected to get error statuses.
+fn http_responds(task: &DebugTask<DebugTaskHttp>) -> bool {
+    task.result.get().and_then(DebugTaskHttp::status_code).is_some()
+}
+
+fn http_works_status(task: &DebugTask<DebugTaskHttp>) -> bool {
+    task.result
+        .get()
+        .and_then(DebugTaskHttp::status_code)
+        .is_some_and(|status| (200..300).contains(&status))
+}
+
+fn captive_portal_suspected(info: &DebugInfo) -> bool {
+    let public_names = [&info.dns_google, &info.dns_apple, &info.dns_obscura];
+    let addrs: Vec<&[IpAddr]> = public_names.iter().filter_map(|task| dns_addrs(task)).collect();
+    // Portals commonly answer every DNS query with their own, usually private, address.
+    let hijacked_dns = addrs.iter().flat_map(|addrs| addrs.iter()).any(|addr| is_private(*addr))
+        || (addrs.len() > 1 && addrs.windows(2).all(|pair| pair[0] == pair[1]));
+    // Or they intercept HTTPS and redirect to a login page.
+    let redirected = [&info.http_google, &info.http_apple]
+        .iter()
+        .filter_map(|task| task.result.get().and_then(DebugTaskHttp::status_code))
+        .any(|status| (300..400).contains(&status) || status == 511);
+    hijacked_dns || redirected
+}
+
+fn is_private(addr: IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(addr) => addr.is_private() || addr.is_loopback() || addr.is_link_local() || addr.is_unspecified(),
+        IpAddr::V6(addr) => addr.is_loopback() || addr.is_unspecified() || addr.is_unique_local() || addr.is_unicast_link_local(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dns(addrs: &[&str]) -> DebugTask<DebugTaskDns> {
+        let addrs: Vec<IpAddr> = addrs.iter().map(|addr| addr.parse().unwrap()).collect();
+        serde_json::from_value(serde_json::json!({"total_s": 0.1, "result": {"Success": {"host": "example.com", "addrs": addrs}}})).unwrap()
+    }
+
+    fn http(status_code: Option<u16>) -> DebugTask<DebugTaskHttp> {
+        serde_json::from_value(serde_json::json!({"total_s": 0.1, "result": {"Success": {
+            "addrs": null, "body": null, "body_truncated": false, "error": null, "fwmark": null, "header_content_type": null,
+            "header_date": null, "http_version": null, "sni": true, "status_code": status_code, "url": "https://example.com"
+        }}}))
+        .unwrap()
+    }
+
+    fn debug_info(dns_obscura: &[&str], http_obscura: Option<u16>, http_google: Option<u16>) -> DebugInfo {
+        DebugInfo {
+            config: crate::config::Config::default().into(),
+            dns_apple: dns(&["17.253.144.10"]),
+            dns_google: dns(&["142.250.185.78"]),
+            dns_obscura: dns(dns_obscura),
+            http_apple: http(Some(200)),
+            http_google: http(http_google),
+            http_nosni: http(None),
+            http_obscura: http(http_obscura),
+            http_obscura_apple: http(None),
+            http_obscura_google: http(None),
+            dns_watchdog_interventions: 0,
+            network_interface: None,
+            network_interface_mtu: None,
+        }
+    }
+
+    fn relays(reachable: bool) -> RelayProbe {
+        let reachable = if reachable {
+            vec![RelayReachable { relay_id: "relay".to_string(), port: 443, rtt_ms: 20 }]
+        } else {
+            Vec::new()
+        };
+        RelayProbe { attempted: 4, reachable, error: None }
+    }
+
+    #[test]
+    fn test_verdicts() {
+        let report = DiagnosticsReport::new(false, debug_info(&["198.51.100.1"], Some(200), Some(200)), relays(false), relays(true));
+        assert_eq!(report.verdicts, ["UDP blocked, TCP/TLS works"]);
+        assert_eq!(
+            report.recommendations,
+            [Recommendation::EnableFeatureFlag { flag: "tcpTlsTunnel".to_string() }]
+        );
+
+        let report = DiagnosticsReport::new(false, debug_info(&[], None, Some(200)), relays(true), relays(true));
+        assert_eq!(
+            report.verdicts,
+            [
+                "API DNS blocked: the API hostname does not resolve while other names do",
+                "Relays reachable over UDP and TCP/TLS"
+            ]
+        );
+
+        let report = DiagnosticsReport::new(false, debug_info(&["10.0.0.1"], None, Some(302)), relays(false), relays(false));
+        assert_eq!(report.verdicts[0], "Captive portal suspected, sign in to the network in a browser");
+    }
+}
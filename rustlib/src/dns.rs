@@ -1,10 +1,16 @@
 use crate::client_state::WeakClientStateHandle;
+use crate::config::Config;
+use crate::constants::DEFAULT_API_DOH_BOOTSTRAP_URLS;
+use crate::doh;
+use futures::future::{BoxFuture, select_ok};
 use obscuravpn_api::reexports::reqwest::dns::{Addrs, Name, Resolve, Resolving};
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::time::timeout;
 
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
 pub struct DnsResolver {
     client_state: WeakClientStateHandle,
 }
@@ -40,27 +46,26 @@ impl Resolve for DnsResolver {
     }
 }
 
-async fn resolve_and_cache(client_state: WeakClientStateHandle, name: String) -> Result<Vec<SocketAddr>, Box<dyn std::error::Error + Send + Sync>> {
+async fn resolve_and_cache(client_state: WeakClientStateHandle, name: String) -> Result<Vec<SocketAddr>, BoxError> {
     const TIMEOUT: Duration = Duration::from_secs(60);
 
     let name = name.as_str();
-    match timeout(TIMEOUT, tokio::net::lookup_host((name, 0u16))).await {
-        Ok(Ok(addrs)) => {
-            let addrs: Vec<_> = addrs.collect();
-            if !addrs.is_empty() {
-                tracing::info!(message_id = "ea1Ooquu", name, ?addrs, "DNS resolution succeeded");
-                match client_state.upgrade() {
-                    None => tracing::warn!(message_id = "2aZU1KWD", "can't write to DNS cache of dropped client state"),
-                    Some(client_state) => client_state.update_dns_cache(name, &addrs),
-                }
-            } else {
-                tracing::warn!(message_id = "Uu3ohPh4", name, "DNS resolution returned no addresses");
+    let doh_urls = match client_state.upgrade() {
+        None => Vec::new(),
+        Some(client_state) => doh_bootstrap_urls(client_state.borrow().config()),
+    };
+    match timeout(TIMEOUT, resolve_racing(name, &doh_urls)).await {
+        Ok(Ok((source, addrs))) => {
+            tracing::info!(message_id = "ea1Ooquu", name, source, ?addrs, "DNS resolution succeeded");
+            match client_state.upgrade() {
+                None => tracing::warn!(message_id = "2aZU1KWD", "can't write to DNS cache of dropped client state"),
+                Some(client_state) => client_state.update_dns_cache(name, &addrs),
             }
             Ok(addrs)
         }
         Ok(Err(error)) => {
             tracing::warn!(message_id = "ieC5ahv3", name, ?error, "DNS resolution failed: {error}");
-            Err(error.into())
+            Err(error)
         }
         Err(error) => {
             tracing::warn!(message_id = "RwX9EdwE", name, ?error, "DNS resolution timed out: {error}");
@@ -68,3 +73,48 @@ async fn resolve_and_cache(client_state: WeakClientStateHandle, name: String) ->
         }
     }
 }
+
+fn doh_bootstrap_urls(config: &Config) -> Vec<String> {
+    match &config.api_doh_bootstrap_urls {
+        Some(urls) => urls.clone(),
+        None => DEFAULT_API_DOH_BOOTSTRAP_URLS.iter().map(ToString::to_string).collect(),
+    }
+}
+
+// Races the system resolver against all DoH bootstrap endpoints and returns the first non-empty result, so a blocked or censored local resolver doesn't prevent reaching the API.
+async fn resolve_racing<'a>(name: &'a str, doh_urls: &'a [String]) -> Result<(&'a str, Vec<SocketAddr>), BoxError> {
+    let system: BoxFuture<'a, Result<(&'a str, Vec<SocketAddr>), BoxError>> = Box::pin(async move {
+        let addrs: Vec<_> = tokio::net::lookup_host((name, 0u16)).await?.collect();
+        if addrs.is_empty() {
+            tracing::warn!(message_id = "Uu3ohPh4", name, "DNS resolution returned no addresses");
+            return Err("system resolver returned no addresses".into());
+        }
+        Ok(("system", addrs))
+    });
+    let doh = doh_urls.iter().map(|url| -> BoxFuture<'a, Result<(&'a str, Vec<SocketAddr>), BoxError>> {
+        Box::pin(async move {
+            let addrs = doh::resolve(url, name).await.map_err(|error| {
+                tracing::warn!(
+                    message_id = "hN6xRb2W",
+                    name,
+                    url = url.as_str(),
+                    ?error,
+                    "DoH bootstrap resolution failed: {error}"
+                );
+                error
+            })?;
+            if addrs.is_empty() {
+                tracing::warn!(
+                    message_id = "bS9kLm4T",
+                    name,
+                    url = url.as_str(),
+                    "DoH bootstrap resolution returned no addresses"
+                );
+                return Err("DoH resolver returned no addresses".into());
+            }
+            Ok((url.as_str(), addrs.into_iter().map(|ip| SocketAddr::new(ip, 0)).collect()))
+        })
+    });
+    let (result, _pending) = select_ok(std::iter::once(system).chain(doh)).await?;
+    Ok(result)
+}
@@ -0,0 +1,195 @@
+//! Minimal DNS-over-HTTPS (RFC 8484) client used to bootstrap API hostname resolution without relying on the local network resolver.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+use thiserror::Error;
+
+const CONTENT_TYPE: &str = "application/dns-message";
+const TIMEOUT: Duration = Duration::from_secs(10);
+const RESPONSE_LIMIT: usize = 64 * 1024;
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+#[derive(Debug, Error)]
+pub enum DohError {
+    #[error("invalid query name")]
+    InvalidName,
+    #[error("request failed: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("unexpected HTTP status: {0}")]
+    Status(u16),
+    #[error("response too large")]
+    ResponseTooLarge,
+    #[error("malformed response")]
+    Malformed,
+    #[error("DNS error response code: {0}")]
+    ResponseCode(u8),
+}
+
+/// Resolves `name` to IPv4 and IPv6 addresses by querying the DoH endpoint at `url`. The endpoint should be addressed by IP, otherwise resolving the endpoint itself would hit the system resolver.
+pub async fn resolve(url: &str, name: &str) -> Result<Vec<IpAddr>, DohError> {
+    let builder = reqwest::Client::builder().timeout(TIMEOUT);
+    #[cfg(target_os = "linux")]
+    let builder = builder.so_mark(Some(crate::net::FWMARK));
+    let client = builder.build()?;
+    let (v4, v6) = futures::join!(query(&client, url, name, TYPE_A), query(&client, url, name, TYPE_AAAA));
+    match (v4, v6) {
+        (Ok(mut v4), Ok(v6)) => {
+            v4.extend(v6);
+            Ok(v4)
+        }
+        (Ok(addrs), Err(error)) | (Err(error), Ok(addrs)) => {
+            tracing::warn!(message_id = "Tq8vWn3K", url, name, ?error, "partial DoH resolution failure: {error}");
+            Ok(addrs)
+        }
+        (Err(error), Err(_)) => Err(error),
+    }
+}
+
+async fn query(client: &reqwest::Client, url: &str, name: &str, record_type: u16) -> Result<Vec<IpAddr>, DohError> {
+    let message = encode_query(name, record_type)?;
+    let res = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, CONTENT_TYPE)
+        .header(reqwest::header::ACCEPT, CONTENT_TYPE)
+        .body(message)
+        .send()
+        .await?;
+    if !res.status().is_success() {
+        return Err(DohError::Status(res.status().as_u16()));
+    }
+    let body = res.bytes().await?;
+    if body.len() > RESPONSE_LIMIT {
+        return Err(DohError::ResponseTooLarge);
+    }
+    decode_response(&body, record_type)
+}
+
+fn encode_query(name: &str, record_type: u16) -> Result<Vec<u8>, DohError> {
+    // ID 0 is recommended by RFC 8484 to maximize HTTP cache friendliness. Only the recursion desired flag is set.
+    let mut message = vec![0, 0, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in name.trim_end_matches('.').split('.') {
+        let len = u8::try_from(label.len()).map_err(|_| DohError::InvalidName)?;
+        if len == 0 || len > 63 {
+            return Err(DohError::InvalidName);
+        }
+        message.push(len);
+        message.extend_from_slice(label.as_bytes());
+    }
+    message.push(0);
+    message.extend_from_slice(&record_type.to_be_bytes());
+    message.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(message)
+}
+
+fn decode_response(message: &[u8], record_type: u16) -> Result<Vec<IpAddr>, DohError> {
+    let mut reader = Reader { message, pos: 0 };
+    let _id = reader.u16()?;
+    let flags = reader.u16()?;
+    if flags & 0x8000 == 0 {
+        return Err(DohError::Malformed);
+    }
+    let response_code = u8::try_from(flags & 0x000f).map_err(|_| DohError::Malformed)?;
+    if response_code != 0 {
+        return Err(DohError::ResponseCode(response_code));
+    }
+    let question_count = reader.u16()?;
+    let answer_count = reader.u16()?;
+    let _authority_count = reader.u16()?;
+    let _additional_count = reader.u16()?;
+    for _ in 0..question_count {
+        reader.skip_name()?;
+        reader.take(4)?;
+    }
+    let mut addrs = Vec::new();
+    for _ in 0..answer_count {
+        reader.skip_name()?;
+        let answer_type = reader.u16()?;
+        let answer_class = reader.u16()?;
+        let _ttl = reader.take(4)?;
+        let len = reader.u16()?;
+        let data = reader.take(usize::from(len))?;
+        if answer_class != CLASS_IN || answer_type != record_type {
+            // CNAME chains and unrelated records
+            continue;
+        }
+        match (answer_type, data.len()) {
+            (TYPE_A, 4) => addrs.push(IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(data).unwrap()))),
+            (TYPE_AAAA, 16) => addrs.push(IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(data).unwrap()))),
+            _ => return Err(DohError::Malformed),
+        }
+    }
+    Ok(addrs)
+}
+
+struct Reader<'a> {
+    message: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], DohError> {
+        let end = self.pos.checked_add(n).ok_or(DohError::Malformed)?;
+        let bytes = self.message.get(self.pos..end).ok_or(DohError::Malformed)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, DohError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn skip_name(&mut self) -> Result<(), DohError> {
+        loop {
+            let len = self.take(1)?[0];
+            match len {
+                0 => return Ok(()),
+                // Compression pointers always terminate the name.
+                len if len & 0xc0 == 0xc0 => {
+                    self.take(1)?;
+                    return Ok(());
+                }
+                len if len & 0xc0 == 0 => {
+                    self.take(usize::from(len))?;
+                }
+                _ => return Err(DohError::Malformed),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_query_labels() {
+        let query = encode_query("v1.api.obscura.net.", TYPE_A).unwrap();
+        assert_eq!(&query[..12], &[0, 0, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&query[12..], b"\x02v1\x03api\x07obscura\x03net\x00\x00\x01\x00\x01");
+        assert!(encode_query("a..b", TYPE_A).is_err());
+    }
+
+    #[test]
+    fn decode_response_with_cname_and_compression() {
+        let mut response = vec![0, 0, 0x81, 0x80, 0, 1, 0, 2, 0, 0, 0, 0];
+        response.extend_from_slice(b"\x03api\x07example\x03com\x00\x00\x01\x00\x01");
+        // CNAME answer pointing at the question name
+        response.extend_from_slice(&[0xc0, 12, 0, 5, 0, 1, 0, 0, 0, 60, 0, 2, 0xc0, 12]);
+        response.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 66, 42, 95, 12]);
+        assert_eq!(decode_response(&response, TYPE_A).unwrap(), [IpAddr::V4(Ipv4Addr::new(66, 42, 95, 12))]);
+    }
+
+    #[test]
+    fn decode_response_errors() {
+        let nxdomain = [0, 0, 0x81, 0x83, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert!(matches!(decode_response(&nxdomain, TYPE_A), Err(DohError::ResponseCode(3))));
+        let query = encode_query("example.com", TYPE_A).unwrap();
+        assert!(matches!(decode_response(&query, TYPE_A), Err(DohError::Malformed)));
+        let truncated = [0, 0, 0x81, 0x80, 0, 0, 0, 1];
+        assert!(matches!(decode_response(&truncated, TYPE_A), Err(DohError::Malformed)));
+    }
+}
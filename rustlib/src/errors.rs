@@ -27,6 +27,8 @@ pub enum ConnectErrorCode {
     ApiError,
     ApiRateLimitExceeded,
     ApiUnreachable,
+    /// Only detected on Linux, by a probe running in the background. If the probe hasn't finished when the first attempt on a new network fails, that attempt reports `ApiUnreachable` or `Other` and a later retry reports this.
+    CaptivePortal,
     InvalidAccountId,
     NoInternet,
     NoLongerSupported,
@@ -78,6 +80,7 @@ impl From<&TunnelConnectError> for ConnectErrorCode {
                     }
                 },
             },
+            TunnelConnectError::CaptivePortal => Self::CaptivePortal,
             TunnelConnectError::NoInternet => Self::NoInternet,
             TunnelConnectError::NetworkConfig(_)
             | TunnelConnectError::NoExit
@@ -97,6 +100,8 @@ impl From<&TunnelConnectError> for ConnectErrorCode {
 pub enum TunnelConnectError {
     #[error("tunnel creation: {0}")]
     ApiError(#[from] ApiError),
+    #[error("captive portal detected")]
+    CaptivePortal,
     #[error("failed to save config file")]
     ConfigSave(#[from] ConfigSaveError),
     #[error("api returned invalid tunnel id")]
@@ -121,6 +126,18 @@ pub enum TunnelConnectError {
     UnexpectedTunnelKind,
 }
 
+impl TunnelConnectError {
+    /// Whether the API or relays were unreachable, which is what connecting behind a captive portal looks like.
+    pub fn is_unreachable(&self) -> bool {
+        matches!(
+            self,
+            Self::ApiError(ApiError::ApiClient(ClientError::RequestExecError(_)))
+                | Self::RelaySelection(RelaySelectionError::NoSuccess)
+                | Self::TunnelConnect(_)
+        )
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum ApiError {
     #[error(transparent)]
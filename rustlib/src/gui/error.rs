@@ -6,6 +6,7 @@ use obscuravpn_client::manager_cmd::ManagerCmdErrorCode;
 pub enum LinuxIpcErrorCode {
     InsufficientPermissions,
     NoListener,
+    UnsupportedCommand,
     VersionMismatch,
 }
 
@@ -68,6 +69,7 @@ impl From<&LinuxIpcError> for LinuxErrorCode {
             LinuxIpcError::InsufficientPermissions => Self::Ipc(LinuxIpcErrorCode::InsufficientPermissions),
             LinuxIpcError::NoListener => Self::Ipc(LinuxIpcErrorCode::NoListener),
             LinuxIpcError::VersionMismatch { service_version: _, app_version: _ } => Self::Ipc(LinuxIpcErrorCode::VersionMismatch),
+            LinuxIpcError::UnsupportedCommand { command: _ } => Self::Ipc(LinuxIpcErrorCode::UnsupportedCommand),
             LinuxIpcError::Other => Self::Other,
         }
     }
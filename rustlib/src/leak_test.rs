@@ -0,0 +1,41 @@
+//! Result of the leak self-test. The checks depend on the OS network integration and are run by the platform service, this module only defines the report shared with clients.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LeakTestOutcome {
+    Pass,
+    Fail,
+    /// The check could not be performed, e.g. because a required tool is missing. Does not fail the test.
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LeakTestCheck {
+    pub name: String,
+    pub outcome: LeakTestOutcome,
+    pub details: Vec<String>,
+}
+
+impl LeakTestCheck {
+    pub fn new(name: &str, outcome: LeakTestOutcome, details: Vec<String>) -> Self {
+        Self { name: name.to_owned(), outcome, details }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LeakTestReport {
+    pub passed: bool,
+    pub checks: Vec<LeakTestCheck>,
+}
+
+impl LeakTestReport {
+    pub fn new(checks: Vec<LeakTestCheck>) -> Self {
+        let passed =
+            checks.iter().any(|check| check.outcome == LeakTestOutcome::Pass) && checks.iter().all(|check| check.outcome != LeakTestOutcome::Fail);
+        Self { passed, checks }
+    }
+}
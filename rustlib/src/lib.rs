@@ -2,16 +2,23 @@
 pub mod rate_limited_log;
 
 pub mod backoff;
+pub mod captive_portal;
 pub mod client_state;
 pub mod config;
+pub mod diagnostics;
 pub mod errors;
 pub mod exit_selection;
 pub mod ffi_helpers;
 pub mod int_helper;
+pub mod leak_test;
+pub mod log_query;
 pub mod manager;
 pub mod manager_cmd;
+pub mod manager_event;
 pub mod net;
 pub mod network_config;
+pub mod network_rules;
+pub mod policy;
 pub mod quicwg;
 pub mod relay_selection;
 mod serde_safe;
@@ -27,10 +34,11 @@ mod backoff_test;
 pub mod android;
 #[cfg(any(target_os = "macos", target_os = "ios"))]
 pub mod apple;
-mod cached_value;
+pub mod cached_value;
 mod constants;
 pub mod debug_bundle;
 mod dns;
+mod doh;
 #[cfg(target_os = "linux")]
 pub mod linux;
 mod liveness;
@@ -6,48 +6,151 @@ use std::io::ErrorKind;
 use std::iter::once;
 use std::os::unix::fs::MetadataExt;
 use std::os::unix::process::CommandExt;
+use strum::VariantNames;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::UnixStream;
 
 use super::argv0;
 use crate::int_helper::u32_into_usize;
-use crate::manager_cmd::{ManagerCmd, ManagerCmdErrorCode};
+use crate::manager_cmd::{AccessLevel, ManagerCmd, ManagerCmdErrorCode};
+use crate::manager_event::{EventSubscription, ManagerEvent};
 use crate::version::release_version;
 
 pub const SOCKET_PATH: &str = "/run/obscura.sock";
 
+/// Version of the framing and connection semantics. Only incremented for changes which older peers can't handle, additional commands are announced via [`LinuxIpcHeader::commands`] instead.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Oldest protocol version this binary can still speak. Version 0 are peers predating protocol negotiation, see [`LinuxIpcHeader::is_legacy`].
+pub const MIN_PROTOCOL_VERSION: u32 = 0;
+
+/// Commands known to services predating protocol negotiation, which don't announce their commands.
+const LEGACY_COMMANDS: &[&str] = &[
+    "apiAppleAssociateAccount",
+    "apiDeleteAccount",
+    "apiGetAccountInfo",
+    "apiGoogleAssociateAccount",
+    "apiGoogleBillingDetails",
+    "createDebugBundle",
+    "createServiceDebugBundle",
+    "getDebugInfo",
+    "getExitList",
+    "getStatus",
+    "getTrafficStats",
+    "terminateProcess",
+    "login",
+    "logout",
+    "ping",
+    "refreshExitList",
+    "deleteServiceDebugBundle",
+    "rotateWgKey",
+    "setApiHostAlternate",
+    "setApiUrl",
+    "setAutoConnect",
+    "setDnsContentBlock",
+    "setFeatureFlag",
+    "setInNewAccountFlow",
+    "setPinnedExits",
+    "setSniRelay",
+    "setTunnelArgs",
+    "setUseSystemDns",
+    "setLocalNetworkAccess",
+];
+
+/// Sent by the service when a client connects.
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct LinuxIpcHeader {
     pub version: String,
+    /// Services predating protocol negotiation don't send the protocol fields, which are therefore treated as protocol version 0.
+    #[serde(default)]
+    pub protocol: u32,
+    #[serde(default)]
+    pub min_protocol: u32,
+    /// Names of the supported `ManagerCmd` variants.
+    #[serde(default)]
+    pub commands: Vec<String>,
+    /// Commands the connecting peer may run, see [`ManagerCmd::required_access`]. Unset if the service doesn't check.
+    #[serde(default)]
+    pub access: Option<AccessLevel>,
+    /// Whether the service asks polkit to authorize operator commands of peers with less access, which may prompt the user to authenticate.
+    #[serde(default)]
+    pub polkit: bool,
 }
 
 const MAX_HEADER_LEN: u32 = 4096;
 
+/// Upper bound for responses and events, which are larger than commands, e.g. log queries or debug info.
+pub const MAX_RESPONSE_LEN: u32 = 64 * 1024 * 1024;
+
+/// Sent by clients as their first message to opt into protocol version 1, i.e. framed responses and any number of commands per connection. Older clients send a command right away and get a single unframed response, after which the service closes the connection.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct LinuxIpcClientHello {
+    pub protocol: u32,
+}
+
+impl LinuxIpcClientHello {
+    /// Returns `None` for anything but a hello, in particular commands of older clients.
+    pub fn parse(message: &[u8]) -> Option<Self> {
+        serde_json::from_slice(message).ok()
+    }
+}
+
 impl LinuxIpcHeader {
+    pub fn current(access: AccessLevel) -> Self {
+        Self {
+            version: release_version().to_owned(),
+            protocol: PROTOCOL_VERSION,
+            min_protocol: MIN_PROTOCOL_VERSION,
+            commands: ManagerCmd::VARIANTS.iter().map(ToString::to_string).collect(),
+            access: Some(access),
+            polkit: false,
+        }
+    }
+
+    /// Whether both sides share a protocol version, regardless of the release versions.
+    pub fn compatible(&self) -> bool {
+        self.min_protocol <= PROTOCOL_VERSION && MIN_PROTOCOL_VERSION <= self.protocol
+    }
+
+    /// Whether the service predates protocol negotiation. It handles a single command per connection, responds unframed and closes the connection.
+    pub fn is_legacy(&self) -> bool {
+        self.protocol == 0
+    }
+
+    pub fn supports(&self, cmd: &ManagerCmd) -> bool {
+        if self.is_legacy() {
+            return LEGACY_COMMANDS.contains(&cmd.name());
+        }
+        self.commands.iter().any(|name| name == cmd.name())
+    }
+
+    pub fn allows(&self, access: AccessLevel) -> bool {
+        self.grants(access) || (self.polkit && access == AccessLevel::Operator)
+    }
+
+    /// Whether the access level is granted without asking polkit, so commands requiring it never prompt the user to authenticate.
+    pub fn grants(&self, access: AccessLevel) -> bool {
+        self.access.is_none_or(|granted| granted >= access)
+    }
+
     pub async fn write<W: AsyncWriteExt + Unpin>(&self, writer: &mut W) -> std::io::Result<()> {
         let json = serde_json::to_vec(self)?;
-        let len: u32 = json.len().try_into().map_err(std::io::Error::other)?;
-        let mut message = Vec::with_capacity(4 + json.len());
-        message.extend_from_slice(&len.to_be_bytes());
-        message.extend_from_slice(&json);
-        writer.write_all(&message).await
+        write_frame(writer, &json).await
     }
 
     async fn read<R: AsyncReadExt + Unpin>(reader: &mut R) -> Result<Self, ()> {
-        let mut len = [0u8; 4];
-        reader.read_exact(&mut len).await.map_err(|error| {
-            tracing::error!(message_id = "pY7wKn2V", ?error, "failed to read IPC header length");
-        })?;
-        let len = u32::from_be_bytes(len);
-        if len > MAX_HEADER_LEN {
-            tracing::error!(message_id = "eJ5wQc8N", len, "IPC header length exceeds limit");
-            return Err(());
-        }
-        let mut json = vec![0; u32_into_usize(len)];
-        reader.read_exact(&mut json).await.map_err(|error| {
-            tracing::error!(message_id = "aY3fVm7T", ?error, "failed to read IPC header");
-        })?;
+        let json = match read_frame(reader, MAX_HEADER_LEN).await {
+            Ok(Some(json)) => json,
+            Ok(None) => {
+                tracing::error!(message_id = "pY7wKn2V", "socket stream closed before IPC header");
+                return Err(());
+            }
+            Err(error) => {
+                tracing::error!(message_id = "aY3fVm7T", ?error, "failed to read IPC header");
+                return Err(());
+            }
+        };
         serde_json::from_slice(&json).map_err(|error| {
             tracing::error!(message_id = "rD6kXb2S", ?error, "failed to parse IPC header");
         })
@@ -58,11 +161,49 @@ impl LinuxIpcHeader {
 pub enum LinuxIpcError {
     InsufficientPermissions,
     NoListener,
-    VersionMismatch { service_version: String, app_version: String },
+    /// The service doesn't speak any protocol version this binary supports.
+    VersionMismatch {
+        service_version: String,
+        app_version: String,
+    },
+    /// The service is compatible, but doesn't know this command, usually because it's older than this binary.
+    UnsupportedCommand {
+        command: &'static str,
+    },
     Other,
 }
 
-pub async fn run_command<O: DeserializeOwned>(cmd: ManagerCmd) -> Result<Result<O, ManagerCmdErrorCode>, LinuxIpcError> {
+/// Writes a command or response. Both are framed by their length as big-endian `u32`, so a connection can carry any number of commands once the client sent a [`LinuxIpcClientHello`]. Responses are sent in command order.
+pub async fn write_frame<W: AsyncWriteExt + Unpin>(writer: &mut W, message: &[u8]) -> std::io::Result<()> {
+    let len: u32 = message.len().try_into().map_err(std::io::Error::other)?;
+    let mut frame = Vec::with_capacity(4 + message.len());
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(message);
+    writer.write_all(&frame).await
+}
+
+/// Reads a message written by [`write_frame`]. Returns `None` if the peer closed the stream between messages.
+pub async fn read_frame<R: AsyncReadExt + Unpin>(reader: &mut R, max_len: u32) -> std::io::Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 4];
+    match reader.read_exact(&mut len).await {
+        Ok(_) => {}
+        Err(error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(error) => return Err(error),
+    }
+    let len = u32::from_be_bytes(len);
+    if len > max_len {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("message length {len} exceeds limit {max_len}"),
+        ));
+    }
+    let mut message = vec![0; u32_into_usize(len)];
+    reader.read_exact(&mut message).await?;
+    Ok(Some(message))
+}
+
+/// Connects to the service and checks that both sides speak a common protocol version. The returned header tells which commands the service supports.
+pub async fn connect() -> Result<(UnixStream, LinuxIpcHeader), LinuxIpcError> {
     let mut stream = UnixStream::connect(SOCKET_PATH).await.map_err(|error| {
         tracing::warn!(message_id = "RJEP2IV5", ?error, "failed to connect to socket");
         match error.kind() {
@@ -73,39 +214,131 @@ pub async fn run_command<O: DeserializeOwned>(cmd: ManagerCmd) -> Result<Result<
         }
     })?;
 
-    let json_cmd = serde_json::to_vec(&cmd).map_err(|error| {
-        tracing::error!(message_id = "AdBGoG5S", ?error, "failed to serialize command");
-        LinuxIpcError::Other
-    })?;
-    let len: u32 = json_cmd.len().try_into().map_err(|_| {
-        tracing::error!(message_id = "Vq8mXpL2", "command too large to send");
-        LinuxIpcError::Other
-    })?;
-    stream.write_all(&len.to_be_bytes()).await.map_err(|error| {
-        tracing::error!(message_id = "GYCVPD3t", ?error, "failed to write length of json command");
-        LinuxIpcError::Other
-    })?;
-    stream.write_all(json_cmd.as_slice()).await.map_err(|error| {
-        tracing::error!(message_id = "FGduR73M", ?error, "failed to send json command");
-        LinuxIpcError::Other
-    })?;
-
     let header = LinuxIpcHeader::read(&mut stream).await.map_err(|()| LinuxIpcError::Other)?;
-    if header.version != release_version() {
+    if !header.compatible() {
         tracing::error!(
             message_id = "sQ8bHn4Z",
             service_version = header.version,
+            service_protocol = header.protocol,
+            service_min_protocol = header.min_protocol,
             client_version = release_version(),
-            "IPC header version does not match this binary"
+            client_protocol = PROTOCOL_VERSION,
+            client_min_protocol = MIN_PROTOCOL_VERSION,
+            "IPC protocol of service is incompatible with this binary"
         );
         return Err(LinuxIpcError::VersionMismatch { service_version: header.version, app_version: release_version().to_owned() });
     }
+    if header.version != release_version() {
+        tracing::info!(
+            message_id = "Hy3rMc6V",
+            service_version = header.version,
+            client_version = release_version(),
+            "service version differs from this binary, but speaks a compatible protocol"
+        );
+    }
+    if header.is_legacy() {
+        tracing::info!(
+            message_id = "Lt8cWq5N",
+            service_version = header.version,
+            "service predates IPC protocol negotiation"
+        );
+        return Ok((stream, header));
+    }
+    let hello = serde_json::to_vec(&LinuxIpcClientHello { protocol: PROTOCOL_VERSION }).map_err(|error| {
+        tracing::error!(message_id = "Kd7wPz3M", ?error, "failed to serialize IPC client hello");
+        LinuxIpcError::Other
+    })?;
+    write_frame(&mut stream, &hello).await.map_err(|error| {
+        tracing::error!(message_id = "Jq4sVn8B", ?error, "failed to send IPC client hello");
+        LinuxIpcError::Other
+    })?;
+    Ok((stream, header))
+}
 
+/// Fails without contacting the service further if it doesn't support or wouldn't allow the command.
+fn check_supported(header: &LinuxIpcHeader, cmd: &ManagerCmd) -> Result<(), LinuxIpcError> {
+    let command = cmd.name();
+    if !header.supports(cmd) {
+        tracing::warn!(
+            message_id = "Vb8kTq2L",
+            command,
+            service_version = header.version,
+            "service does not support command"
+        );
+        return Err(LinuxIpcError::UnsupportedCommand { command });
+    }
+    let required_access = cmd.required_access();
+    if !header.allows(required_access) {
+        tracing::warn!(
+            message_id = "Sd3pXk9F",
+            command,
+            ?required_access,
+            granted_access =? header.access,
+            "not authorized to run command"
+        );
+        return Err(LinuxIpcError::InsufficientPermissions);
+    }
+    Ok(())
+}
+
+/// Checks whether the current process may run commands requiring the given access level, without running any.
+pub async fn check_access(access: AccessLevel) -> Result<(), LinuxIpcError> {
+    let (_stream, header) = connect().await?;
+    if !header.allows(access) {
+        tracing::debug!(message_id = "Wm6nTb4R", ?access, granted_access =? header.access, "insufficient access level");
+        return Err(LinuxIpcError::InsufficientPermissions);
+    }
+    Ok(())
+}
+
+/// Sends a JSON encoded command on a connection returned by [`connect`] and returns the JSON encoded `Result<ManagerCmdOk, ManagerCmdErrorCode>`. Legacy services close the connection after responding, see [`LinuxIpcHeader::is_legacy`].
+pub async fn run_raw_command(stream: &mut UnixStream, header: &LinuxIpcHeader, json_cmd: &[u8]) -> Result<Vec<u8>, LinuxIpcError> {
+    write_frame(stream, json_cmd).await.map_err(|error| {
+        tracing::error!(message_id = "FGduR73M", ?error, "failed to send json command");
+        LinuxIpcError::Other
+    })?;
+    if header.is_legacy() {
+        return read_unframed_response(stream).await;
+    }
+    read_response(stream).await
+}
+
+async fn read_unframed_response<R: AsyncReadExt + Unpin>(reader: &mut R) -> Result<Vec<u8>, LinuxIpcError> {
     let mut response = Vec::new();
-    stream.read_to_end(&mut response).await.map_err(|error| {
-        tracing::error!(message_id = "pdkSRS95", ?error, "failed to receive json command response");
+    reader
+        .take(u64::from(MAX_RESPONSE_LEN))
+        .read_to_end(&mut response)
+        .await
+        .map_err(|error| {
+            tracing::error!(message_id = "Qm3bZt7H", ?error, "failed to receive unframed json command response");
+            LinuxIpcError::Other
+        })?;
+    Ok(response)
+}
+
+async fn read_response<R: AsyncReadExt + Unpin>(reader: &mut R) -> Result<Vec<u8>, LinuxIpcError> {
+    match read_frame(reader, MAX_RESPONSE_LEN).await {
+        Ok(Some(response)) => Ok(response),
+        Ok(None) => {
+            tracing::error!(message_id = "Bn5tWq7E", "service closed socket stream before responding");
+            Err(LinuxIpcError::Other)
+        }
+        Err(error) => {
+            tracing::error!(message_id = "pdkSRS95", ?error, "failed to receive json command response");
+            Err(LinuxIpcError::Other)
+        }
+    }
+}
+
+pub async fn run_command<O: DeserializeOwned>(cmd: ManagerCmd) -> Result<Result<O, ManagerCmdErrorCode>, LinuxIpcError> {
+    let (mut stream, header) = connect().await?;
+    check_supported(&header, &cmd)?;
+
+    let json_cmd = serde_json::to_vec(&cmd).map_err(|error| {
+        tracing::error!(message_id = "AdBGoG5S", ?error, "failed to serialize command");
         LinuxIpcError::Other
     })?;
+    let response = run_raw_command(&mut stream, &header, &json_cmd).await?;
     stream.shutdown().await.map_err(|error| {
         tracing::error!(message_id = "SqVcXJe4", ?error, "failed to close write end of socket stream");
         LinuxIpcError::Other
@@ -122,21 +355,66 @@ pub async fn run_command<O: DeserializeOwned>(cmd: ManagerCmd) -> Result<Result<
     })
 }
 
+/// Events streamed by the service over a dedicated connection, see [`subscribe_events`].
+pub struct EventStream {
+    stream: UnixStream,
+    header: LinuxIpcHeader,
+}
+
+/// Services which don't support event subscriptions yet fail with [`LinuxIpcError::UnsupportedCommand`], callers can fall back to polling.
+pub async fn subscribe_events(subscription: EventSubscription) -> Result<EventStream, LinuxIpcError> {
+    let (mut stream, header) = connect().await?;
+    let cmd = ManagerCmd::SubscribeEvents { subscription };
+    check_supported(&header, &cmd)?;
+    let json_cmd = serde_json::to_vec(&cmd).map_err(|error| {
+        tracing::error!(message_id = "Wf2pYs8B", ?error, "failed to serialize event subscription");
+        LinuxIpcError::Other
+    })?;
+    write_frame(&mut stream, &json_cmd).await.map_err(|error| {
+        tracing::error!(message_id = "Ck6mRd4T", ?error, "failed to send event subscription");
+        LinuxIpcError::Other
+    })?;
+    Ok(EventStream { stream, header })
+}
+
+impl EventStream {
+    /// Header of the service connection, e.g. to tell whether the peer may control the service.
+    pub fn header(&self) -> &LinuxIpcHeader {
+        &self.header
+    }
+
+    /// Returns `None` if the service closed the stream, e.g. because it's shutting down.
+    pub async fn recv(&mut self) -> Result<Option<ManagerEvent>, LinuxIpcError> {
+        let json = read_frame(&mut self.stream, MAX_RESPONSE_LEN).await.map_err(|error| {
+            tracing::error!(message_id = "Tj9qBv3N", ?error, "failed to receive event");
+            LinuxIpcError::Other
+        })?;
+        let Some(json) = json else {
+            return Ok(None);
+        };
+        serde_json::from_slice(&json).map(Some).map_err(|error| {
+            tracing::error!(
+                message_id = "Gn4xKs7P",
+                ?error,
+                event = &*String::from_utf8_lossy(&json),
+                "failed to parse event"
+            );
+            LinuxIpcError::Other
+        })
+    }
+}
+
 // Tests if IPC fails due to insufficient permissions and if this can be resolved by refreshing a group membership. If that's the case, the process is replaced by a new one, which first updates the group memberships and then reruns the current command.
 pub async fn try_group_refresh_fix() {
-    match run_command::<()>(ManagerCmd::Ping {}).await {
+    match check_access(AccessLevel::Operator).await {
         Err(LinuxIpcError::InsufficientPermissions) => tracing::debug!(
             message_id = "t4O1pv8K",
             "insufficient permissions for IPC commands, check if IPC works in a new shell"
         ),
-        Ok(Ok(())) => {
+        Ok(()) => {
             tracing::debug!(message_id = "ZA5DS6pc", "IPC test succeeded, group refresh not necessary");
             return;
         }
-        Ok(Err(error)) => {
-            tracing::debug!(message_id = "hV1KZ8pw", ?error, "IPC ping returned error, not attempting group refresh");
-            return;
-        }
         Err(error) => {
             tracing::debug!(
                 message_id = "EP7be96J",
@@ -257,3 +535,81 @@ fn build_sg_exec_cmd<'a>(
     cmd.arg(sg_command_arg);
     Ok(cmd)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_compatibility() {
+        let current = LinuxIpcHeader::current(AccessLevel::Operator);
+        assert!(current.compatible());
+        assert!(current.supports(&ManagerCmd::Ping {}));
+        assert!(current.supports(&ManagerCmd::SubscribeEvents { subscription: EventSubscription::default() }));
+
+        // Services predating protocol negotiation only send their version.
+        let legacy: LinuxIpcHeader = serde_json::from_str(r#"{"version":"0.1.0"}"#).unwrap();
+        assert!(legacy.compatible());
+        assert!(legacy.is_legacy());
+        assert!(legacy.supports(&ManagerCmd::GetStatus { known_version: None }));
+        assert!(!legacy.supports(&ManagerCmd::SubscribeEvents { subscription: EventSubscription::default() }));
+        assert!(LEGACY_COMMANDS.iter().all(|name| ManagerCmd::VARIANTS.contains(name)));
+
+        let newer = LinuxIpcHeader { protocol: PROTOCOL_VERSION + 1, ..LinuxIpcHeader::current(AccessLevel::Operator) };
+        assert!(newer.compatible());
+        let newer_only = LinuxIpcHeader { min_protocol: PROTOCOL_VERSION + 1, ..newer };
+        assert!(!newer_only.compatible());
+
+        let older = LinuxIpcHeader { commands: vec!["ping".to_string()], ..LinuxIpcHeader::current(AccessLevel::Operator) };
+        assert!(older.supports(&ManagerCmd::Ping {}));
+        assert!(!older.supports(&ManagerCmd::GetTrafficStats {}));
+    }
+
+    #[test]
+    fn test_header_len() {
+        let header = LinuxIpcHeader { polkit: true, ..LinuxIpcHeader::current(AccessLevel::Operator) };
+        assert!(serde_json::to_vec(&header).unwrap().len() <= u32_into_usize(MAX_HEADER_LEN));
+
+        // Leave room for more commands.
+        let longest = ManagerCmd::VARIANTS.iter().map(|name| name.len()).max().unwrap();
+        let grown = LinuxIpcHeader { commands: vec!["x".repeat(longest); 60], ..header };
+        assert!(serde_json::to_vec(&grown).unwrap().len() <= u32_into_usize(MAX_HEADER_LEN));
+    }
+
+    #[test]
+    fn test_client_hello() {
+        let hello = serde_json::to_vec(&LinuxIpcClientHello { protocol: PROTOCOL_VERSION }).unwrap();
+        assert_eq!(LinuxIpcClientHello::parse(&hello).unwrap().protocol, PROTOCOL_VERSION);
+        // Commands of clients predating the hello are never mistaken for one.
+        for cmd in [ManagerCmd::Ping {}, ManagerCmd::GetStatus { known_version: None }] {
+            assert!(LinuxIpcClientHello::parse(&serde_json::to_vec(&cmd).unwrap()).is_none());
+        }
+    }
+
+    #[test]
+    fn test_header_access() {
+        let read_only = LinuxIpcHeader::current(AccessLevel::ReadOnly);
+        assert!(check_supported(&read_only, &ManagerCmd::GetStatus { known_version: None }).is_ok());
+        assert!(matches!(
+            check_supported(&read_only, &ManagerCmd::Logout {}),
+            Err(LinuxIpcError::InsufficientPermissions)
+        ));
+
+        let operator = LinuxIpcHeader::current(AccessLevel::Operator);
+        assert!(check_supported(&operator, &ManagerCmd::Logout {}).is_ok());
+        assert!(matches!(
+            check_supported(&operator, &ManagerCmd::TerminateProcess {}),
+            Err(LinuxIpcError::InsufficientPermissions)
+        ));
+        assert!(check_supported(&LinuxIpcHeader::current(AccessLevel::Admin), &ManagerCmd::TerminateProcess {}).is_ok());
+
+        let polkit = LinuxIpcHeader { polkit: true, ..LinuxIpcHeader::current(AccessLevel::ReadOnly) };
+        assert!(check_supported(&polkit, &ManagerCmd::Logout {}).is_ok());
+        assert!(check_supported(&polkit, &ManagerCmd::TerminateProcess {}).is_err());
+        assert!(!polkit.grants(AccessLevel::Operator));
+        assert!(polkit.grants(AccessLevel::ReadOnly));
+
+        let unchecked = LinuxIpcHeader { access: None, ..LinuxIpcHeader::current(AccessLevel::ReadOnly) };
+        assert!(unchecked.allows(AccessLevel::Admin));
+    }
+}
@@ -5,6 +5,7 @@ pub mod ipc;
 pub mod status;
 pub mod status_watch;
 pub mod systemd;
+pub mod traffic_stats;
 pub mod tray;
 
 pub fn argv0() -> Option<std::path::PathBuf> {
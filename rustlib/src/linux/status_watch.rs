@@ -6,12 +6,13 @@ use tokio::sync::watch;
 use tokio_util::task::AbortOnDropHandle;
 use uuid::Uuid;
 
-use super::ipc::{LinuxIpcError, run_command};
+use super::ipc::{EventStream, LinuxIpcError, run_command, subscribe_events};
 use super::status::{DebugBundleStatus, LinuxServiceDegradation, OsStatus, ServiceStatus};
 use super::systemd::SystemdUnitStatus;
 use super::{argv0, current_user_name};
 use crate::manager::Status;
-use crate::manager_cmd::ManagerCmd;
+use crate::manager_cmd::{AccessLevel, ManagerCmd};
+use crate::manager_event::{EventSubscription, ManagerEvent};
 use crate::version::release_version;
 
 pub struct GuiStatusWatch {
@@ -53,48 +54,104 @@ async fn forward_debug_bundle_status(tx: watch::Sender<OsStatus>, mut rx: watch:
 }
 
 async fn run_status_poller(tx: watch::Sender<OsStatus>) {
-    let mut known_version: Option<Uuid> = None;
     loop {
-        let degradation = match run_command::<Status>(ManagerCmd::GetStatus { known_version }).await {
-            Ok(Ok(status)) => {
-                known_version = Some(status.version);
-                tx.send_if_modified(|os_status| {
-                    let version = os_status.version;
-                    os_status.set_service_status(ServiceStatus::Healthy(status));
-                    os_status.version != version
-                });
-                continue;
-            }
-            Err(LinuxIpcError::NoListener) => classify_unreachable(ConnectFailure::NoListener).await,
-            Err(LinuxIpcError::InsufficientPermissions) => classify_unreachable(ConnectFailure::InsufficientPermissions).await,
-            Err(LinuxIpcError::VersionMismatch { service_version, app_version }) => {
+        let error = match subscribe_events(EventSubscription::default()).await {
+            Ok(mut events) => match forward_status_events(&tx, &mut events, access_degradation(&events).await).await {
+                Ok(()) => {
+                    tracing::info!(message_id = "Dq7tLx2W", "service closed event stream");
+                    LinuxIpcError::NoListener
+                }
+                Err(error) => error,
+            },
+            Err(LinuxIpcError::UnsupportedCommand { command: _ }) => forward_polled_status(&tx).await,
+            Err(error) => error,
+        };
+        let degradation = match error {
+            LinuxIpcError::NoListener => classify_unreachable(ConnectFailure::NoListener).await,
+            LinuxIpcError::InsufficientPermissions => classify_unreachable(ConnectFailure::InsufficientPermissions).await,
+            LinuxIpcError::VersionMismatch { service_version, app_version } => {
                 let installed_app_version_differs = installed_app_version_differs().await.ok();
                 LinuxServiceDegradation::VersionMismatch { service_version, app_version, installed_app_version_differs }
             }
-            Ok(Err(error)) => {
-                tracing::error!(message_id = "Jc2vZq8k", ?error, "service failed to get status");
+            LinuxIpcError::UnsupportedCommand { command } => {
+                tracing::error!(message_id = "Fs4kWn9B", command, "service does not support getting status");
                 LinuxServiceDegradation::Unknown
             }
-            Err(LinuxIpcError::Other) => {
+            LinuxIpcError::Other => {
                 tracing::error!(message_id = "Xw5nRt3p", "cannot reach service to get status");
                 LinuxServiceDegradation::Unknown
             }
         };
-        known_version = None;
-        tx.send_if_modified(|os_status| {
-            let version = os_status.version;
-            let last_status = match os_status.service_status.clone() {
-                ServiceStatus::Initializing => None,
-                ServiceStatus::Healthy(status) => Some(status),
-                ServiceStatus::Degraded { last_status, linux_degradation: _ } => last_status,
-            };
-            os_status.set_service_status(ServiceStatus::Degraded { last_status, linux_degradation: degradation });
-            os_status.version != version
-        });
+        let last_status = match tx.borrow().service_status.clone() {
+            ServiceStatus::Initializing => None,
+            ServiceStatus::Healthy(status) => Some(status),
+            ServiceStatus::Degraded { last_status, linux_degradation: _ } => last_status,
+        };
+        set_degraded(&tx, last_status, degradation);
         tokio::time::sleep(Duration::from_secs(1)).await;
     }
 }
 
+/// Any local user may subscribe to status events, but the service is only usable for users who may control it. Otherwise the status is shown as degraded, which offers to fix the group membership.
+async fn access_degradation(events: &EventStream) -> Option<LinuxServiceDegradation> {
+    if events.header().allows(AccessLevel::Operator) {
+        return None;
+    }
+    tracing::info!(message_id = "Yc4nWs8F", granted_access =? events.header().access, "insufficient access to control service");
+    Some(LinuxServiceDegradation::SocketPermissionDenied { user: current_user_name().await })
+}
+
+/// Returns once the service closes the stream. With `degradation`, statuses are forwarded as the last status of the degraded service.
+async fn forward_status_events(
+    tx: &watch::Sender<OsStatus>,
+    events: &mut EventStream,
+    degradation: Option<LinuxServiceDegradation>,
+) -> Result<(), LinuxIpcError> {
+    while let Some(event) = events.recv().await? {
+        if let ManagerEvent::Status(status) = event {
+            match &degradation {
+                None => set_healthy(tx, status),
+                Some(degradation) => set_degraded(tx, Some(status), degradation.clone()),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Follows status changes of services which don't support event subscriptions. Returns the error which ended polling.
+async fn forward_polled_status(tx: &watch::Sender<OsStatus>) -> LinuxIpcError {
+    let mut known_version: Option<Uuid> = None;
+    loop {
+        match run_command::<Status>(ManagerCmd::GetStatus { known_version }).await {
+            Ok(Ok(status)) => {
+                known_version = Some(status.version);
+                set_healthy(tx, status);
+            }
+            Ok(Err(error)) => {
+                tracing::error!(message_id = "Jc2vZq8k", ?error, "service failed to get status");
+                return LinuxIpcError::Other;
+            }
+            Err(error) => return error,
+        }
+    }
+}
+
+fn set_healthy(tx: &watch::Sender<OsStatus>, status: Status) {
+    tx.send_if_modified(|os_status| {
+        let version = os_status.version;
+        os_status.set_service_status(ServiceStatus::Healthy(status));
+        os_status.version != version
+    });
+}
+
+fn set_degraded(tx: &watch::Sender<OsStatus>, last_status: Option<Status>, linux_degradation: LinuxServiceDegradation) {
+    tx.send_if_modified(|os_status| {
+        let version = os_status.version;
+        os_status.set_service_status(ServiceStatus::Degraded { last_status, linux_degradation });
+        os_status.version != version
+    });
+}
+
 async fn installed_app_version_differs() -> Result<bool, ()> {
     let Some(invocation_path) = argv0() else {
         tracing::error!(message_id = "wN3kFb7T", "cannot probe installed app version without argv[0]");
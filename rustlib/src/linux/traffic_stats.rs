@@ -0,0 +1,98 @@
+//! Throughput rates computed from successive `ManagerTrafficStats` samples, shown by `obscura stats` and the tray.
+
+use std::time::Duration;
+
+use crate::manager::ManagerTrafficStats;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrafficRates {
+    pub tx_bytes_per_sec: u64,
+    pub rx_bytes_per_sec: u64,
+}
+
+impl TrafficRates {
+    /// Returns `None` if the samples belong to different connections or no connected time passed between them.
+    pub fn between(previous: &ManagerTrafficStats, current: &ManagerTrafficStats) -> Option<Self> {
+        if previous.conn_id != current.conn_id {
+            return None;
+        }
+        let elapsed_ms = current
+            .connected_ms
+            .checked_sub(previous.connected_ms)
+            .filter(|elapsed_ms| *elapsed_ms > 0)?;
+        let rate = |previous: u64, current: u64| {
+            let bytes = u128::from(current.saturating_sub(previous));
+            u64::try_from(bytes * 1000 / u128::from(elapsed_ms)).unwrap_or(u64::MAX)
+        };
+        Some(Self {
+            tx_bytes_per_sec: rate(previous.tx_bytes, current.tx_bytes),
+            rx_bytes_per_sec: rate(previous.rx_bytes, current.rx_bytes),
+        })
+    }
+}
+
+/// Formats a byte count with one decimal and SI units, e.g. "1.5 MB".
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["kB", "MB", "GB", "TB", "PB"];
+    if bytes < 1000 {
+        return format!("{bytes} B");
+    }
+    let mut unit = 0;
+    let mut divisor: u64 = 1000;
+    while unit + 1 < UNITS.len() && bytes / divisor >= 1000 {
+        unit += 1;
+        divisor *= 1000;
+    }
+    let tenths = u128::from(bytes) * 10 / u128::from(divisor);
+    format!("{}.{} {}", tenths / 10, tenths % 10, UNITS[unit])
+}
+
+pub fn format_rate(bytes_per_sec: u64) -> String {
+    format!("{}/s", format_bytes(bytes_per_sec))
+}
+
+/// Formats a duration as e.g. "2h 05m 09s", omitting leading zero units.
+pub fn format_uptime(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (hours, minutes, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if hours > 0 {
+        format!("{hours}h {minutes:02}m {secs:02}s")
+    } else if minutes > 0 {
+        format!("{minutes}m {secs:02}s")
+    } else {
+        format!("{secs}s")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn sample(conn_id: Uuid, connected_ms: u64, tx_bytes: u64, rx_bytes: u64) -> ManagerTrafficStats {
+        ManagerTrafficStats { connected_ms, conn_id, tx_bytes, rx_bytes, latest_latency_ms: 20 }
+    }
+
+    #[test]
+    fn test_rates_between() {
+        let conn_id = Uuid::new_v4();
+        let previous = sample(conn_id, 1000, 1000, 5000);
+        let rates = TrafficRates::between(&previous, &sample(conn_id, 3000, 3000, 1_005_000));
+        assert_eq!(rates, Some(TrafficRates { tx_bytes_per_sec: 1000, rx_bytes_per_sec: 500_000 }));
+        assert_eq!(TrafficRates::between(&previous, &previous), None);
+        assert_eq!(TrafficRates::between(&previous, &sample(Uuid::new_v4(), 3000, 3000, 3000)), None);
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format_bytes(999), "999 B");
+        assert_eq!(format_bytes(1500), "1.5 kB");
+        assert_eq!(format_bytes(999_999), "999.9 kB");
+        assert_eq!(format_bytes(12_340_000_000), "12.3 GB");
+        assert_eq!(format_rate(2_000_000), "2.0 MB/s");
+        assert_eq!(format_uptime(Duration::from_secs(9)), "9s");
+        assert_eq!(format_uptime(Duration::from_secs(125)), "2m 05s");
+        assert_eq!(format_uptime(Duration::from_secs(7509)), "2h 05m 09s");
+    }
+}
@@ -21,8 +21,9 @@ use super::exit_list_watch::GuiExitListWatch;
 use super::ipc::run_command;
 use super::status::{LinuxServiceDegradation, NEVPNStatus, OsStatus, ServiceStatus};
 use super::status_watch::GuiStatusWatch;
+use super::traffic_stats::{TrafficRates, format_rate};
 use crate::exit_selection::ExitSelector;
-use crate::manager::{TunnelArgs, VpnStatus};
+use crate::manager::{ManagerTrafficStats, TunnelArgs, VpnStatus};
 use crate::manager_cmd::ManagerCmd;
 
 pub enum ShowTarget {
@@ -35,6 +36,8 @@ pub enum TrayRequest {
     Quit,
 }
 
+const TRAFFIC_STATS_INTERVAL: Duration = Duration::from_secs(2);
+
 static ICONS: LazyLock<TrayIcons> = LazyLock::new(|| TrayIcons::render().expect("embedded tray icon svgs must render"));
 
 struct TrayIcons {
@@ -97,6 +100,13 @@ fn status_line(os_status: &OsStatus) -> String {
     }
 }
 
+fn is_connected(os_status: &OsStatus) -> bool {
+    match &os_status.service_status {
+        ServiceStatus::Healthy(status) => matches!(status.vpn_status, VpnStatus::Connected { .. }),
+        ServiceStatus::Initializing | ServiceStatus::Degraded { last_status: _, linux_degradation: _ } => false,
+    }
+}
+
 fn selector_is_city(selector: &ExitSelector, country_code: &str, city_code: &str) -> bool {
     match selector {
         ExitSelector::City { city_code: selected } => selected.country_code.0 == country_code && selected.city_code == city_code,
@@ -109,6 +119,7 @@ struct TrayState {
     connecting_frame: usize,
     requests: Sender<TrayRequest>,
     exit_list: Option<Arc<ExitList>>,
+    traffic: Option<(TrafficRates, u16)>,
     rt: Handle,
 }
 
@@ -299,7 +310,15 @@ impl Tray for TrayState {
             icon_name: String::new(),
             icon_pixmap: Vec::new(),
             title: "Obscura VPN".to_owned(),
-            description: status_line(&self.os_status),
+            description: match self.traffic {
+                Some((rates, latency_ms)) if is_connected(&self.os_status) => format!(
+                    "{}\n↓ {}  ↑ {}  {latency_ms} ms",
+                    status_line(&self.os_status),
+                    format_rate(rates.rx_bytes_per_sec),
+                    format_rate(rates.tx_bytes_per_sec),
+                ),
+                _ => status_line(&self.os_status),
+            },
         }
     }
 
@@ -433,6 +452,7 @@ async fn run_tray(status: Arc<GuiStatusWatch>, exit_list: Arc<GuiExitListWatch>,
             connecting_frame: 0,
             requests: requests.clone(),
             exit_list: None,
+            traffic: None,
             rt: Handle::current(),
         };
         let tray = tray
@@ -447,6 +467,41 @@ async fn run_tray(status: Arc<GuiStatusWatch>, exit_list: Arc<GuiExitListWatch>,
             }
         }
     };
+    let traffic_handle = handle.clone();
+    tokio::spawn(async move {
+        let mut previous: Option<ManagerTrafficStats> = None;
+        loop {
+            sleep(TRAFFIC_STATS_INTERVAL).await;
+            let Some(connected) = traffic_handle.update(|tray| is_connected(&tray.os_status)).await else {
+                tracing::error!(message_id = "Jc8vRt3e", "tray service stopped");
+                return;
+            };
+            let stats = if connected {
+                match run_command::<ManagerTrafficStats>(ManagerCmd::GetTrafficStats {}).await {
+                    Ok(Ok(stats)) => Some(stats),
+                    Ok(Err(error)) => {
+                        tracing::warn!(message_id = "Gd3mWq7p", ?error, "tray failed to get traffic stats");
+                        None
+                    }
+                    Err(error) => {
+                        tracing::warn!(message_id = "Lh5sNc2x", ?error, "tray failed to get traffic stats");
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+            let traffic = match (&previous, &stats) {
+                (Some(previous), Some(stats)) => TrafficRates::between(previous, stats).map(|rates| (rates, stats.latest_latency_ms)),
+                _ => None,
+            };
+            previous = stats;
+            let Some(()) = traffic_handle.update(|tray| tray.traffic = traffic).await else {
+                tracing::error!(message_id = "Qp2kZe6n", "tray service stopped");
+                return;
+            };
+        }
+    });
     let exit_list_handle = handle.clone();
     tokio::spawn(async move {
         let mut known: Option<Arc<ExitList>> = None;
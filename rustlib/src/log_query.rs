@@ -0,0 +1,341 @@
+//! Filtered reads of the persisted ndjson log, for clients which can't read the service log dir themselves.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom};
+use std::time::SystemTime;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use strum::EnumString;
+
+use crate::logging::LOG_FILE_NAME;
+
+/// Ordered by severity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, EnumString)]
+#[serde(rename_all = "camelCase")]
+#[strum(ascii_case_insensitive)]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+/// Position in the current log file. The timestamp of the first entry identifies the file, which is kept when it's rotated.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogCursor {
+    first_timestamp: Option<String>,
+    offset: u64,
+}
+
+#[serde_with::serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogQuery {
+    #[serde_as(as = "Option<serde_with::TimestampSeconds>")]
+    pub since: Option<SystemTime>,
+    pub min_level: Option<LogLevel>,
+    pub message_id: Option<String>,
+    /// Only return entries written after a previous query, for following the log.
+    pub cursor: Option<LogCursor>,
+    /// Maximum number of entries, the newest are kept.
+    pub limit: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogEntries {
+    pub entries: Vec<Value>,
+    pub cursor: LogCursor,
+    /// Older matching entries were dropped because of the limit.
+    pub truncated: bool,
+}
+
+impl LogQuery {
+    fn matches(&self, entry: &Value) -> bool {
+        if let Some(min_level) = self.min_level
+            && entry_level(entry).is_some_and(|level| level < min_level)
+        {
+            return false;
+        }
+        if let Some(message_id) = &self.message_id
+            && entry["fields"]["message_id"].as_str() != Some(message_id.as_str())
+        {
+            return false;
+        }
+        if let Some(since) = self.since
+            && entry_time(entry).is_some_and(|time| time < since)
+        {
+            return false;
+        }
+        true
+    }
+}
+
+fn entry_level(entry: &Value) -> Option<LogLevel> {
+    entry["level"].as_str()?.parse().ok()
+}
+
+fn entry_time(entry: &Value) -> Option<SystemTime> {
+    let timestamp = chrono::DateTime::parse_from_rfc3339(entry["timestamp"].as_str()?).ok()?;
+    Some(timestamp.into())
+}
+
+/// Reads matching entries of the current log file. Rotated files are only read if the current file doesn't go back far enough, or if the log rotated since the cursor was returned.
+pub async fn query_log(log_dir: &Utf8Path, query: LogQuery) -> Result<LogEntries, ()> {
+    let log_dir = log_dir.to_owned();
+    tokio::task::spawn_blocking(move || read_log(&log_dir, &query))
+        .await
+        .map_err(|error| tracing::error!(message_id = "Ub7kRm4D", ?error, "log query task failed: {}", error))?
+}
+
+fn read_log(log_dir: &Utf8Path, query: &LogQuery) -> Result<LogEntries, ()> {
+    let path = log_dir.join(LOG_FILE_NAME);
+    let file = File::open(&path).map_err(|error| tracing::error!(message_id = "Hv3nTq8K", ?error, %path, "failed to open log file: {}", error))?;
+    let mut current = read_entries(BufReader::new(file), query)
+        .map_err(|error| tracing::error!(message_id = "Xc5pLw2B", ?error, %path, "failed to read log file: {}", error))?;
+    let read_rotated_files = match &query.cursor {
+        Some(cursor) => cursor.first_timestamp != current.cursor.first_timestamp,
+        None => !current.truncated && current.entries.len() < query.limit,
+    };
+    if !read_rotated_files {
+        return Ok(current);
+    }
+
+    let mut entries = VecDeque::from(std::mem::take(&mut current.entries));
+    for path in rotated_log_files(log_dir) {
+        if let Some(since) = query.since
+            && std::fs::metadata(&path)
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|modified| modified < since)
+        {
+            break;
+        }
+        let remaining = LogQuery { limit: query.limit.saturating_sub(entries.len()), ..query.clone() };
+        let older = match open_rotated(&path).and_then(|reader| read_rotated(reader, &remaining)) {
+            Ok(Some(older)) => older,
+            Ok(None) => continue,
+            Err(error) => {
+                tracing::error!(message_id = "Rk8vNc3Q", ?error, %path, "failed to read rotated log file: {}", error);
+                continue;
+            }
+        };
+        for entry in older.entries.into_iter().rev() {
+            entries.push_front(entry);
+        }
+        if query.cursor.is_some() || older.truncated || entries.len() >= query.limit {
+            current.truncated = older.truncated;
+            break;
+        }
+    }
+    Ok(LogEntries { entries: entries.into(), ..current })
+}
+
+/// Rotated log files, newest first.
+fn rotated_log_files(log_dir: &Utf8Path) -> Vec<Utf8PathBuf> {
+    let prefix = format!("{LOG_FILE_NAME}.");
+    let read_dir = match log_dir.read_dir_utf8() {
+        Ok(read_dir) => read_dir,
+        Err(error) => {
+            tracing::error!(message_id = "Wd2hXp7L", ?error, %log_dir, "failed to list log dir: {}", error);
+            return Vec::new();
+        }
+    };
+    let mut files: Vec<(SystemTime, Utf8PathBuf)> = read_dir
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_name().starts_with(&prefix))
+        .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.into_path())))
+        .collect();
+    files.sort_by(|(a, _), (b, _)| b.cmp(a));
+    files.into_iter().map(|(_, path)| path).collect()
+}
+
+fn open_rotated(path: &Utf8Path) -> std::io::Result<Box<dyn BufRead>> {
+    let file = File::open(path)?;
+    if path.extension() == Some("xz") {
+        return open_xz(file);
+    }
+    Ok(Box::new(BufReader::new(file)))
+}
+
+#[cfg(not(target_os = "macos"))]
+fn open_xz(file: File) -> std::io::Result<Box<dyn BufRead>> {
+    Ok(Box::new(BufReader::new(xz2::read::XzDecoder::new(file))))
+}
+
+#[cfg(target_os = "macos")]
+fn open_xz(_file: File) -> std::io::Result<Box<dyn BufRead>> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// Reads matching entries of a rotated file. With a cursor only the file the cursor points into is read, from the cursor position, otherwise `None` is returned. Compressed files can't seek, so the skipped part is decompressed and discarded.
+fn read_rotated(mut reader: impl BufRead, query: &LogQuery) -> std::io::Result<Option<LogEntries>> {
+    let mut first_line = String::new();
+    reader.read_line(&mut first_line)?;
+    let first_timestamp = first_timestamp(&first_line);
+    let offset = match &query.cursor {
+        Some(cursor) if cursor.first_timestamp != first_timestamp => return Ok(None),
+        Some(cursor) => cursor.offset,
+        None => 0,
+    };
+    let mut reader = Cursor::new(first_line).chain(reader);
+    std::io::copy(&mut (&mut reader).take(offset), &mut std::io::sink())?;
+    read_lines(reader, offset, query, first_timestamp).map(Some)
+}
+
+fn read_entries(mut reader: impl BufRead + Seek, query: &LogQuery) -> std::io::Result<LogEntries> {
+    let mut first_line = String::new();
+    reader.read_line(&mut first_line)?;
+    let first_timestamp = first_timestamp(&first_line);
+    let len = reader.seek(SeekFrom::End(0))?;
+    let offset = match &query.cursor {
+        Some(cursor) if cursor.first_timestamp == first_timestamp && cursor.offset <= len => cursor.offset,
+        Some(_) | None => 0,
+    };
+    reader.seek(SeekFrom::Start(offset))?;
+    read_lines(reader, offset, query, first_timestamp)
+}
+
+fn first_timestamp(first_line: &str) -> Option<String> {
+    serde_json::from_str::<Value>(first_line)
+        .ok()
+        .and_then(|entry| entry["timestamp"].as_str().map(str::to_string))
+}
+
+fn read_lines(mut reader: impl BufRead, mut offset: u64, query: &LogQuery, first_timestamp: Option<String>) -> std::io::Result<LogEntries> {
+    let mut entries = VecDeque::new();
+    let mut truncated = false;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = reader.read_line(&mut line)?;
+        // Stop at a partially written line, it's read completely by the next query.
+        if read == 0 || !line.ends_with('\n') {
+            break;
+        }
+        offset += u64::try_from(read).unwrap_or(u64::MAX);
+        let Ok(entry) = serde_json::from_str::<Value>(&line) else {
+            continue;
+        };
+        if query.matches(&entry) {
+            entries.push_back(entry);
+            if entries.len() > query.limit {
+                entries.pop_front();
+                truncated = true;
+            }
+        }
+    }
+    Ok(LogEntries { entries: entries.into(), cursor: LogCursor { first_timestamp, offset }, truncated })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = concat!(
+        r#"{"timestamp":"2025-03-01T10:00:00.000000Z","level":"INFO","fields":{"message":"started","message_id":"aaaaaaaa"}}"#,
+        "\n",
+        r#"{"timestamp":"2025-03-01T10:05:00.000000Z","level":"WARN","fields":{"message":"slow","message_id":"bbbbbbbb"}}"#,
+        "\n",
+        r#"{"timestamp":"2025-03-01T10:10:00.000000Z","level":"ERROR","fields":{"message":"failed","message_id":"cccccccc"}}"#,
+        "\n",
+        r#"{"timestamp":"2025-03-01T10:15:00.000000Z","level":"INFO","fie"#,
+    );
+
+    fn query() -> LogQuery {
+        LogQuery { since: None, min_level: None, message_id: None, cursor: None, limit: 100 }
+    }
+
+    fn message_ids(entries: &LogEntries) -> Vec<&str> {
+        entries
+            .entries
+            .iter()
+            .map(|entry| entry["fields"]["message_id"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_filters() {
+        let entries = read_entries(Cursor::new(LOG), &LogQuery { min_level: Some(LogLevel::Warn), ..query() }).unwrap();
+        assert_eq!(message_ids(&entries), ["bbbbbbbb", "cccccccc"]);
+
+        let entries = read_entries(Cursor::new(LOG), &LogQuery { message_id: Some("aaaaaaaa".to_string()), ..query() }).unwrap();
+        assert_eq!(message_ids(&entries), ["aaaaaaaa"]);
+
+        let since = chrono::DateTime::parse_from_rfc3339("2025-03-01T10:05:00Z").unwrap().into();
+        let entries = read_entries(Cursor::new(LOG), &LogQuery { since: Some(since), limit: 1, ..query() }).unwrap();
+        assert_eq!(message_ids(&entries), ["cccccccc"]);
+        assert!(entries.truncated);
+    }
+
+    #[test]
+    fn test_cursor() {
+        let (complete, partial) = LOG.split_at(LOG.rfind('\n').unwrap() + 1);
+        let first = read_entries(Cursor::new(complete), &query()).unwrap();
+        assert_eq!(first.entries.len(), 3);
+        assert_eq!(first.cursor.offset, u64::try_from(complete.len()).unwrap());
+
+        let appended = format!("{complete}{partial}lds\":{{}}}}\n");
+        let next = read_entries(
+            Cursor::new(appended.as_str()),
+            &LogQuery { cursor: Some(first.cursor.clone()), ..query() },
+        )
+        .unwrap();
+        assert_eq!(next.entries.len(), 1);
+
+        // A rotated file starts with a different entry, so it's read from the start.
+        let rotated = LOG.replace("10:00:00", "11:00:00");
+        let rotated = read_entries(Cursor::new(rotated.as_str()), &LogQuery { cursor: Some(first.cursor), ..query() }).unwrap();
+        assert_eq!(rotated.entries.len(), 3);
+    }
+
+    #[test]
+    fn test_rotated() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(dir.path()).unwrap();
+        let (complete, _) = LOG.split_at(LOG.rfind('\n').unwrap() + 1);
+        std::fs::write(dir.join(LOG_FILE_NAME), complete).unwrap();
+        let first = read_log(dir, &query()).unwrap();
+        assert_eq!(first.entries.len(), 3);
+
+        // Rotate and write a newer file.
+        std::fs::rename(dir.join(LOG_FILE_NAME), dir.join(format!("{LOG_FILE_NAME}.1"))).unwrap();
+        let newer = complete.replace("2025-03-01T10:", "2025-03-01T11:");
+        std::fs::write(dir.join(LOG_FILE_NAME), &newer).unwrap();
+
+        // Following continues in the rotated file at the cursor.
+        let next = read_log(dir, &LogQuery { cursor: Some(first.cursor), ..query() }).unwrap();
+        assert_eq!(next.entries.len(), 3);
+        assert_eq!(next.entries[0]["timestamp"], "2025-03-01T11:00:00.000000Z");
+
+        // Queries without a cursor go back into rotated files if needed.
+        let all = read_log(dir, &query()).unwrap();
+        assert_eq!(all.entries.len(), 6);
+        assert_eq!(all.entries[0]["timestamp"], "2025-03-01T10:00:00.000000Z");
+        let newest = read_log(dir, &LogQuery { limit: 2, ..query() }).unwrap();
+        assert_eq!(message_ids(&newest), ["bbbbbbbb", "cccccccc"]);
+        assert!(newest.truncated);
+    }
+
+    #[test]
+    fn test_read_rotated() {
+        let (complete, _) = LOG.split_at(LOG.rfind('\n').unwrap() + 1);
+        let first_line_len = u64::try_from(complete.find('\n').unwrap() + 1).unwrap();
+        let cursor = LogCursor { first_timestamp: Some("2025-03-01T10:00:00.000000Z".to_string()), offset: first_line_len };
+        let entries = read_rotated(Cursor::new(complete), &LogQuery { cursor: Some(cursor.clone()), ..query() })
+            .unwrap()
+            .unwrap();
+        assert_eq!(message_ids(&entries), ["bbbbbbbb", "cccccccc"]);
+
+        let other = LogCursor { first_timestamp: Some("2025-03-01T09:00:00.000000Z".to_string()), ..cursor };
+        assert!(
+            read_rotated(Cursor::new(complete), &LogQuery { cursor: Some(other), ..query() })
+                .unwrap()
+                .is_none()
+        );
+    }
+}
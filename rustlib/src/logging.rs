@@ -29,6 +29,9 @@ impl Drop for LogPersistence {
     }
 }
 
+/// Current file of the persisted log, rotated files are compressed.
+pub const LOG_FILE_NAME: &str = "rust-log.ndjson";
+
 static LOG_GUARDS: Mutex<Vec<Arc<WorkerGuard>>> = Mutex::new(Vec::new());
 
 pub fn flush_and_stop_persisted_log() {
@@ -48,7 +51,6 @@ extern "C" fn at_exit() {
 fn build_log_roller(log_dir: &Utf8Path) -> anyhow::Result<(NonBlocking, LogPersistence)> {
     use logroller::{Compression, LogRollerBuilder, Rotation, RotationSize, TimeZone};
 
-    static LOG_FILE_NAME: &str = "rust-log.ndjson";
     const MAX_LOG_FILES: u64 = 24;
     const MAX_LOG_SIZE: u64 = 10_000_000;
 
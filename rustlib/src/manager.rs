@@ -6,6 +6,7 @@ use std::{
 };
 
 use camino::Utf8PathBuf;
+use futures::future::OptionFuture;
 use obscuravpn_api::{
     cmd::{
         AppleAssociateAccount, AppleAssociateAccountOutput, Cmd, DeleteAccount, DeleteAccountOutput, ExitList, GetAccountInfo,
@@ -15,12 +16,15 @@ use obscuravpn_api::{
 };
 use serde::{Deserialize, Serialize};
 use tokio::select;
+use tokio::sync::mpsc;
 use tokio::sync::watch::{Receiver, Sender, channel};
+use tokio::time::{Interval, MissedTickBehavior, interval};
 use uuid::Uuid;
 
 use crate::{
     backoff::Backoff,
     cached_value::CachedValue,
+    captive_portal::CaptivePortal,
     client_state::{AccountStatus, ClientState, ClientStateHandle},
     config::{Config, ConfigLoadError, PinnedLocation, feature_flags::FeatureFlags},
     debug_bundle::{
@@ -30,14 +34,20 @@ use crate::{
         service,
         service::{NetworkInfo, ServiceDebugBundleHandle, ServiceDebugBundleToken},
     },
+    diagnostics::DiagnosticsReport,
     errors::{ApiError, ConfigDirty, ConfigDirtyOrApiError, ConnectErrorCode},
     exit_selection::ExitSelector,
+    log_query::{LogEntries, LogQuery, query_log},
     logging::LogPersistence,
-    manager_cmd::{ManagerCmdErrorCode, ManagerCmdOk},
+    manager_cmd::{ManagerCmd, ManagerCmdErrorCode, ManagerCmdOk},
+    manager_event::{EVENT_BUFFER, EventSender, EventSubscription, ManagerEvent},
     net::NetworkInterface,
     network_config::DnsContentBlock,
+    network_rules::{NetworkIdentity, NetworkRule},
     os::os_trait::Os,
+    policy::Policy,
     quicwg::TransportKind,
+    relay_selection::RelayBench,
     tunnel_state::TunnelState,
     wg_key_store::WgKeyStore,
 };
@@ -62,6 +72,9 @@ pub struct Status {
     pub last_chosen_exit: ExitSelector,
     pub last_exit: ExitSelector,
     pub api_url: String,
+    /// API URL configured by the user, unset if the default is used.
+    #[serde(default)]
+    pub api_url_override: Option<String>,
     pub account: Option<AccountStatus>,
     pub auto_connect: bool,
     pub feature_flags: FeatureFlags,
@@ -69,9 +82,29 @@ pub struct Status {
     pub use_system_dns: bool,
     pub local_network_access: bool,
     pub dns_content_block: DnsContentBlock,
+    pub sni_relay: Option<String>,
+    pub api_host_alternate: Option<String>,
+    pub api_doh_bootstrap_urls: Option<Vec<String>>,
+    /// Settings locked by the administrator. The locked values are already applied to the other fields.
+    #[serde(default)]
+    pub policy: Policy,
+    #[serde(default)]
+    pub network_rules: Vec<NetworkRule>,
+    /// Network the tunnel runs over, unset if there is none or the platform doesn't support network rules.
+    #[serde(default)]
+    pub network: Option<NetworkIdentity>,
+    /// Network rule applied when joining the current network.
+    #[serde(default)]
+    pub matched_network_rule: Option<NetworkRule>,
 }
 
 impl Status {
+    /// Removes the account ID and account info, for IPC peers which may observe the tunnel, but not control the account.
+    pub fn redact_account(&mut self) {
+        self.account_id = None;
+        self.account = None;
+    }
+
     fn new(version: Uuid, vpn_status: VpnStatus, client_state: &ClientState) -> Self {
         let Config {
             account_id,
@@ -85,9 +118,15 @@ impl Status {
             dns,
             dns_content_block,
             local_network_access,
+            sni_relay,
+            api_host_alternate,
+            api_doh_bootstrap_urls,
+            network_rules,
+            api_url: api_url_override,
             ..
         } = client_state.config();
         let api_url = client_state.base_url();
+        let policy = client_state.policy();
         Self {
             version,
             vpn_status,
@@ -97,13 +136,21 @@ impl Status {
             last_chosen_exit: last_chosen_exit_selector.clone(),
             last_exit: last_exit_selector.clone(),
             api_url,
+            api_url_override: api_url_override.clone(),
             account: cached_account_status.clone(),
-            auto_connect: *auto_connect,
+            auto_connect: policy.auto_connect.unwrap_or(*auto_connect),
             feature_flags: feature_flags.clone(),
             feature_flag_keys: FeatureFlags::KEYS.iter().map(ToString::to_string).collect(),
-            use_system_dns: dns.is_system(),
-            local_network_access: local_network_access.is_enabled(),
+            use_system_dns: policy.use_system_dns.unwrap_or(dns.is_system()),
+            local_network_access: policy.local_network_access.unwrap_or(local_network_access.is_enabled()),
             dns_content_block: *dns_content_block,
+            sni_relay: sni_relay.clone(),
+            api_host_alternate: api_host_alternate.clone(),
+            api_doh_bootstrap_urls: api_doh_bootstrap_urls.clone(),
+            policy: policy.clone(),
+            network_rules: network_rules.clone(),
+            network: client_state.network_identity().cloned(),
+            matched_network_rule: client_state.matched_network_rule().cloned(),
         }
     }
 }
@@ -177,8 +224,9 @@ impl Manager {
         network_interface: Receiver<Option<NetworkInterface>>,
         log_persistence: Option<LogPersistence>,
         force_init_inactive: bool,
+        policy: Policy,
     ) -> Result<Arc<Self>, ConfigLoadError> {
-        let client_state = ClientState::new(config_dir, wg_key_store, user_agent, force_init_inactive)?;
+        let client_state = ClientState::new(config_dir, wg_key_store, user_agent, force_init_inactive, policy)?;
         let tunnel_state = TunnelState::new(client_state.clone(), os_impl.clone());
         let initial_status = Status::new(Uuid::new_v4(), VpnStatus::Disconnected {}, &client_state.borrow());
         let this = Arc::new(Self {
@@ -202,10 +250,96 @@ impl Manager {
         self.status_watch.subscribe()
     }
 
+    /// Whether the command leaves the settings locked by the policy unchanged.
+    pub fn permitted_by_policy(&self, cmd: &ManagerCmd) -> bool {
+        let client_state = self.client_state.borrow();
+        let permitted = client_state.policy().permits(cmd, client_state.config().account_id.as_ref());
+        if !permitted {
+            tracing::warn!(message_id = "Kp7vRn3Y", command = cmd.name(), "rejecting command conflicting with policy");
+        }
+        permitted
+    }
+
+    pub fn subscribe_tunnel_state(&self) -> Receiver<TunnelState> {
+        self.tunnel_state.clone()
+    }
+
     pub fn traffic_stats(&self) -> ManagerTrafficStats {
         self.tunnel_state.borrow().traffic_stats()
     }
 
+    /// Streams events until the returned receiver is dropped.
+    pub fn subscribe_events(self: &Arc<Self>, subscription: EventSubscription) -> mpsc::Receiver<ManagerEvent> {
+        let (sender, receiver) = mpsc::channel(EVENT_BUFFER);
+        tokio::spawn(Self::event_task(self.clone(), subscription, EventSender::new(sender)));
+        receiver
+    }
+
+    async fn event_task(this: Arc<Self>, subscription: EventSubscription, mut events: EventSender) {
+        let mut status_recv = this.subscribe();
+        let mut client_state_recv = this.client_state.subscribe();
+        let key_and_exits = |client_state: &ClientState| {
+            let config = client_state.config();
+            let exit_list_version = config.cached_exits.as_ref().map(|exits| exits.version_bytes().to_vec());
+            (config.wireguard_key_cache.public_key(), exit_list_version)
+        };
+        let (mut public_key, mut exit_list_version) = key_and_exits(&*client_state_recv.borrow_and_update());
+        let mut connect_error = None;
+        let mut traffic_ticks = subscription.traffic_interval_ms.map(|interval_ms| {
+            let mut ticks = interval(Duration::from_millis(interval_ms.max(100)));
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            ticks
+        });
+        status_recv.mark_changed();
+        loop {
+            let cont = select! {
+                () = events.closed() => false,
+                res = status_recv.changed() => {
+                    let status = status_recv.borrow_and_update().clone();
+                    let new_connect_error = match &status.vpn_status {
+                        VpnStatus::Connecting { connect_error, .. } => *connect_error,
+                        VpnStatus::Connected { .. } | VpnStatus::Disconnected {} => None,
+                    };
+                    let mut cont = res.is_ok() && events.send(ManagerEvent::Status(status));
+                    if let Some(error) = new_connect_error
+                        && connect_error != new_connect_error
+                    {
+                        cont = cont && events.send(ManagerEvent::ConnectError { error });
+                    }
+                    connect_error = new_connect_error;
+                    cont
+                }
+                res = client_state_recv.changed() => {
+                    let (new_public_key, new_exit_list_version) = key_and_exits(&*client_state_recv.borrow_and_update());
+                    let mut cont = res.is_ok();
+                    if let Some(new_public_key) = new_public_key
+                        && public_key != Some(new_public_key)
+                    {
+                        cont = cont && events.send(ManagerEvent::KeyRotated { public_key: new_public_key });
+                    }
+                    if let Some(version) = &new_exit_list_version
+                        && exit_list_version != new_exit_list_version
+                    {
+                        cont = cont && events.send(ManagerEvent::ExitListUpdated { version: version.clone() });
+                    }
+                    (public_key, exit_list_version) = (new_public_key, new_exit_list_version);
+                    cont
+                }
+                Some(_) = OptionFuture::from(traffic_ticks.as_mut().map(Interval::tick)) => {
+                    let connected = matches!(status_recv.borrow().vpn_status, VpnStatus::Connected { .. });
+                    !connected || events.send(ManagerEvent::TrafficStats(this.traffic_stats()))
+                }
+            };
+            if !cont {
+                break;
+            }
+            if events.take_resync() && !events.send(ManagerEvent::Status(status_recv.borrow().clone())) {
+                break;
+            }
+        }
+        tracing::info!(message_id = "Vh3kTs6W", "event subscription ended");
+    }
+
     pub async fn login(&self, account_id: AccountId, validate: bool) -> Result<(), ConfigDirtyOrApiError> {
         let mut auth_token = None;
         if validate {
@@ -330,6 +464,21 @@ impl Manager {
         }
     }
 
+    /// Called by the OS integration whenever it re-applied DNS settings that were changed by something else. Reported in debug info.
+    pub fn set_dns_watchdog_interventions(&self, interventions: u64) {
+        self.client_state.set_dns_watchdog_interventions(interventions);
+    }
+
+    /// Called by the OS integration whenever the preferred network interface changes, see `NetworkRule`.
+    pub fn set_network_identity(&self, network_identity: Option<NetworkIdentity>) {
+        self.client_state.set_network_identity(network_identity);
+    }
+
+    /// See `ClientStateHandle::detect_captive_portal`.
+    pub async fn detect_captive_portal(&self) -> Result<Option<CaptivePortal>, ()> {
+        self.client_state.detect_captive_portal().await
+    }
+
     pub async fn create_debug_bundle(
         &self,
         user_feedback: Option<String>,
@@ -371,6 +520,28 @@ impl Manager {
         self.client_state.get_debug_info().await
     }
 
+    pub async fn query_log(&self, query: LogQuery) -> Result<LogEntries, ()> {
+        let Some(log_persistence) = &self.log_persistence else {
+            tracing::error!(message_id = "Pn4wEh6T", "log query without log persistence");
+            return Err(());
+        };
+        query_log(log_persistence.log_dir(), query).await
+    }
+
+    pub async fn bench_relays(&self) -> Result<RelayBench, ApiError> {
+        self.client_state.bench_relays().await
+    }
+
+    pub async fn run_diagnostics(&self) -> DiagnosticsReport {
+        let vpn_connected = matches!(*self.tunnel_state.borrow(), TunnelState::Connected { .. });
+        let (debug_info, relays_quic, relays_tcp_tls) = tokio::join!(
+            self.get_debug_info(),
+            self.client_state.probe_relays(false),
+            self.client_state.probe_relays(true)
+        );
+        DiagnosticsReport::new(vpn_connected, debug_info, relays_quic, relays_tcp_tls)
+    }
+
     pub fn wake(&self) {
         if let Some(conn) = self.tunnel_state.borrow().get_conn() {
             conn.wake();
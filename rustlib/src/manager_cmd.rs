@@ -10,12 +10,13 @@ use obscuravpn_api::{
     types::{AccountId, AccountInfo},
 };
 use serde::{Deserialize, Serialize};
-use strum::IntoStaticStr;
+use strum::{IntoStaticStr, VariantNames};
 use tokio::spawn;
 use uuid::Uuid;
 
 use crate::{
     cached_value::CachedValue,
+    captive_portal::CaptivePortalBypass,
     client_state::ClientStateHandle,
     config::PinnedLocation,
     debug_bundle::{
@@ -23,9 +24,15 @@ use crate::{
         debug_info::DebugInfo,
         service::{ServiceDebugBundleHandle, ServiceDebugBundleToken},
     },
+    diagnostics::DiagnosticsReport,
     errors::{ApiError, ConfigDirty, ConfigDirtyOrApiError},
+    leak_test::LeakTestReport,
+    log_query::{LogEntries, LogQuery},
     manager::{Manager, ManagerTrafficStats, Status, TunnelArgs},
+    manager_event::EventSubscription,
     network_config::DnsContentBlock,
+    network_rules::NetworkRule,
+    relay_selection::RelayBench,
 };
 
 /// High-level json command error codes, which are actionable for frontends.
@@ -50,7 +57,10 @@ pub enum ManagerCmdErrorCode {
     ApiSignupLimitExceeded,
     ApiUnreachable,
     ConfigSaveError,
+    InsufficientPermissions,
+    NoCaptivePortal,
     Other,
+    PolicyLocked,
 }
 
 impl ManagerCmdErrorCode {
@@ -115,10 +125,24 @@ impl From<&ApiError> for ManagerCmdErrorCode {
     }
 }
 
+/// Privilege required to run a command, for platforms where the IPC channel is shared by local users with different trust levels.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, IntoStaticStr)]
+#[serde(rename_all = "camelCase")]
+#[strum(serialize_all = "camelCase")]
+pub enum AccessLevel {
+    /// Observing the tunnel, without access to the account ID.
+    ReadOnly,
+    /// Controlling the tunnel and the account.
+    Operator,
+    /// Changing how the client reaches the API, feature flags, deleting the account and terminating the service.
+    Admin,
+}
+
 // Keep synchronized with ../../apple/shared/NetworkExtensionIpc.swift
 #[serde_with::serde_as]
-#[derive(derive_more::Debug, Serialize, Deserialize, Clone)]
+#[derive(derive_more::Debug, Serialize, Deserialize, Clone, IntoStaticStr, VariantNames)]
 #[serde(rename_all = "camelCase", rename_all_fields = "camelCase")]
+#[strum(serialize_all = "camelCase")]
 pub enum ManagerCmd {
     ApiAppleAssociateAccount {
         app_transaction_jws: String,
@@ -132,6 +156,9 @@ pub enum ManagerCmd {
     ApiGoogleBillingDetails {
         promo_code: Option<String>,
     },
+    BenchRelays {},
+    /// Temporarily lets traffic bypass the tunnel to log in to a captive portal, see `CaptivePortalBypass`.
+    BypassCaptivePortal {},
     CreateDebugBundle {
         user_feedback: Option<String>,
         bundle_info: BundleInfo,
@@ -139,6 +166,9 @@ pub enum ManagerCmd {
     },
     CreateServiceDebugBundle {},
     GetDebugInfo {},
+    GetLogs {
+        query: LogQuery,
+    },
     GetExitList {
         #[debug("{:?}", known_version.as_ref().map(|b| BASE64_STANDARD.encode(b)))]
         #[serde_as(as = "Option<serde_with::base64::Base64>")]
@@ -163,6 +193,11 @@ pub enum ManagerCmd {
         token: ServiceDebugBundleToken,
     },
     RotateWgKey {},
+    RunDiagnostics {},
+    RunLeakTest {},
+    SetApiDohBootstrapUrls {
+        urls: Option<Vec<String>>,
+    },
     SetApiHostAlternate {
         host: Option<String>,
     },
@@ -198,6 +233,14 @@ pub enum ManagerCmd {
     SetLocalNetworkAccess {
         enable: bool,
     },
+    SetNetworkRules {
+        rules: Vec<NetworkRule>,
+    },
+    /// Turns the connection into a stream of `ManagerEvent`s instead of responding.
+    SubscribeEvents {
+        #[serde(default)]
+        subscription: EventSubscription,
+    },
 }
 
 #[derive(Debug, derive_more::From, Serialize)]
@@ -211,13 +254,23 @@ pub enum ManagerCmdOk {
     ApiGetAccountInfo(AccountInfo),
     #[from]
     ApiGoogleBillingDetails(GoogleBillingDetailsOutput),
+    #[from]
+    BenchRelays(RelayBench),
+    #[from]
+    BypassCaptivePortal(CaptivePortalBypass),
     CreateDebugBundle(String),
     CreateServiceDebugBundle(ServiceDebugBundleHandle),
     Empty,
     GetDebugInfo(DebugInfo),
     GetExitList(CachedValue<Arc<ExitList>>),
+    #[from]
+    GetLogs(LogEntries),
     GetStatus(Status),
     GetTrafficStats(ManagerTrafficStats),
+    #[from]
+    RunDiagnostics(DiagnosticsReport),
+    #[from]
+    RunLeakTest(LeakTestReport),
 }
 
 impl From<()> for ManagerCmdOk {
@@ -235,6 +288,51 @@ where
 }
 
 impl ManagerCmd {
+    /// Variant name as used in the JSON encoding and in `ManagerCmd::VARIANTS`.
+    pub fn name(&self) -> &'static str {
+        self.into()
+    }
+
+    pub fn required_access(&self) -> AccessLevel {
+        match self {
+            Self::GetExitList { .. } | Self::GetStatus { .. } | Self::GetTrafficStats {} | Self::Ping {} | Self::SubscribeEvents { .. } => {
+                AccessLevel::ReadOnly
+            }
+            Self::ApiAppleAssociateAccount { .. }
+            | Self::ApiGetAccountInfo {}
+            | Self::ApiGoogleAssociateAccount { .. }
+            | Self::ApiGoogleBillingDetails { .. }
+            | Self::BenchRelays {}
+            | Self::BypassCaptivePortal {}
+            | Self::CreateDebugBundle { .. }
+            | Self::CreateServiceDebugBundle {}
+            | Self::DeleteServiceDebugBundle { .. }
+            | Self::GetDebugInfo {}
+            | Self::GetLogs { .. }
+            | Self::Login { .. }
+            | Self::Logout {}
+            | Self::RefreshExitList { .. }
+            | Self::RotateWgKey {}
+            | Self::RunDiagnostics {}
+            | Self::RunLeakTest {}
+            | Self::SetAutoConnect { .. }
+            | Self::SetDnsContentBlock { .. }
+            | Self::SetInNewAccountFlow { .. }
+            | Self::SetLocalNetworkAccess { .. }
+            | Self::SetNetworkRules { .. }
+            | Self::SetPinnedExits { .. }
+            | Self::SetTunnelArgs { .. }
+            | Self::SetUseSystemDns { .. } => AccessLevel::Operator,
+            Self::ApiDeleteAccount {}
+            | Self::SetApiDohBootstrapUrls { .. }
+            | Self::SetApiHostAlternate { .. }
+            | Self::SetApiUrl { .. }
+            | Self::SetFeatureFlag { .. }
+            | Self::SetSniRelay { .. }
+            | Self::TerminateProcess {} => AccessLevel::Admin,
+        }
+    }
+
     pub fn from_json(json_cmd: &[u8]) -> Result<Self, ManagerCmdErrorCode> {
         // apple frameworks log IPC message SHA1
         let hash = ring::digest::digest(&ring::digest::SHA1_FOR_LEGACY_USE_ONLY, json_cmd);
@@ -261,6 +359,9 @@ impl ManagerCmd {
     }
 
     pub async fn run(self, manager: &Manager) -> Result<ManagerCmdOk, ManagerCmdErrorCode> {
+        if !manager.permitted_by_policy(&self) {
+            return Err(ManagerCmdErrorCode::PolicyLocked);
+        }
         match self {
             Self::ApiAppleAssociateAccount { app_transaction_jws } => map_result(manager.apple_associate_account(app_transaction_jws).await),
             Self::ApiDeleteAccount {} => map_result(manager.delete_account().await),
@@ -270,6 +371,12 @@ impl ManagerCmd {
             }
             Self::ApiGoogleBillingDetails { promo_code } => map_result(manager.google_billing_details(promo_code).await),
             Self::SetFeatureFlag { flag, active } => manager.run_on_client_state(|c| c.set_feature_flag(&flag, active)),
+            Self::BenchRelays {} => map_result(manager.bench_relays().await),
+            Self::BypassCaptivePortal {} => {
+                // Requires access to the OS network integration, platforms supporting it handle the command before it gets here.
+                tracing::error!(message_id = "Bk8rZw3M", "captive portal bypass is not supported on this platform");
+                Err(ManagerCmdErrorCode::Other)
+            }
             Self::CreateDebugBundle { user_feedback, bundle_info, android_cache_dir } => manager
                 .create_debug_bundle(user_feedback, bundle_info, android_cache_dir)
                 .await
@@ -290,6 +397,11 @@ impl ManagerCmd {
                 .map_err(|()| ManagerCmdErrorCode::Other),
             Self::GetDebugInfo {} => Ok(ManagerCmdOk::GetDebugInfo(manager.get_debug_info().await)),
             Self::GetExitList { known_version } => manager.get_exit_list(known_version).await.map(ManagerCmdOk::GetExitList),
+            Self::GetLogs { query } => manager
+                .query_log(query)
+                .await
+                .map(ManagerCmdOk::GetLogs)
+                .map_err(|()| ManagerCmdErrorCode::Other),
             Self::GetStatus { known_version } => manager
                 .subscribe()
                 .wait_for(|s| Some(s.version) != known_version)
@@ -315,7 +427,14 @@ impl ManagerCmd {
             Self::Ping {} => Ok(ManagerCmdOk::Empty),
             Self::RefreshExitList { freshness } => map_result(manager.maybe_update_exits(freshness).await),
             Self::RotateWgKey {} => manager.run_on_client_state(ClientStateHandle::rotate_wg_key),
+            Self::RunDiagnostics {} => Ok(manager.run_diagnostics().await.into()),
+            Self::RunLeakTest {} => {
+                // Requires access to the OS network integration, platforms supporting it handle the command before it gets here.
+                tracing::error!(message_id = "Wm4hTq8C", "leak test is not supported on this platform");
+                Err(ManagerCmdErrorCode::Other)
+            }
             Self::SetAutoConnect { enable } => manager.run_on_client_state(|c| c.set_auto_connect(enable)),
+            Self::SetApiDohBootstrapUrls { urls } => manager.run_on_client_state(|c| c.set_api_doh_bootstrap_urls(urls)),
             Self::SetApiHostAlternate { host } => manager.run_on_client_state(|c| c.set_api_host_alternate(host)),
             Self::SetApiUrl { url } => manager.run_on_client_state(|c| c.set_api_url(url)),
             Self::SetDnsContentBlock { value } => manager.run_on_client_state(|c| c.set_dns_content_block(value)),
@@ -325,6 +444,12 @@ impl ManagerCmd {
             Self::SetTunnelArgs { args, active } => manager.run_on_client_state(|c| c.set_tunnel_target_state(args, active)),
             Self::SetUseSystemDns { enable } => manager.run_on_client_state(|c| c.set_use_system_dns(enable)),
             Self::SetLocalNetworkAccess { enable } => manager.run_on_client_state(|c| c.set_local_network_access(enable)),
+            Self::SetNetworkRules { rules } => manager.run_on_client_state(|c| c.set_network_rules(rules)),
+            Self::SubscribeEvents { subscription: _ } => {
+                // Requires a streaming transport, platforms supporting it handle the command before it gets here.
+                tracing::error!(message_id = "Kc9wFm2T", "event subscriptions are not supported on this platform");
+                Err(ManagerCmdErrorCode::Other)
+            }
         }
     }
 }
@@ -0,0 +1,116 @@
+//! Typed events derived from manager state, for clients which follow changes over a single connection instead of polling.
+
+use obscuravpn_api::types::WgPubkey;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+use crate::errors::ConnectErrorCode;
+use crate::manager::{ManagerTrafficStats, Status};
+
+/// Events buffered per subscriber before further events are dropped, see [`ManagerEvent::Lagged`].
+pub const EVENT_BUFFER: usize = 64;
+
+#[serde_with::serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", rename_all_fields = "camelCase")]
+pub enum ManagerEvent {
+    /// Sent on subscription and whenever the status version changes.
+    Status(Status),
+    /// Sent once per distinct error while connecting.
+    ConnectError {
+        error: ConnectErrorCode,
+    },
+    /// Sent periodically while connected, if requested by the subscription.
+    TrafficStats(ManagerTrafficStats),
+    KeyRotated {
+        public_key: WgPubkey,
+    },
+    ExitListUpdated {
+        #[serde_as(as = "serde_with::base64::Base64")]
+        version: Vec<u8>,
+    },
+    /// The subscriber didn't keep up and events were dropped. It's followed by a current status event.
+    Lagged {
+        dropped: u64,
+    },
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventSubscription {
+    /// Interval of traffic stats events, none are sent if unset.
+    pub traffic_interval_ms: Option<u64>,
+}
+
+/// Never waits for the subscriber, so a slow subscriber can't stall event production. Events which don't fit the buffer are counted and reported as [`ManagerEvent::Lagged`] once there's room again.
+pub struct EventSender {
+    sender: mpsc::Sender<ManagerEvent>,
+    dropped: u64,
+    resync: bool,
+}
+
+impl EventSender {
+    pub fn new(sender: mpsc::Sender<ManagerEvent>) -> Self {
+        Self { sender, dropped: 0, resync: false }
+    }
+
+    /// Returns `false` once the subscriber is gone.
+    pub fn send(&mut self, event: ManagerEvent) -> bool {
+        if self.dropped > 0 {
+            match self.sender.try_send(ManagerEvent::Lagged { dropped: self.dropped }) {
+                Ok(()) => {
+                    tracing::warn!(message_id = "Lq5vNd8R", dropped = self.dropped, "event subscriber lagged behind");
+                    self.dropped = 0;
+                    self.resync = true;
+                }
+                Err(TrySendError::Full(_)) => {
+                    self.dropped += 1;
+                    return true;
+                }
+                Err(TrySendError::Closed(_)) => return false,
+            }
+        }
+        match self.sender.try_send(event) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.dropped += 1;
+                true
+            }
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+
+    /// Whether a status event should be sent because the subscriber may have missed status changes.
+    pub fn take_resync(&mut self) -> bool {
+        std::mem::take(&mut self.resync)
+    }
+
+    pub async fn closed(&self) {
+        self.sender.closed().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lagged() {
+        let (sender, mut receiver) = mpsc::channel(2);
+        let mut sender = EventSender::new(sender);
+        for dropped in 0..5 {
+            assert!(sender.send(ManagerEvent::Lagged { dropped }));
+        }
+        assert!(matches!(receiver.try_recv(), Ok(ManagerEvent::Lagged { dropped: 0 })));
+        assert!(matches!(receiver.try_recv(), Ok(ManagerEvent::Lagged { dropped: 1 })));
+
+        assert!(sender.send(ManagerEvent::Lagged { dropped: 10 }));
+        assert!(matches!(receiver.try_recv(), Ok(ManagerEvent::Lagged { dropped: 3 })));
+        assert!(matches!(receiver.try_recv(), Ok(ManagerEvent::Lagged { dropped: 10 })));
+        assert!(sender.take_resync());
+        assert!(!sender.take_resync());
+
+        drop(receiver);
+        assert!(!sender.send(ManagerEvent::Lagged { dropped: 0 }));
+    }
+}
@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+/// Identity of the network the tunnel runs over, gathered by the OS integration whenever the preferred network interface changes. Fields the OS integration can't determine are unset, the gateway MAC may be set later once it is known.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkIdentity {
+    pub interface_name: String,
+    pub wireless: bool,
+    pub gateway_ip: Option<IpAddr>,
+    /// Lowercase and colon separated, e.g. `00:11:22:aa:bb:cc`.
+    pub gateway_mac: Option<String>,
+    /// NetworkManager connection profile.
+    pub connection_uuid: Option<String>,
+    pub ssid: Option<String>,
+}
+
+impl NetworkIdentity {
+    /// Whether both identify the same network. The gateway MAC isn't compared, since it's only known once the gateway's neighbor entry was resolved, which would make the same network look new.
+    pub fn same_network(&self, other: &Self) -> bool {
+        let Self { interface_name, wireless, gateway_ip, gateway_mac: _, connection_uuid, ssid } = self;
+        *interface_name == other.interface_name
+            && *wireless == other.wireless
+            && *gateway_ip == other.gateway_ip
+            && *connection_uuid == other.connection_uuid
+            && *ssid == other.ssid
+    }
+}
+
+/// Conditions on the network identity. All set fields have to match, so an empty match applies to every network.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct NetworkMatch {
+    pub interface_name: Option<String>,
+    pub wireless: Option<bool>,
+    pub gateway_ip: Option<IpAddr>,
+    /// Compared case-insensitively.
+    pub gateway_mac: Option<String>,
+    pub connection_uuid: Option<String>,
+    pub ssid: Option<String>,
+}
+
+impl NetworkMatch {
+    pub fn matches(&self, identity: &NetworkIdentity) -> bool {
+        let Self { interface_name, wireless, gateway_ip, gateway_mac, connection_uuid, ssid } = self;
+        fn field<T: PartialEq>(wanted: &Option<T>, actual: Option<&T>) -> bool {
+            wanted.as_ref().is_none_or(|wanted| actual == Some(wanted))
+        }
+        field(interface_name, Some(&identity.interface_name))
+            && field(wireless, Some(&identity.wireless))
+            && field(gateway_ip, identity.gateway_ip.as_ref())
+            && gateway_mac
+                .as_ref()
+                .is_none_or(|wanted| identity.gateway_mac.as_ref().is_some_and(|actual| actual.eq_ignore_ascii_case(wanted)))
+            && field(connection_uuid, identity.connection_uuid.as_ref())
+            && field(ssid, identity.ssid.as_ref())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum NetworkAction {
+    Connect,
+    Disconnect,
+}
+
+/// Connects or disconnects the tunnel when joining a matching network. Rules are only applied when the network changes, so the tunnel can still be toggled manually afterwards.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct NetworkRule {
+    /// Shown to users, e.g. "Office Ethernet".
+    #[serde(default)]
+    pub name: String,
+    pub network: NetworkMatch,
+    pub action: NetworkAction,
+}
+
+/// The first matching rule applies, so more specific rules (e.g. a trusted SSID) go before more general ones (e.g. any wireless network).
+pub fn matching_rule<'a>(rules: &'a [NetworkRule], identity: &NetworkIdentity) -> Option<&'a NetworkRule> {
+    rules.iter().find(|rule| rule.network.matches(identity))
+}
+
+#[test]
+fn test_matching_rule() {
+    let rules: Vec<NetworkRule> = serde_json::from_str(
+        r#"[
+            {"name":"Home","network":{"ssid":"home"},"action":"disconnect"},
+            {"name":"Office","network":{"wireless":false,"gatewayMac":"00:11:22:AA:BB:CC"},"action":"disconnect"},
+            {"name":"Untrusted Wi-Fi","network":{"wireless":true},"action":"connect"}
+        ]"#,
+    )
+    .unwrap();
+    let wifi = |ssid: &str| NetworkIdentity { interface_name: "wlan0".into(), wireless: true, ssid: Some(ssid.into()), ..Default::default() };
+    let ethernet =
+        |gateway_mac: Option<&str>| NetworkIdentity { interface_name: "eth0".into(), gateway_mac: gateway_mac.map(Into::into), ..Default::default() };
+    assert_eq!(matching_rule(&rules, &wifi("home")).unwrap().name, "Home");
+    assert_eq!(matching_rule(&rules, &wifi("airport")).unwrap().name, "Untrusted Wi-Fi");
+    assert_eq!(matching_rule(&rules, &ethernet(Some("00:11:22:aa:bb:cc"))).unwrap().name, "Office");
+    assert_eq!(matching_rule(&rules, &ethernet(Some("00:11:22:aa:bb:cd"))), None);
+    assert_eq!(matching_rule(&rules, &ethernet(None)), None);
+
+    assert!(NetworkMatch::default().matches(&ethernet(None)));
+    assert!(ethernet(None).same_network(&ethernet(Some("00:11:22:aa:bb:cc"))));
+    assert!(!wifi("home").same_network(&wifi("airport")));
+    assert!(serde_json::from_str::<NetworkMatch>(r#"{"SSID":"home"}"#).is_err());
+}
@@ -0,0 +1,128 @@
+use crate::exit_selection::ExitSelector;
+use crate::manager_cmd::ManagerCmd;
+use obscuravpn_api::types::{AccountId, CountryCode, OneExit};
+use serde::{Deserialize, Serialize};
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// Settings locked by an administrator, e.g. `/etc/obscura/policy.json` on Linux. Locked settings take precedence over the config, and commands changing them to anything else are rejected with `ManagerCmdErrorCode::PolicyLocked`. Unset fields aren't locked.
+///
+/// The policy is only loaded when the service starts, changes take effect after restarting it.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Policy {
+    /// Keeps the tunnel active, so traffic never bypasses it. This includes captive portal bypasses. There's no separate kill switch setting, the service always blocks traffic outside the tunnel while it's active.
+    #[serde(default)]
+    pub lockdown: bool,
+    pub local_network_access: Option<bool>,
+    pub use_system_dns: Option<bool>,
+    pub auto_connect: Option<bool>,
+    /// Only exits in these countries (lowercase ISO 3166-1 alpha-2) are selected.
+    pub allowed_exit_countries: Option<Vec<CountryCode>>,
+    /// Prevents logging out, switching accounts and deleting the account.
+    #[serde(default)]
+    pub disable_logout: bool,
+}
+
+impl Policy {
+    /// A missing file is an empty policy. An invalid file is an error rather than being ignored, since that would silently unlock all settings.
+    pub fn load(path: &Path) -> Result<Self, ()> {
+        let json = match std::fs::read(path) {
+            Ok(json) => json,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(error) => {
+                tracing::error!(message_id = "Wq3hZk8D", ?error, ?path, "failed to read policy: {error}");
+                return Err(());
+            }
+        };
+        let policy: Self = serde_json::from_slice(&json).map_err(|error| {
+            tracing::error!(message_id = "Fb6tMv2N", ?error, ?path, "failed to parse policy: {error}");
+        })?;
+        tracing::info!(message_id = "Rs9cXp4L", ?policy, "loaded policy");
+        Ok(policy)
+    }
+
+    /// Whether the selector may select any exit. Selectors of single exits are permitted here, and filtered out by [`Policy::allows_exit`] when connecting.
+    pub fn permits_selector(&self, selector: &ExitSelector) -> bool {
+        match selector {
+            ExitSelector::Any {} | ExitSelector::Exit { .. } => true,
+            ExitSelector::Country { country_code } => self.allows_country(country_code),
+            ExitSelector::City { city_code } => self.allows_country(&city_code.country_code),
+        }
+    }
+
+    pub fn allows_exit(&self, exit: &OneExit) -> bool {
+        self.allows_country(&exit.city_code.country_code)
+    }
+
+    fn allows_country(&self, country_code: &CountryCode) -> bool {
+        self.allowed_exit_countries.as_ref().is_none_or(|allowed| allowed.contains(country_code))
+    }
+
+    /// Whether the command leaves locked settings unchanged. `account_id` is the currently logged in account.
+    pub fn permits(&self, cmd: &ManagerCmd, account_id: Option<&AccountId>) -> bool {
+        let conflicts = |locked: Option<bool>, value: bool| locked.is_some_and(|locked| locked != value);
+        match cmd {
+            ManagerCmd::SetAutoConnect { enable } => !conflicts(self.auto_connect, *enable),
+            ManagerCmd::SetLocalNetworkAccess { enable } => !conflicts(self.local_network_access, *enable),
+            ManagerCmd::SetUseSystemDns { enable } => !conflicts(self.use_system_dns, *enable),
+            ManagerCmd::SetTunnelArgs { args, active } => {
+                !(self.lockdown && *active == Some(false)) && args.as_ref().is_none_or(|args| self.permits_selector(&args.exit))
+            }
+            ManagerCmd::Login { account_id: new_account_id, validate: _ } => {
+                !self.disable_logout || account_id.is_none_or(|account_id| account_id == new_account_id)
+            }
+            ManagerCmd::ApiDeleteAccount {} | ManagerCmd::Logout {} => !self.disable_logout,
+            ManagerCmd::BypassCaptivePortal {} => !self.lockdown,
+            ManagerCmd::ApiAppleAssociateAccount { .. }
+            | ManagerCmd::ApiGetAccountInfo {}
+            | ManagerCmd::ApiGoogleAssociateAccount { .. }
+            | ManagerCmd::ApiGoogleBillingDetails { .. }
+            | ManagerCmd::BenchRelays {}
+            | ManagerCmd::CreateDebugBundle { .. }
+            | ManagerCmd::CreateServiceDebugBundle {}
+            | ManagerCmd::DeleteServiceDebugBundle { .. }
+            | ManagerCmd::GetDebugInfo {}
+            | ManagerCmd::GetExitList { .. }
+            | ManagerCmd::GetLogs { .. }
+            | ManagerCmd::GetStatus { .. }
+            | ManagerCmd::GetTrafficStats {}
+            | ManagerCmd::Ping {}
+            | ManagerCmd::RefreshExitList { .. }
+            | ManagerCmd::RotateWgKey {}
+            | ManagerCmd::RunDiagnostics {}
+            | ManagerCmd::RunLeakTest {}
+            | ManagerCmd::SetApiDohBootstrapUrls { .. }
+            | ManagerCmd::SetApiHostAlternate { .. }
+            | ManagerCmd::SetApiUrl { .. }
+            | ManagerCmd::SetDnsContentBlock { .. }
+            | ManagerCmd::SetFeatureFlag { .. }
+            | ManagerCmd::SetInNewAccountFlow { .. }
+            | ManagerCmd::SetNetworkRules { .. }
+            | ManagerCmd::SetPinnedExits { .. }
+            | ManagerCmd::SetSniRelay { .. }
+            | ManagerCmd::SubscribeEvents { .. }
+            | ManagerCmd::TerminateProcess {} => true,
+        }
+    }
+}
+
+#[test]
+fn test_permits() {
+    use crate::manager::TunnelArgs;
+
+    let policy: Policy = serde_json::from_str(r#"{"lockdown":true,"allowedExitCountries":["de"],"disableLogout":true}"#).unwrap();
+    let country = |code: &str| ExitSelector::Country { country_code: CountryCode(code.into()) };
+    let set_tunnel_args = |exit: Option<ExitSelector>, active| ManagerCmd::SetTunnelArgs { args: exit.map(|exit| TunnelArgs { exit }), active };
+    assert!(policy.permits(&set_tunnel_args(Some(country("de")), Some(true)), None));
+    assert!(!policy.permits(&set_tunnel_args(Some(country("us")), None), None));
+    assert!(!policy.permits(&set_tunnel_args(None, Some(false)), None));
+    assert!(policy.permits(&ManagerCmd::SetFeatureFlag { flag: "forceSmallMtu".into(), active: false }, None));
+    assert!(policy.permits(&ManagerCmd::SetAutoConnect { enable: false }, None));
+    assert!(!policy.permits(&ManagerCmd::Logout {}, None));
+    assert!(!policy.permits(&ManagerCmd::BypassCaptivePortal {}, None));
+
+    assert!(Policy::default().permits(&ManagerCmd::Logout {}, None));
+    assert!(serde_json::from_str::<Policy>(r#"{"lockDown":true}"#).is_err());
+    assert!(serde_json::from_str::<Policy>(r#"{"killSwitch":true}"#).is_err());
+}
@@ -209,6 +209,11 @@ impl QuicWgConnPacketSender {
         Self(conn.map(Arc::downgrade).unwrap_or_default())
     }
 
+    /// Whether packets go to a tunnel, rather than being dropped while connecting.
+    pub fn has_tunnel(&self) -> bool {
+        self.0.strong_count() > 0
+    }
+
     pub fn send<'a>(&self, packets: impl Iterator<Item = &'a [u8]>) {
         if let Some(conn) = self.0.upgrade() {
             conn.send(packets)
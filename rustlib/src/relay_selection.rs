@@ -1,14 +1,23 @@
 use crate::errors::RelaySelectionError;
 use crate::net::{NetworkInterface, new_quic, new_udp};
-use crate::quicwg::{QuicWgConnHandshaking, QuicWgConnectError};
+use crate::quicwg::{QuicWgConnHandshaking, QuicWgConnectError, QuicWgRelayHandshakeError, TransportKind};
 use flume::{Receiver, SendError, bounded};
 use obscuravpn_api::types::OneRelay;
+use serde::{Deserialize, Serialize};
+use std::net::Ipv4Addr;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::spawn;
 use tokio::task::JoinSet;
+use tokio::time::timeout;
 
-pub fn race_relay_handshakes(
+// Maximum number of relays to probe. This limit should be high enough that a non-malicious API server won't exceed it.
+// This prevents memory exhaustion issues in case a malicious API server sends a large number of relays.
+pub const MAX_RELAYS: usize = 100;
+
+type RelayHandshakeResult = (Result<(QuicWgConnHandshaking, Duration), QuicWgConnectError>, OneRelay, u16);
+
+fn spawn_relay_handshakes(
     network_interface: Option<&NetworkInterface>,
     relays: &[OneRelay],
     sni: String,
@@ -16,16 +25,13 @@ pub fn race_relay_handshakes(
     quic_frame_padding: bool,
     force_small_mtu: bool,
     mtu: Option<u16>,
-) -> Result<Receiver<(OneRelay, u16, Duration, QuicWgConnHandshaking)>, RelaySelectionError> {
+    handshake_timeout: Option<Duration>,
+) -> Result<JoinSet<RelayHandshakeResult>, RelaySelectionError> {
     let sni = Arc::new(sni);
     let mut tasks = JoinSet::new();
     let udp = new_udp(network_interface).map_err(RelaySelectionError::UdpSetup)?;
     let quic_endpoint = new_quic(udp, mtu, force_small_mtu).map_err(RelaySelectionError::QuicSetup)?;
 
-    // Maximum number of relays to probe. This limit should be high enough that a non-malicious API server won't exceed it.
-    // This prevents memory exhaustion issues in case a malicious API server sends a large number of relays.
-    const MAX_RELAYS: usize = 100;
-
     for relay in relays.iter().take(MAX_RELAYS) {
         for &port in &relay.ports {
             let quic_endpoint = quic_endpoint.clone();
@@ -35,7 +41,7 @@ pub fn race_relay_handshakes(
             let sni = sni.clone();
             let network_interface = network_interface.cloned();
             tasks.spawn(async move {
-                let result: Result<(QuicWgConnHandshaking, Duration), QuicWgConnectError> = async {
+                let handshake = async {
                     let mut handshaking = match use_tcp_tls {
                         true => {
                             QuicWgConnHandshaking::start_tcp_tls(relay.id.clone(), network_interface.as_ref(), relay_addr, relay_cert, &sni).await
@@ -46,13 +52,40 @@ pub fn race_relay_handshakes(
                         }
                     }?;
                     let rtt = handshaking.measure_rtt().await?;
-                    Ok((handshaking, rtt))
-                }
-                .await;
+                    Ok::<_, QuicWgConnectError>((handshaking, rtt))
+                };
+                let result = match handshake_timeout {
+                    Some(handshake_timeout) => timeout(handshake_timeout, handshake)
+                        .await
+                        .unwrap_or_else(|_| Err(QuicWgRelayHandshakeError::Timeout("relay handshake").into())),
+                    None => handshake.await,
+                };
                 (result, relay, port)
             });
         }
     }
+    Ok(tasks)
+}
+
+pub fn race_relay_handshakes(
+    network_interface: Option<&NetworkInterface>,
+    relays: &[OneRelay],
+    sni: String,
+    use_tcp_tls: bool,
+    quic_frame_padding: bool,
+    force_small_mtu: bool,
+    mtu: Option<u16>,
+) -> Result<Receiver<(OneRelay, u16, Duration, QuicWgConnHandshaking)>, RelaySelectionError> {
+    let mut tasks = spawn_relay_handshakes(
+        network_interface,
+        relays,
+        sni,
+        use_tcp_tls,
+        quic_frame_padding,
+        force_small_mtu,
+        mtu,
+        None,
+    )?;
 
     let (sender, receiver) = bounded(0);
     spawn(async move {
@@ -84,3 +117,89 @@ pub fn race_relay_handshakes(
     });
     Ok(receiver)
 }
+
+/// Outcome of a handshake with one relay port, see [`bench_relay_handshakes`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RelayBenchResult {
+    pub relay_id: String,
+    pub ip: Ipv4Addr,
+    pub port: u16,
+    pub transport: TransportKind,
+    pub rtt_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RelayBench {
+    pub results: Vec<RelayBenchResult>,
+}
+
+/// Handshakes with every port of every relay and reports each outcome, unlike [`race_relay_handshakes`] which only yields successes.
+pub async fn bench_relay_handshakes(
+    network_interface: Option<&NetworkInterface>,
+    relays: &[OneRelay],
+    sni: String,
+    use_tcp_tls: bool,
+    quic_frame_padding: bool,
+    force_small_mtu: bool,
+    mtu: Option<u16>,
+) -> Vec<RelayBenchResult> {
+    const BENCH_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+    let transport = if use_tcp_tls { TransportKind::TcpTls } else { TransportKind::Quic };
+    let result = |relay: &OneRelay, port, rtt: Option<Duration>, error: Option<String>| RelayBenchResult {
+        relay_id: relay.id.clone(),
+        ip: relay.ip_v4,
+        port,
+        transport,
+        rtt_ms: rtt.map(|rtt| rtt.as_millis().try_into().unwrap_or(u64::MAX)),
+        error,
+    };
+
+    let mut tasks = match spawn_relay_handshakes(
+        network_interface,
+        relays,
+        sni,
+        use_tcp_tls,
+        quic_frame_padding,
+        force_small_mtu,
+        mtu,
+        Some(BENCH_HANDSHAKE_TIMEOUT),
+    ) {
+        Ok(tasks) => tasks,
+        Err(error) => {
+            tracing::warn!(
+                message_id = "Tb6cQz1V",
+                ?error,
+                use_tcp_tls,
+                "failed to set up relay benchmark: {}",
+                error
+            );
+            return relays
+                .iter()
+                .take(MAX_RELAYS)
+                .flat_map(|relay| relay.ports.iter().map(|&port| result(relay, port, None, Some(error.to_string()))))
+                .collect();
+        }
+    };
+
+    let mut results = Vec::new();
+    while let Some(joined) = tasks.join_next().await {
+        let (handshake, relay, port) = match joined {
+            Ok(joined) => joined,
+            Err(error) => {
+                tracing::error!(message_id = "Gx2mVs9N", ?error, "relay benchmark task failed: {}", error);
+                continue;
+            }
+        };
+        results.push(match handshake {
+            Ok((handshaking, rtt)) => {
+                spawn(handshaking.abandon());
+                result(&relay, port, Some(rtt), None)
+            }
+            Err(error) => result(&relay, port, None, Some(error.to_string())),
+        });
+    }
+    results
+}
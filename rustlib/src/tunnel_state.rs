@@ -268,6 +268,16 @@ impl TunnelState {
                                 }
                                 Some(Err(error)) => {
                                     tracing::error!(message_id = "OfLfwKhf", ?error, "failed to connect");
+                                    // Only Linux can probe the physical network while traffic is captured.
+                                    #[cfg(target_os = "linux")]
+                                    let error = if error.is_unreachable()
+                                        && let Some(portal) = client_state.known_captive_portal()
+                                    {
+                                        tracing::warn!(message_id = "Pz5nKc8W", ?portal, "captive portal detected");
+                                        TunnelConnectError::CaptivePortal
+                                    } else {
+                                        error
+                                    };
                                     tunnel_state.send_modify(|tunnel_state| tunnel_state.set_connect_error(error));
                                     ControlFlow::Break(())
                                 }